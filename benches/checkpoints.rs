@@ -0,0 +1,60 @@
+//! Compares full chain validation against validation with a checkpoint
+//! at half the chain height.
+//!
+//! Run with `cargo bench --bench checkpoints`.
+//!
+
+use coin::chain::Chain;
+use coin::core::blockchain::Height;
+use coin::core::keys::KeyPair;
+use coin::core::transaction::Output;
+use coin::utils::{new_block, new_tx};
+use std::time::{Duration, Instant};
+
+const CHAIN_LENGTH: usize = 100;
+const CHECKPOINT_HEIGHT: usize = 50;
+const ITERATIONS: u32 = 20;
+
+fn build_chain(key: &KeyPair) -> Chain {
+    let mut chain = Chain::new(&key.public_key());
+    while chain.height() < CHAIN_LENGTH {
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        let tx = new_tx(key, &utxos, vec![Output::new(1, key.public_key())]).unwrap();
+        let block = new_block(&chain, 0, vec![tx]);
+        chain.add_block(block).unwrap();
+    }
+    chain
+}
+
+fn measure(chain: &Chain) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(chain.validate_chain());
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let key = KeyPair::new();
+    let mut chain = build_chain(&key);
+
+    let full = measure(&chain);
+
+    let hash = chain.get_block(CHECKPOINT_HEIGHT).unwrap().hash.clone();
+    chain
+        .rules
+        .add_checkpoint(Height::from(CHECKPOINT_HEIGHT), hash)
+        .unwrap();
+    let checkpointed = measure(&chain);
+
+    println!("Chain length: {} blocks", CHAIN_LENGTH);
+    println!("Full validation: {:?}", full);
+    println!(
+        "With checkpoint at height {}: {:?}",
+        CHECKPOINT_HEIGHT, checkpointed
+    );
+    println!(
+        "Speedup: {:.2}x",
+        full.as_secs_f64() / checkpointed.as_secs_f64()
+    );
+}
@@ -0,0 +1,91 @@
+//! Compares sequential signature verification against parallel verification with
+//! `Block::batch_verify_signatures`, on 10 blocks with 5 transactions each.
+//!
+//! Run with `cargo bench --bench signatures`.
+//!
+
+use coin::chain::Chain;
+use coin::core::block::Block;
+use coin::core::keys::KeyPair;
+use coin::core::transaction::Output;
+use coin::utils::{new_block, new_tx};
+use std::time::{Duration, Instant};
+
+const BLOCK_COUNT: usize = 10;
+const TXS_PER_BLOCK: usize = 5;
+const ITERATIONS: u32 = 20;
+
+fn output(key: &KeyPair, value: u64) -> Output {
+    Output::new(value, key.public_key())
+}
+
+/// Builds a chain whose first block splits the genesis coins, followed by the blocks
+/// to verify, each spending some of the split outputs
+///
+fn build_chain(key: &KeyPair) -> Chain {
+    let mut chain = Chain::new(&key.public_key());
+    let utxos = chain.find_utxos_for_key(&key.public_key());
+    let outputs = (0..BLOCK_COUNT * TXS_PER_BLOCK)
+        .map(|_| output(key, 100))
+        .collect();
+    let split = new_tx(key, &utxos, outputs).unwrap();
+    chain.add_block(new_block(&chain, 0, vec![split])).unwrap();
+
+    for _ in 0..BLOCK_COUNT {
+        let mut utxos = chain.find_utxos_for_key(&key.public_key());
+        utxos.retain(|utxo| utxo.value == 100);
+        let txs = utxos[..TXS_PER_BLOCK]
+            .iter()
+            .map(|utxo| new_tx(key, std::slice::from_ref(utxo), vec![output(key, 99)]).unwrap())
+            .collect();
+        chain.add_block(new_block(&chain, 0, txs)).unwrap();
+    }
+    chain
+}
+
+fn verify_sequential(blocks: &[Block], chain: &Chain) -> Vec<bool> {
+    blocks
+        .iter()
+        .map(|block| {
+            block
+                .transactions()
+                .iter()
+                .flat_map(|tx| tx.data.inputs.iter())
+                .all(|input| match chain.chain.query_tx(&input.hash) {
+                    Some((_, spent_tx)) => input.verify_signature(spent_tx),
+                    None => false,
+                })
+        })
+        .collect()
+}
+
+fn measure<F>(verify: F) -> Duration
+where
+    F: Fn() -> Vec<bool>,
+{
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(verify().iter().all(|valid| *valid));
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let key = KeyPair::new();
+    let chain = build_chain(&key);
+    let blocks = &chain.chain.list[2..];
+
+    let sequential = measure(|| verify_sequential(blocks, &chain));
+    let batch = measure(|| Block::batch_verify_signatures(blocks, &chain.chain));
+
+    println!(
+        "Blocks: {}, transactions per block: {}",
+        BLOCK_COUNT, TXS_PER_BLOCK
+    );
+    println!("Sequential verification: {:?}", sequential);
+    println!("Batch verification: {:?}", batch);
+    println!(
+        "Speedup: {:.2}x",
+        sequential.as_secs_f64() / batch.as_secs_f64()
+    );
+}
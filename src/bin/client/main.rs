@@ -2,10 +2,15 @@
 //!
 //! This client can:
 //! - Generate new pairs of public/private keys
-//! - Display a hex string address from a public key
+//! - Generate a key pair from a new mnemonic phrase
+//! - Restore a key pair from a mnemonic phrase
+//! - Display a Base58Check address from a public key
 //! - Fetch the total funds for a public address
+//! - Watch the balance of an address without its private key
 //! - Send some coins from a private key to an address
 //! - Build and send a transaction involving more recipients
+//! - Send coins to more recipients listed in a JSON file
+//! - Send a batch of built transactions signed by the same key
 //!
 //! For simplicity, the commands interacting with remote nodes
 //! require specifying the host in a flag. There is no way
@@ -13,12 +18,27 @@
 //!
 
 use clap::{Parser, Subcommand};
-use coin::core::keys::{KeyPair, PublicKey};
+use coin::core::blockchain::Height;
+use coin::core::keys::{KeyPair, Keyring, PubkeyDeserializeError, PublicKey};
+use coin::core::transaction::Transaction;
 use coin::core::transaction::{Output, Value};
-use coin::traits::io::FileIO;
-use coin::utils::{json_to_utxos, new_tx};
-use std::path::PathBuf;
+use coin::traits::io::{FileIO, IOError};
+use coin::utils::{
+    format_coins, json_to_utxos, new_tx_multi_output, parse_coins, sign_tx_batch, ParseCoinsError,
+};
+use coin::utxo::Utxo;
+use coin::wallet::{Wallet, WatchOnlyWallet};
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+/// Number of decimal digits used when displaying coin amounts
+const COIN_DECIMALS: u8 = 4;
 
 #[derive(Parser)]
 #[command(name = "Coin")]
@@ -31,10 +51,34 @@ struct Cli {
 #[derive(Subcommand, Clone)]
 enum Commands {
     #[command(about = "Generate new key pair")]
-    GenKeys { path: PathBuf },
+    GenKeys {
+        path: PathBuf,
+        #[arg(long, help = "Encrypt the key file with the password")]
+        password: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "password",
+            help = "Append the key pair to the keyring file, creating it if missing"
+        )]
+        keyring: bool,
+    },
+
+    #[command(about = "Generate new key pair from random mnemonic words")]
+    GenMnemonicKeys { path: PathBuf },
+
+    #[command(about = "Generate key pair from mnemonic words")]
+    MnemonicKeys {
+        path: PathBuf,
+        #[arg(num_args = 12..=24, required = true)]
+        words: Vec<String>,
+    },
 
     #[command(about = "Print public address for key")]
-    GetAddr { path: PathBuf },
+    GetAddr {
+        path: PathBuf,
+        #[arg(long, help = "Password of the encrypted key file")]
+        password: Option<String>,
+    },
 
     #[command(about = "Print the number of coins the key owns")]
     GetFunds {
@@ -43,19 +87,39 @@ enum Commands {
         path: PathBuf,
     },
 
+    #[command(about = "Print the balance of an address, optionally polling the node")]
+    WatchBalance {
+        #[arg(short, long, required = false, default_value = "http://127.0.0.1:8080")]
+        node: String,
+        addr: String,
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Seconds between polls, the balance is printed once if missing"
+        )]
+        interval: Option<u64>,
+    },
+
     #[command(about = "Send coins to address")]
     Send {
         #[arg(short, long, required = false, default_value = "http://127.0.0.1:8080")]
         node: String,
         key: PathBuf,
         addr: String,
+        #[arg(value_parser = parse_amount, help = "Amount of coins, e.g. 1.5")]
         value: Value,
+        #[arg(
+            long,
+            help = "Height from which the transaction can't be mined anymore"
+        )]
+        expiry_height: Option<u64>,
     },
 
     #[command(about = "Build transaction")]
     BuildTx {
         tx: PathBuf,
         addr: String,
+        #[arg(value_parser = parse_amount, help = "Amount of coins, e.g. 1.5")]
         value: Value,
     },
 
@@ -66,15 +130,96 @@ enum Commands {
         key: PathBuf,
         tx: PathBuf,
     },
+
+    #[command(about = "Send coins to the recipients listed in a JSON file")]
+    MultiSend {
+        #[arg(short, long, required = false, default_value = "http://127.0.0.1:8080")]
+        node: String,
+        key: PathBuf,
+        payments: PathBuf,
+    },
+
+    #[command(about = "Send a batch of transactions")]
+    SendTxBatch {
+        #[arg(short, long, required = false, default_value = "http://127.0.0.1:8080")]
+        node: String,
+        key: PathBuf,
+        #[arg(required = true)]
+        txs: Vec<PathBuf>,
+    },
+}
+
+/// A payment in the file read by the MultiSend command, in the form
+/// `{"addr": "...", "value": N}`. The value is an amount of coins with the same
+/// decimals used when displaying it, either as a number or as a string.
+///
+#[derive(Deserialize)]
+struct Payment {
+    addr: String,
+    #[serde(deserialize_with = "deserialize_amount")]
+    value: Value,
+}
+
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<Value, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let amount = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(number) => number.to_string(),
+        serde_json::Value::String(string) => string,
+        _ => return Err(de::Error::custom("the amount is not a number")),
+    };
+    parse_amount(&amount).map_err(de::Error::custom)
+}
+
+/// Errors returned when sending a transaction to a node
+#[derive(Debug)]
+enum BroadcastError {
+    Request(reqwest::Error),
+    Rejected(reqwest::StatusCode),
+}
+
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastError::Request(err) => write!(f, "Broadcast error: {}", err),
+            BroadcastError::Rejected(status) => {
+                write!(f, "Broadcast error: rejected with status {}", status)
+            }
+        }
+    }
 }
 
+impl std::error::Error for BroadcastError {}
+
 fn main() -> ExitCode {
     let cli = Cli::parse();
 
     match &cli.command {
-        Commands::GenKeys { path } => {
+        Commands::GenKeys {
+            path,
+            password,
+            keyring,
+        } => {
             println!("Saving new key pair in file {}", path.display());
             let key = KeyPair::new();
+            let result = match password {
+                Some(password) => key.to_encrypted_file(path, password),
+                None if *keyring => add_to_keyring(path, key),
+                None => key.to_file(path).map(|_| ()),
+            };
+            match result {
+                Ok(_) => println!("Key pair saved!"),
+                Err(_) => {
+                    println!("Failed to save keys to file");
+                    return ExitCode::from(1);
+                }
+            }
+            ExitCode::from(0)
+        }
+        Commands::GenMnemonicKeys { path } => {
+            println!("Saving new key pair in file {}", path.display());
+            let (key, words) = KeyPair::new_with_mnemonic();
             match key.to_file(path) {
                 Ok(_) => println!("Key pair saved!"),
                 Err(_) => {
@@ -82,10 +227,36 @@ fn main() -> ExitCode {
                     return ExitCode::from(1);
                 }
             }
+            println!("Mnemonic words: {}", words.join(" "));
+            println!("Public address: {}", key.public_key().to_address());
             ExitCode::from(0)
         }
-        Commands::GetAddr { path } => {
-            let key = match KeyPair::from_file(path) {
+        Commands::MnemonicKeys { path, words } => {
+            let words: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+            let key = match KeyPair::from_mnemonic(&words) {
+                Ok(key) => key,
+                Err(err) => {
+                    println!("Failed to derive key pair: {}", err);
+                    return ExitCode::from(1);
+                }
+            };
+
+            println!("Saving key pair in file {}", path.display());
+            match key.to_file(path) {
+                Ok(_) => println!("Key pair saved!"),
+                Err(_) => {
+                    println!("Failed to save keys to file");
+                    return ExitCode::from(1);
+                }
+            }
+            ExitCode::from(0)
+        }
+        Commands::GetAddr { path, password } => {
+            let key = match password {
+                Some(password) => KeyPair::from_encrypted_file(path, password),
+                None => KeyPair::from_file(path),
+            };
+            let key = match key {
                 Ok(key) => key,
                 Err(_) => {
                     println!("Failed to read key from file!");
@@ -96,7 +267,7 @@ fn main() -> ExitCode {
             println!(
                 "Public address for key {}: {}",
                 path.to_str().unwrap(),
-                key.public_key().to_hex_str()
+                key.public_key().to_address()
             );
             ExitCode::from(0)
         }
@@ -109,27 +280,65 @@ fn main() -> ExitCode {
                 }
             };
 
-            let addr = key.public_key().to_hex_str();
+            let addr = key.public_key().to_address();
+            let value = match fetch_balance(node, &key.public_key()) {
+                Some(value) => value,
+                None => return ExitCode::from(1),
+            };
 
-            let utxos = match reqwest::blocking::get(format!("{}/utxos/{}", node, addr,)) {
-                Ok(body) => json_to_utxos(&body.text().unwrap()).unwrap(),
-                Err(err) => {
-                    println!("Failed to fetch utxos! {:?}", err);
+            println!(
+                "Total coins for address {}: {}",
+                addr,
+                format_coins(value, COIN_DECIMALS)
+            );
+            ExitCode::from(0)
+        }
+        Commands::WatchBalance {
+            node,
+            addr,
+            interval,
+        } => {
+            let wallet = match parse_address(addr) {
+                Ok(pubkey) => WatchOnlyWallet::new(pubkey),
+                Err(_) => {
+                    println!("The address is not valid!");
                     return ExitCode::from(1);
                 }
             };
 
-            let value = utxos.into_iter().fold(0, |acc, utxo| acc + utxo.value);
-            println!("Total coins for address {}: {}", addr, value);
-            ExitCode::from(0)
+            loop {
+                match fetch_utxos(node, &wallet.pubkey) {
+                    Some(utxos) => {
+                        let balance: Value = utxos.iter().map(|utxo| utxo.value).sum();
+                        println!(
+                            "Balance of {}: {} in {} outputs",
+                            wallet.pubkey.to_address(),
+                            format_coins(balance, COIN_DECIMALS),
+                            utxos.len()
+                        );
+                    }
+                    // A failed poll is retried at the next interval
+                    None if interval.is_some() => (),
+                    None => return ExitCode::from(1),
+                }
+                match interval {
+                    Some(interval) => thread::sleep(Duration::from_secs(*interval)),
+                    None => return ExitCode::from(0),
+                }
+            }
         }
         Commands::Send {
             node,
             key,
             addr,
             value,
+            expiry_height,
         } => {
-            println!("Sending {} coins to {}", value, addr);
+            println!(
+                "Sending {} coins to {}",
+                format_coins(*value, COIN_DECIMALS),
+                addr
+            );
 
             let key = match KeyPair::from_file(key) {
                 Ok(key) => key,
@@ -139,7 +348,7 @@ fn main() -> ExitCode {
                 }
             };
 
-            let recipient = match PublicKey::from_hex_str(addr) {
+            let recipient = match parse_address(addr) {
                 Ok(key) => key,
                 Err(_) => {
                     println!("The address is not valid!");
@@ -147,18 +356,19 @@ fn main() -> ExitCode {
                 }
             };
 
-            let outputs = [Output {
-                pubkey: recipient,
-                value: *value,
-            }];
+            let outputs = [Output::new(*value, recipient)];
 
-            send_tx(node, key, &outputs)
+            send_tx(node, key, &outputs, expiry_height.map(Height::from))
         }
         Commands::BuildTx { tx, addr, value } => {
             println!("Build transaction into file {}", tx.display());
-            println!("  Send {} coins to {}", value, addr);
+            println!(
+                "  Send {} coins to {}",
+                format_coins(*value, COIN_DECIMALS),
+                addr
+            );
 
-            let recipient = match PublicKey::from_hex_str(addr) {
+            let recipient = match parse_address(addr) {
                 Ok(key) => key,
                 Err(_) => {
                     println!("The address is not valid!");
@@ -175,10 +385,7 @@ fn main() -> ExitCode {
                 }
             };
 
-            outputs.push(Output {
-                pubkey: recipient,
-                value: *value,
-            });
+            outputs.push(Output::new(*value, recipient));
 
             match outputs.to_file(tx) {
                 Ok(_) => ExitCode::from(0),
@@ -207,23 +414,228 @@ fn main() -> ExitCode {
                 }
             };
 
-            send_tx(node, key, outputs.as_slice())
+            send_tx(node, key, outputs.as_slice(), None)
+        }
+        Commands::MultiSend {
+            node,
+            key,
+            payments,
+        } => {
+            println!("Send payments from file {}", payments.display());
+
+            let key = match KeyPair::from_file(key) {
+                Ok(key) => key,
+                Err(_) => {
+                    println!("Failed to read key from file!");
+                    return ExitCode::from(1);
+                }
+            };
+
+            let list: Vec<Payment> = match fs::read_to_string(payments)
+                .ok()
+                .and_then(|json| serde_json::from_str(&json).ok())
+            {
+                Some(list) => list,
+                None => {
+                    println!("Error reading payments file {}!", payments.display());
+                    return ExitCode::from(1);
+                }
+            };
+
+            let mut payments = HashMap::<PublicKey, Value>::new();
+            for payment in list {
+                println!(
+                    "  Send {} coins to {}",
+                    format_coins(payment.value, COIN_DECIMALS),
+                    payment.addr
+                );
+                let recipient = match parse_address(&payment.addr) {
+                    Ok(key) => key,
+                    Err(_) => {
+                        println!("The address {} is not valid!", payment.addr);
+                        return ExitCode::from(1);
+                    }
+                };
+                let value = payments.entry(recipient).or_insert(0);
+                *value = match value.checked_add(payment.value) {
+                    Some(value) => value,
+                    None => {
+                        println!("The total value for {} is too large!", payment.addr);
+                        return ExitCode::from(1);
+                    }
+                };
+            }
+
+            let utxos = match fetch_utxos(node, &key.public_key()) {
+                Some(utxos) => utxos,
+                None => return ExitCode::from(1),
+            };
+
+            match new_tx_multi_output(&key, &utxos, payments) {
+                Ok(tx) => post_tx(node, &tx),
+                Err(err) => {
+                    println!("Failed to build transaction: {}", err);
+                    ExitCode::from(1)
+                }
+            }
+        }
+        Commands::SendTxBatch { node, key, txs } => {
+            println!("Send {} transactions", txs.len());
+
+            let key = match KeyPair::from_file(key) {
+                Ok(key) => key,
+                Err(_) => {
+                    println!("Failed to read key from file!");
+                    return ExitCode::from(1);
+                }
+            };
+
+            let mut batch = Vec::with_capacity(txs.len());
+            for tx in txs {
+                match Vec::from_file(tx) {
+                    Ok(outputs) => batch.push(outputs),
+                    Err(_) => {
+                        println!("Error reading tx file {}!", tx.display());
+                        return ExitCode::from(1);
+                    }
+                }
+            }
+
+            let results = match sign_and_broadcast_batch(&key, batch, node) {
+                Some(results) => results,
+                None => return ExitCode::from(1),
+            };
+
+            let mut failed = false;
+            for (tx, result) in txs.iter().zip(results) {
+                match result {
+                    Ok(_) => println!("Sent transaction from file {}", tx.display()),
+                    Err(err) => {
+                        println!(
+                            "Failed to send transaction from file {}: {}",
+                            tx.display(),
+                            err
+                        );
+                        failed = true;
+                    }
+                }
+            }
+            ExitCode::from(failed as u8)
         }
     }
 }
 
-fn send_tx(node: &str, key: KeyPair, outputs: &[Output]) -> ExitCode {
-    let mut utxos =
-        match reqwest::blocking::get(format!("{}/utxos/{}", node, key.public_key().to_hex_str())) {
-            Ok(body) => json_to_utxos(&body.text().unwrap()).unwrap(),
+/// Appends the key pair to the keyring file, which is created if it doesn't exist
+///
+fn add_to_keyring(path: &Path, key: KeyPair) -> Result<(), IOError> {
+    let mut keyring = match path.exists() {
+        true => Keyring::from_file(path)?,
+        false => Keyring::new(),
+    };
+    keyring.add(key);
+    keyring.to_file(path)?;
+    println!("Keyring contains {} key pairs", keyring.len());
+    Ok(())
+}
+
+/// Parses an amount of coins typed by the user, with the same decimals used
+/// when displaying it
+///
+fn parse_amount(amount: &str) -> Result<Value, ParseCoinsError> {
+    parse_coins(amount, COIN_DECIMALS)
+}
+
+/// Parses a Base58Check address, falling back to the hex encoding of the public key
+///
+fn parse_address(addr: &str) -> Result<PublicKey, PubkeyDeserializeError> {
+    PublicKey::from_address(addr).or_else(|_| PublicKey::from_hex_str(addr))
+}
+
+fn fetch_balance(node: &str, pubkey: &PublicKey) -> Option<Value> {
+    let url = format!("{}/balance/{}", node, pubkey.to_hex_str());
+    match reqwest::blocking::get(url).and_then(|res| res.error_for_status()?.text()) {
+        Ok(body) => match serde_json::from_str(&body) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                println!("Failed to parse balance! {:?}", err);
+                None
+            }
+        },
+        Err(err) => {
+            println!("Failed to fetch balance! {:?}", err);
+            None
+        }
+    }
+}
+
+fn fetch_utxos(node: &str, pubkey: &PublicKey) -> Option<Vec<Utxo>> {
+    match reqwest::blocking::get(format!("{}/utxos/{}", node, pubkey.to_hex_str())) {
+        Ok(body) => match body.text().map(|text| json_to_utxos(&text)) {
+            Ok(Ok(mut utxos)) => {
+                utxos.sort_by(|a, b| a.value.cmp(&b.value));
+                Some(utxos)
+            }
+            Ok(Err(err)) => {
+                println!("Failed to parse utxos! {:?}", err);
+                None
+            }
             Err(err) => {
                 println!("Failed to fetch utxos! {:?}", err);
-                return ExitCode::from(1);
+                None
             }
-        };
+        },
+        Err(err) => {
+            println!("Failed to fetch utxos! {:?}", err);
+            None
+        }
+    }
+}
 
-    utxos.sort_by(|a, b| a.value.cmp(&b.value));
-    let tx = match new_tx(&key, &utxos, outputs.to_vec()) {
+fn broadcast_tx(
+    client: &reqwest::blocking::Client,
+    node: &str,
+    tx: &Transaction,
+) -> Result<(), BroadcastError> {
+    match client.post(format!("{}/chain", node)).json(tx).send() {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => Err(BroadcastError::Rejected(res.status())),
+        Err(err) => Err(BroadcastError::Request(err)),
+    }
+}
+
+/// Builds the transactions with [sign_tx_batch] and sends them in order, returning
+/// the result of every transaction. Returns None if the batch can't be built.
+///
+fn sign_and_broadcast_batch(
+    key: &KeyPair,
+    batch: Vec<Vec<Output>>,
+    node: &str,
+) -> Option<Vec<Result<(), BroadcastError>>> {
+    let utxos = fetch_utxos(node, &key.public_key())?;
+    let txs = match sign_tx_batch(key, &utxos, batch) {
+        Ok(txs) => txs,
+        Err(err) => {
+            println!("Failed to build transactions: {}", err);
+            return None;
+        }
+    };
+
+    let client = reqwest::blocking::Client::new();
+    Some(
+        txs.iter()
+            .map(|tx| broadcast_tx(&client, node, tx))
+            .collect(),
+    )
+}
+
+fn send_tx(node: &str, key: KeyPair, outputs: &[Output], expiry: Option<Height>) -> ExitCode {
+    let wallet = Wallet::new(key);
+    let utxos = match fetch_utxos(node, wallet.public_key()) {
+        Some(utxos) => utxos,
+        None => return ExitCode::from(1),
+    };
+
+    let tx = match wallet.send_from(utxos, outputs.to_vec(), expiry) {
         Ok(tx) => tx,
         Err(err) => {
             println!("Failed to build transaction: {}", err);
@@ -231,10 +643,14 @@ fn send_tx(node: &str, key: KeyPair, outputs: &[Output]) -> ExitCode {
         }
     };
 
+    post_tx(node, &tx)
+}
+
+fn post_tx(node: &str, tx: &Transaction) -> ExitCode {
     println!("Sending transaction: \n{:#?}", tx);
 
     let client = reqwest::blocking::Client::new();
-    match client.post(format!("{}/chain", node)).json(&tx).send() {
+    match client.post(format!("{}/chain", node)).json(tx).send() {
         Ok(res) => {
             println!("Response: {}", res.status())
         }
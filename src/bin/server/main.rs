@@ -4,9 +4,11 @@
 //! - Create a new chain containing a genesis block, and saving the chain on a file
 //! - Start the node/miner by loading an existing chain from a file
 //!
+//! Chain files can also be validated offline, either entirely or one block at a time.
+//!
 //! The node is composed by two parts:
 //! - An HTTP server that allows a minimal set of operation such as querying the
-//!   state of the chain and posting new transactions
+//!   state of the chain and posting new transactions and blocks
 //! - A miner thread that collects the received transactions into blocks and applies
 //!   Proof of Work to generate a new valid entry for the blockchain
 //!
@@ -16,22 +18,30 @@
 //!
 
 use clap::{Parser, Subcommand};
-use coin::chain::{Chain, SerializableChain};
-use coin::consensus::{ConsensusRules, Halving, Target};
+use coin::chain::{Chain, ChainOpError, SerializableChain};
+use coin::consensus::{ConsensusRules, ConsensusRulesBuilder, Halving, Target};
+use coin::core::block::Block;
+use coin::core::blockchain::Height;
+use coin::core::hash::Hash;
 use coin::core::keys::{KeyPair, PublicKey};
 use coin::core::transaction::Transaction;
-use coin::mining::miner::Miner;
+use coin::mining::miner::{mine_until_success, Miner, DEFAULT_POOL_CAPACITY};
 use coin::traits::io::{FileIO, JsonIO};
 use coin::utils::utxos_to_json;
 use rouille::{router, Response, ResponseBody, Server};
+use std::collections::HashMap;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
+/// Blocks per second assumed by /sync/status when no rate is given
+const DEFAULT_SYNC_RATE: f64 = 10.0;
+
 #[derive(Parser)]
 #[command(name = "Coin Node")]
 #[command(version = "0.1")]
@@ -48,6 +58,8 @@ enum Commands {
         path: PathBuf,
         #[arg(short, long)]
         key: PathBuf,
+        #[arg(long, required = false)]
+        rules: Option<PathBuf>,
     },
     #[command(about = "Start node")]
     Start {
@@ -58,24 +70,110 @@ enum Commands {
         #[arg(short, long, required = false, default_value = "60")]
         mining_freq: u64,
     },
+    #[command(about = "Validate a chain file without starting the node")]
+    ValidateChain {
+        #[arg(short, long)]
+        path: PathBuf,
+    },
+    #[command(about = "Validate a single block of a chain file")]
+    CheckBlock {
+        #[arg(short, long)]
+        path: PathBuf,
+        #[arg(long)]
+        height: usize,
+    },
 }
 
+/// Exit code of the validation commands when the chain file can't be read
+const EXIT_READ_ERROR: u8 = 2;
+
 fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    let success = match &cli.command {
-        Commands::New { path, key } => command_new(path, key),
+    let code = match &cli.command {
+        Commands::New { path, key, rules } => exit_code(command_new(path, key, rules.as_deref())),
         Commands::Start {
             path,
             recipient,
             mining_freq,
-        } => command_start(path, recipient, *mining_freq),
+        } => exit_code(command_start(path, recipient, *mining_freq)),
+        Commands::ValidateChain { path } => command_validate_chain(path),
+        Commands::CheckBlock { path, height } => command_check_block(path, *height),
     };
 
-    ExitCode::from(if success { 0 } else { 1 })
+    ExitCode::from(code)
+}
+
+fn exit_code(success: bool) -> u8 {
+    if success {
+        0
+    } else {
+        1
+    }
 }
 
-fn command_new(path: &PathBuf, key: &PathBuf) -> bool {
+fn read_chain_unchecked(path: &Path) -> Option<Chain> {
+    match SerializableChain::from_file(path) {
+        Ok(chain) => Some(Chain::from_serializable_unchecked(chain)),
+        Err(_) => {
+            println!("Cannot deserialize blockchain!");
+            None
+        }
+    }
+}
+
+fn command_validate_chain(path: &Path) -> u8 {
+    println!("Validating chain {}", path.display());
+    let chain = match read_chain_unchecked(path) {
+        Some(chain) => chain,
+        None => return EXIT_READ_ERROR,
+    };
+
+    match chain.find_invalid_block() {
+        None => {
+            println!("Chain is valid (height: {})", u64::from(chain.height()));
+            exit_code(true)
+        }
+        Some(height) => {
+            println!("Chain validation failed at block {}", u64::from(height));
+            if chain.validate_chain_structure() {
+                println!("Chain structure is sound, the block breaks the consensus rules");
+            } else {
+                println!("Chain structure is corrupted");
+            }
+            exit_code(false)
+        }
+    }
+}
+
+fn command_check_block(path: &Path, height: usize) -> u8 {
+    println!("Checking block {} of chain {}", height, path.display());
+    let chain = match read_chain_unchecked(path) {
+        Some(chain) => chain,
+        None => return EXIT_READ_ERROR,
+    };
+
+    match chain.validate_block_at(height) {
+        Some(true) => {
+            println!("Block {} is valid", height);
+            exit_code(true)
+        }
+        Some(false) => {
+            println!("Block {} is not valid", height);
+            exit_code(false)
+        }
+        None => {
+            println!(
+                "Block {} not found (height: {})",
+                height,
+                u64::from(chain.height())
+            );
+            exit_code(false)
+        }
+    }
+}
+
+fn command_new(path: &PathBuf, key: &PathBuf, rules: Option<&Path>) -> bool {
     println!(
         "Creating new chain at {} with key {}",
         path.display(),
@@ -89,10 +187,22 @@ fn command_new(path: &PathBuf, key: &PathBuf) -> bool {
         }
     };
 
-    let chain = Chain::new_with_consensus(
-        &key.public_key(),
-        ConsensusRules::new(Target::from_leading_zeros(15), 10000, Halving::Inf),
-    );
+    let rules = match rules {
+        Some(rules) => match ConsensusRules::from_json_file(rules) {
+            Ok(rules) => rules,
+            Err(_) => {
+                println!("Failed to read consensus rules from file!");
+                return false;
+            }
+        },
+        None => ConsensusRulesBuilder::default()
+            .target(Target::from_leading_zeros(15))
+            .coins_per_block(10000)
+            .halving(Halving::Inf)
+            .build(),
+    };
+
+    let chain = Chain::new_with_consensus(&key.public_key(), rules);
     match SerializableChain::new(chain).to_file(path) {
         Ok(_) => println!("Chain saved to file: {}", path.display()),
         Err(_) => {
@@ -156,6 +266,16 @@ trait CommonResponses {
 
 impl CommonResponses for Response {}
 
+/// Updates the node state after a block is added to the chain, either mined locally
+/// or submitted: the pool transactions made invalid by the block are removed and the
+/// cached confirmations are rebuilt
+///
+fn on_block_added(chain: &Chain, miner: &mut Miner, confirmations: &Mutex<HashMap<Hash, u64>>) {
+    let pruned = miner.prune_invalid_txs(chain);
+    println!("Pruned {} invalid transactions from the pool", pruned);
+    *confirmations.lock().unwrap() = chain.get_tx_confirmations_map();
+}
+
 enum MinerCommand {
     Stop,
     Mine,
@@ -173,7 +293,8 @@ fn command_start(path: &PathBuf, recipient: &PathBuf, mining_freq: u64) -> bool
         }
     };
 
-    let chain = match Chain::from_serializable(ser_chain) {
+    // Only the blocks added after the last validation are validated
+    let chain = match Chain::from_serializable_with_repair(ser_chain) {
         Ok(chain) => Arc::new(Mutex::new(chain)),
         Err(_) => {
             println!("Blockchain validation failed!");
@@ -191,26 +312,52 @@ fn command_start(path: &PathBuf, recipient: &PathBuf, mining_freq: u64) -> bool
     };
 
     // SETUP MINER
-    let miner = Arc::new(Mutex::new(Miner::new(key.public_key())));
+    let mut miner = Miner::new(key.public_key(), DEFAULT_POOL_CAPACITY);
+    miner.with_progress_callback(|tries, best_zeros| {
+        println!("Tries: {}, best leading zeros: {}", tries, best_zeros)
+    });
+    let miner = Arc::new(Mutex::new(miner));
+
+    let stop_mining = Arc::new(AtomicBool::new(false));
+    // The flag aborts the search in progress on shutdown or when a block is submitted
+    let cancel_mining = miner.lock().unwrap().cancel_handle();
+
+    // Confirmations are cached and rebuilt only when a new block is added
+    let confirmations: Arc<Mutex<HashMap<Hash, u64>>> =
+        Arc::new(Mutex::new(chain.lock().unwrap().get_tx_confirmations_map()));
 
     let chain_miner_ref = chain.clone();
+    let confirmations_miner_ref = confirmations.clone();
     let miner_miner_ref = miner.clone();
+    let stop_miner_ref = stop_mining.clone();
     let (miner_sender, miner_receiver) = mpsc::channel();
     let miner_task = thread::spawn(move || {
+        // Same as Miner::solo_mine_until_success, but the chain and the miner are locked
+        // (always in this order) only for one attempt at a time
         let mine = || {
-            let mut chain = chain_miner_ref.lock().unwrap();
-            match miner_miner_ref.lock().unwrap().mine(&chain) {
-                Ok(block) => {
+            let result = mine_until_success(&stop_miner_ref, || {
+                let chain = chain_miner_ref.lock().unwrap();
+                let block = miner_miner_ref.lock().unwrap().mine(&chain);
+                block
+            });
+            match result {
+                Some(block) => {
+                    let mut chain = chain_miner_ref.lock().unwrap();
                     println!("Trying to add block: {:#?}", block);
                     match chain.add_block(block) {
                         Ok(height) => {
-                            println!("Mining successful, inserted block with height: {}", height)
+                            println!("Mining successful, inserted block with height: {}", height);
+                            on_block_added(
+                                &chain,
+                                &mut miner_miner_ref.lock().unwrap(),
+                                &confirmations_miner_ref,
+                            );
                         }
                         Err(_) => println!("Mining failed, block is not valid."),
                     }
                 }
-                Err(err) => {
-                    println!("Mining aborted: {:?}", err);
+                None => {
+                    println!("Mining aborted");
                 }
             }
         };
@@ -234,6 +381,8 @@ fn command_start(path: &PathBuf, recipient: &PathBuf, mining_freq: u64) -> bool
     let chain_ref = chain.clone();
     let miner_ref = miner.clone();
     let miner_sender_ref = miner_sender.clone();
+    let stop_server_ref = stop_mining.clone();
+    let cancel_server_ref = cancel_mining.clone();
     let server_task = Server::new("127.0.0.1:8080", move |request| {
         router!(request,
         (GET) (/chain) => {
@@ -260,14 +409,58 @@ fn command_start(path: &PathBuf, recipient: &PathBuf, mining_freq: u64) -> bool
                 Err(_) => return Response::client_error(),
             };
 
+            let chain = chain_ref.lock().unwrap();
             let mut miner = miner_ref.lock().unwrap();
-            if miner.add_tx(&chain_ref.lock().unwrap(), tx) {
+            if miner.add_tx(&chain, tx) {
                 let _ = miner_sender_ref.send(MinerCommand::Mine);
                 Response::ok("")
             } else {
                 Response::client_error()
             }
         },
+        (POST) (/block) => {
+            println!("POST /block");
+            let mut body = match request.data() {
+                None => return Response::client_error(),
+                Some(body) => body
+            };
+
+            let mut buf = Vec::new();
+            if body.read_to_end(&mut buf).is_err() {
+                return Response::server_error();
+            }
+
+            let block = match Block::from_json(String::from_utf8(buf).unwrap().as_str()) {
+                Ok(block) => block,
+                Err(_) => return Response::client_error(),
+            };
+
+            // Same as Miner::cancel, the miner is locked while mining: the block being
+            // mined is built on a template that is stale once this block is added
+            cancel_server_ref.store(true, Ordering::Relaxed);
+            let mut chain = chain_ref.lock().unwrap();
+            let mut miner = miner_ref.lock().unwrap();
+            if !stop_server_ref.load(Ordering::Relaxed) {
+                miner.reset_cancel();
+            }
+            match chain.add_block(block) {
+                Ok(height) => {
+                    println!("Inserted submitted block with height: {}", height);
+                    on_block_added(&chain, &mut miner, &confirmations);
+                    Response::ok("")
+                }
+                Err(ChainOpError::Orphan) => {
+                    println!("Submitted block kept as orphan");
+                    Response::ok("")
+                }
+                Err(_) => Response::client_error(),
+            }
+        },
+        (GET) (/chain/stats) => {
+            println!("GET /chain/stats");
+            let stats = chain_ref.lock().unwrap().stats();
+            Response::ok(&serde_json::to_string(&stats).unwrap())
+        },
         (GET) (/utxos/all) => {
             println!("GET /utxos/all");
             let utxos = chain_ref.lock().unwrap().find_all_utxos();
@@ -282,9 +475,160 @@ fn command_start(path: &PathBuf, recipient: &PathBuf, mining_freq: u64) -> bool
             let utxos = chain_ref.lock().unwrap().find_utxos_for_key(&pubkey);
             Response::ok(&utxos_to_json(&utxos).unwrap().as_str())
         },
+        (GET) (/balance/{addr: String}) => {
+            println!("GET /balance");
+            let pubkey = match PublicKey::from_hex_str(addr.as_str()) {
+                Ok(key) => key,
+                Err(_) => return Response::client_error(),
+            };
+            match chain_ref.lock().unwrap().get_balance(&pubkey) {
+                Some(balance) => Response::ok(&serde_json::to_string(&balance).unwrap()),
+                None => Response::server_error(),
+            }
+        },
+        (GET) (/wallet/{addr: String}/history) => {
+            println!("GET /wallet/history");
+            let pubkey = match PublicKey::from_hex_str(addr.as_str()) {
+                Ok(key) => key,
+                Err(_) => return Response::client_error(),
+            };
+            let history = chain_ref.lock().unwrap().transaction_history_for_key(&pubkey);
+            Response::ok(&serde_json::to_string(&history).unwrap())
+        },
+        (GET) (/tx/{tx_hash: String}) => {
+            println!("GET /tx");
+            let hash = match Hash::from_hex_str(tx_hash.as_str()) {
+                Ok(hash) => hash,
+                Err(_) => return Response::client_error(),
+            };
+            let chain = chain_ref.lock().unwrap();
+            let (height, block, tx) = match chain.find_tx(&hash) {
+                Some(found) => found,
+                None => return Response::not_found(),
+            };
+            let json = serde_json::json!({
+                "height": u64::from(height),
+                "block_hash": block.hash.to_hex_str(),
+                "transaction": tx,
+            });
+            Response::ok(&json.to_string())
+        },
+        (GET) (/confirmations/{tx_hash: String}) => {
+            println!("GET /confirmations");
+            let hash = match Hash::from_hex_str(tx_hash.as_str()) {
+                Ok(hash) => hash,
+                Err(_) => return Response::client_error(),
+            };
+            match confirmations.lock().unwrap().get(&hash) {
+                Some(count) => Response::ok(&serde_json::to_string(count).unwrap()),
+                None => Response::not_found(),
+            }
+        },
+        (GET) (/block/{height: usize}/author) => {
+            println!("GET /block/author");
+            let chain = chain_ref.lock().unwrap();
+            let block = match chain.get_block(height) {
+                Some(block) => block,
+                None => return Response::not_found(),
+            };
+            let author = block.author_hint().map(|pubkey| pubkey.to_hex_str());
+            Response::ok(&serde_json::to_string(&author).unwrap())
+        },
+        (GET) (/block/{height: usize}/proof/{tx_index: usize}) => {
+            println!("GET /block/proof");
+            let chain = chain_ref.lock().unwrap();
+            let proof = match chain
+                .get_block(height)
+                .and_then(|block| block.transactions_merkle_proof(tx_index))
+            {
+                Some(proof) => proof,
+                None => return Response::not_found(),
+            };
+            Response::ok(&serde_json::to_string(&proof).unwrap())
+        },
+        (GET) (/stats) => {
+            println!("GET /stats");
+            let chain = chain_ref.lock().unwrap();
+            let miner = miner_ref.lock().unwrap();
+            let pool_stats = miner.pool_stats(&chain);
+            let stats = serde_json::json!({
+                "average_block_time_secs": chain.chain.average_block_time_secs(),
+                "estimated_time_to_next_block_secs": chain
+                    .chain
+                    .estimated_time_to_next_block(&chain.compute_retarget_target())
+                    .map(|duration| duration.as_secs_f64()),
+                "utxo_count": chain.utxo_count(),
+                "hashrate_hps": miner.last_hashrate(),
+                "estimated_seconds_to_block": miner
+                    .last_hashrate()
+                    .map(|hashrate| miner.estimate_seconds_to_block(&chain, hashrate)),
+                "pool": {
+                    "tx_count": pool_stats.tx_count,
+                    "total_fees": pool_stats.total_fees,
+                    "avg_fee_per_tx": pool_stats.avg_fee_per_tx,
+                    "total_value": miner.pool_total_value(&chain),
+                },
+            });
+            Response::ok(&stats.to_string())
+        },
+        (GET) (/health) => {
+            println!("GET /health");
+            let chain = chain_ref.lock().unwrap();
+            let health = serde_json::json!({
+                "status": "ok",
+                "height": u64::from(chain.height()),
+                "utxo_count": chain.utxo_count(),
+            });
+            Response::ok(&health.to_string())
+        },
+        (GET) (/addresses/count) => {
+            println!("GET /addresses/count");
+            let chain = chain_ref.lock().unwrap();
+            let counts = serde_json::json!({
+                "total": chain.address_count(),
+                "active": chain.active_address_count(),
+            });
+            Response::ok(&counts.to_string())
+        },
+        (GET) (/sync/status) => {
+            println!("GET /sync/status");
+            let chain = chain_ref.lock().unwrap();
+            let target_height = match request.get_param("target") {
+                Some(target) => match target.parse::<u64>() {
+                    Ok(target) => target,
+                    Err(_) => return Response::client_error(),
+                },
+                None => chain.height().into(),
+            };
+            let blocks_per_second = match request.get_param("rate") {
+                Some(rate) => match rate.parse::<f64>() {
+                    Ok(rate) => rate,
+                    Err(_) => return Response::client_error(),
+                },
+                None => DEFAULT_SYNC_RATE,
+            };
+            let remaining = chain.estimate_sync_time(Height::from(target_height), blocks_per_second);
+            let status = serde_json::json!({
+                "progress": chain.sync_progress(Height::from(target_height)),
+                "estimated_seconds_remaining": remaining.as_secs(),
+                "current_height": u64::from(chain.height()),
+                "target_height": target_height,
+            });
+            Response::ok(&status.to_string())
+        },
         (GET) (/pool) => {
             println!("GET /pool");
-            let transactions: Vec<Transaction> = miner_ref.lock().unwrap().pool.values().map(|tx| tx.clone()).collect();
+            let chain = chain_ref.lock().unwrap();
+            let miner = miner_ref.lock().unwrap();
+            let transactions: Vec<serde_json::Value> = miner
+                .pool_by_fee_rate(&chain)
+                .into_iter()
+                .map(|(_, tx, fee_rate)| {
+                    let mut json = serde_json::to_value(tx).unwrap();
+                    json["fee_rate"] = serde_json::json!(fee_rate);
+                    json
+                })
+                .collect();
             Response::ok(&serde_json::to_string(&transactions).unwrap())
         },
         _ => {
@@ -300,6 +644,8 @@ fn command_start(path: &PathBuf, recipient: &PathBuf, mining_freq: u64) -> bool
     // SETUP HANDLERS AND TEARDOWN
     ctrlc::set_handler(move || {
         println!("CTRL+C");
+        stop_mining.store(true, Ordering::Relaxed);
+        cancel_mining.store(true, Ordering::Relaxed);
         miner_sender.send(MinerCommand::Stop).unwrap();
         server_sender.send(()).unwrap();
     })
@@ -309,7 +655,7 @@ fn command_start(path: &PathBuf, recipient: &PathBuf, mining_freq: u64) -> bool
     server_task.join().unwrap();
 
     let chain = Arc::try_unwrap(chain).unwrap().into_inner().unwrap();
-    match SerializableChain::new(chain).to_file(path) {
+    match chain.save_atomic(path) {
         Ok(_) => println!("Chain saved to file: {}", path.display()),
         Err(_) => {
             println!("Failed to save chain to file!");
@@ -4,47 +4,113 @@
 //! for making sure no invalid transactions or blocks get added to the list.
 //!
 
-use crate::consensus::ConsensusRules;
-use crate::core::block::Block;
+use crate::consensus::{ConsensusRules, Target};
+use crate::core::block::{unix_now, Block};
 use crate::core::blockchain::{Blockchain, BlockchainError, Height};
 use crate::core::hash::Hash;
-use crate::core::keys::{PublicKey, Verifier};
-use crate::core::transaction::{Output, Transaction};
-use crate::traits::io::{ByteIO, FileIO, JsonIO};
+use crate::core::keys::PublicKey;
+use crate::core::transaction::{Output, Transaction, Value};
+use crate::traits::io::{ByteIO, FileIO, IOError, JsonIO};
 use crate::utils::*;
 use crate::utxo::Utxo;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Maximum number of blocks kept in the orphan pool
+const MAX_ORPHANS: usize = 100;
 
 /// A pool of UTXOs that can be used to track the current unspent outputs.
 /// Provides utility functions for querying the current state of coins that
 /// can be exchanged between addresses.
 ///
-#[derive(Debug, Clone)]
+/// The pool also records the height of the block that confirmed each UTXO, and the
+/// number of blocks applied to it, so that UTXOs can be selected by age.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UtxoPool {
     pub utxos: HashMap<(Hash, u32), Output>,
+    pub utxo_ages: HashMap<(Hash, u32), Height>,
+    height: Height,
 }
 
 impl Default for UtxoPool {
     fn default() -> UtxoPool {
         UtxoPool {
             utxos: HashMap::new(),
+            utxo_ages: HashMap::new(),
+            height: Height::from(0),
         }
     }
 }
 
 impl UtxoPool {
     pub fn new(chain: &Blockchain) -> UtxoPool {
-        let mut pool = UtxoPool {
-            utxos: HashMap::new(),
-        };
+        let mut pool = UtxoPool::default();
         for block in chain.iter() {
             pool.update(block);
         }
         pool
     }
 
+    /// Builds the pool from a list of blocks like [new](UtxoPool::new), but splits the
+    /// blocks into segments that are processed in parallel.
+    ///
+    /// Every segment produces a partial pool with the outputs it created and didn't spend,
+    /// plus the set of outputs it spent from previous segments. The partial results are
+    /// then merged in block order.
+    ///
+    pub fn from_blocks_parallel(blocks: &[Block]) -> UtxoPool {
+        let segment_len = blocks.len().div_ceil(rayon::current_num_threads()).max(1);
+        let segments: Vec<(UtxoPool, HashSet<(Hash, u32)>)> = blocks
+            .par_chunks(segment_len)
+            .enumerate()
+            .map(|(segment_index, segment)| {
+                let mut pool = UtxoPool::default();
+                let mut spent = HashSet::new();
+                for (offset, block) in segment.iter().enumerate() {
+                    let height = Height::from(segment_index * segment_len + offset);
+                    for tx in block.transactions() {
+                        for (index, output) in tx.data.outputs.iter().enumerate() {
+                            if !output.is_spendable() {
+                                continue;
+                            }
+                            let key = (tx.hash.clone(), index as u32);
+                            pool.utxo_ages.insert(key.clone(), height.clone());
+                            pool.utxos.insert(key, output.clone());
+                        }
+
+                        for input in tx.data.inputs.iter() {
+                            let key = (input.hash.clone(), input.index);
+                            pool.utxo_ages.remove(&key);
+                            if pool.utxos.remove(&key).is_none() {
+                                spent.insert(key);
+                            }
+                        }
+                    }
+                }
+                (pool, spent)
+            })
+            .collect();
+
+        let mut pool = UtxoPool::default();
+        for (partial, spent) in segments {
+            for key in spent.iter() {
+                pool.utxos.remove(key);
+                pool.utxo_ages.remove(key);
+            }
+            pool.utxos.extend(partial.utxos);
+            pool.utxo_ages.extend(partial.utxo_ages);
+        }
+        pool.height = Height::from(blocks.len());
+        pool
+    }
+
     pub fn get_with_pred<P>(&self, pred: P) -> Vec<Utxo>
     where
         P: Fn(&Output) -> bool,
@@ -56,25 +122,155 @@ impl UtxoPool {
             .collect()
     }
 
+    pub fn get_with_key_pred<P>(&self, pred: P) -> Vec<Utxo>
+    where
+        P: Fn(&(Hash, u32)) -> bool,
+    {
+        self.utxos
+            .iter()
+            .filter(|(key, _)| pred(key))
+            .map(|(k, v)| Utxo::new(k.0.clone(), k.1, v.value))
+            .collect()
+    }
+
     pub fn get_all(&self) -> Vec<Utxo> {
         self.get_with_pred(|_| true)
     }
 
+    /// Maps each input of the transaction to the UTXO it spends, or None if the UTXO is
+    /// not in the pool
+    ///
+    pub fn inputs_as_utxos(&self, tx: &Transaction) -> Vec<Option<Utxo>> {
+        tx.data
+            .inputs
+            .iter()
+            .map(|input| {
+                self.utxos
+                    .get(&(input.hash.clone(), input.index))
+                    .map(|output| Utxo::new(input.hash.clone(), input.index, output.value))
+            })
+            .collect()
+    }
+
+    /// Computes the total input value of the transaction using only the pool, without
+    /// scanning the chain. Returns None if any of the inputs is not in the pool.
+    ///
+    pub fn total_input_value(&self, tx: &Transaction) -> Option<Value> {
+        self.inputs_as_utxos(tx)
+            .into_iter()
+            .map(|utxo| utxo.map(|utxo| utxo.value))
+            .sum()
+    }
+
     pub fn get_for_key(&self, pubkey: &PublicKey) -> Vec<Utxo> {
         self.get_with_pred(|output| output.pubkey == *pubkey)
     }
 
+    pub fn total_utxo_count(&self) -> usize {
+        self.utxos.len()
+    }
+
+    /// Counts the unspent outputs owned by a public key, without collecting them
+    ///
+    pub fn total_utxo_count_for_key(&self, pubkey: &PublicKey) -> usize {
+        self.utxos
+            .values()
+            .filter(|output| output.pubkey == *pubkey)
+            .count()
+    }
+
+    /// Returns the total value of the unspent outputs, i.e. the coin supply
+    ///
+    pub fn total_value(&self) -> Value {
+        self.utxos
+            .values()
+            .fold(0, |acc, output| acc.saturating_add(output.value))
+    }
+
     pub fn update(&mut self, block: &Block) {
         for tx in block.data.transactions.iter() {
-            for (index, output) in tx.data.outputs.iter().enumerate() {
-                self.utxos
-                    .insert((tx.hash.clone(), index as u32), output.clone());
+            self.update_tx(tx);
+        }
+        self.height = Height::from(u64::from(self.height.clone()) + 1);
+    }
+
+    /// Applies a single transaction, as confirmed by the next block. Data outputs are
+    /// skipped, since they can't be spent.
+    ///
+    pub fn update_tx(&mut self, tx: &Transaction) {
+        for (index, output) in tx.data.outputs.iter().enumerate() {
+            if !output.is_spendable() {
+                continue;
+            }
+            let key = (tx.hash.clone(), index as u32);
+            self.utxo_ages.insert(key.clone(), self.height.clone());
+            self.utxos.insert(key, output.clone());
+        }
+
+        for input in tx.data.inputs.iter() {
+            let key = (input.hash.clone(), input.index);
+            self.utxos.remove(&key);
+            self.utxo_ages.remove(&key);
+        }
+    }
+
+    /// Returns the UTXOs of the public key that are not dust, i.e. are worth at least
+    /// the dust limit. Spending dust UTXOs would likely cost more in fees than their value.
+    ///
+    pub fn get_non_dust_for_key(&self, pubkey: &PublicKey, dust_limit: Value) -> Vec<Utxo> {
+        self.get_with_pred(|output| output.pubkey == *pubkey && !output.is_dust(dust_limit))
+    }
+
+    /// Returns the height of the block that confirmed the UTXO
+    ///
+    pub fn utxo_age(&self, hash: &Hash, index: u32) -> Option<Height> {
+        self.utxo_ages.get(&(hash.clone(), index)).cloned()
+    }
+
+    /// Returns the UTXOs of the public key confirmed at least min_age blocks before
+    /// the last block applied to the pool. An age of 0 selects all of them.
+    ///
+    pub fn get_by_min_age(&self, min_age: Height, pubkey: &PublicKey) -> Vec<Utxo> {
+        let height = u64::from(self.height.clone());
+        let min_age = u64::from(min_age);
+        self.utxos
+            .iter()
+            .filter(|(key, output)| {
+                output.pubkey == *pubkey
+                    && match self.utxo_ages.get(*key) {
+                        Some(confirmed) => {
+                            height.saturating_sub(u64::from(confirmed.clone()) + 1) >= min_age
+                        }
+                        None => false,
+                    }
+            })
+            .map(|(k, v)| Utxo::new(k.0.clone(), k.1, v.value))
+            .collect()
+    }
+
+    /// Undoes [update](UtxoPool::update): removes the outputs created by the block and
+    /// restores the outputs it spent, looking them up in the chain. Spent outputs that
+    /// were pruned from the chain can't be restored.
+    ///
+    pub fn rollback(&mut self, block: &Block, chain: &Blockchain) {
+        for tx in block.data.transactions.iter().rev() {
+            for index in 0..tx.data.outputs.len() {
+                let key = (tx.hash.clone(), index as u32);
+                self.utxos.remove(&key);
+                self.utxo_ages.remove(&key);
             }
 
             for input in tx.data.inputs.iter() {
-                self.utxos.remove(&(input.hash.clone(), input.index));
+                if let Some((height, spent)) = chain.query_tx(&input.hash) {
+                    if let Some(output) = spent.data.outputs.get(input.index as usize) {
+                        let key = (input.hash.clone(), input.index);
+                        self.utxo_ages.insert(key.clone(), Height::from(height));
+                        self.utxos.insert(key, output.clone());
+                    }
+                }
             }
         }
+        self.height = Height::from(u64::from(self.height.clone()).saturating_sub(1));
     }
 
     pub fn is_unspent(&self, tx: &Transaction) -> bool {
@@ -96,6 +292,96 @@ pub enum ChainOpError {
     TargetNotSatisfied,
     InvalidBlock,
     InvalidPrevHash,
+    Orphan,
+    LocktimeNotReached,
+    BlockVersionTooLow,
+    BlockTooLarge,
+    FeeTooLow,
+    OrphanPoolFull,
+}
+
+impl fmt::Display for ChainOpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Chain error: {}",
+            match self {
+                ChainOpError::InvalidChain => "invalid chain",
+                ChainOpError::TargetNotSatisfied => "block hash doesn't satisfy the target",
+                ChainOpError::InvalidBlock => "invalid block",
+                ChainOpError::InvalidPrevHash => "block doesn't point to the last block",
+                ChainOpError::Orphan => "block parent is unknown, kept as orphan",
+                ChainOpError::LocktimeNotReached => "output locktime not reached",
+                ChainOpError::BlockVersionTooLow => "block version too low",
+                ChainOpError::BlockTooLarge => "block exceeds the max block size",
+                ChainOpError::FeeTooLow => "transaction fee below the minimum",
+                ChainOpError::OrphanPoolFull => "orphan pool is full",
+            }
+        )
+    }
+}
+
+impl std::error::Error for ChainOpError {}
+
+impl From<BlockchainError> for ChainOpError {
+    fn from(value: BlockchainError) -> Self {
+        match value {
+            BlockchainError::InvalidPrevHash => ChainOpError::InvalidPrevHash,
+        }
+    }
+}
+
+/// Detailed result of a block validation, see
+/// [add_block_with_validation_report](Chain::add_block_with_validation_report).
+///
+/// Passed lists the block-level checks that succeeded, failed describes the first
+/// block-level check that didn't, and details maps invalid transactions to the reason
+/// they were rejected.
+///
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    pub passed: Vec<String>,
+    pub failed: Option<String>,
+    pub details: HashMap<Hash, String>,
+}
+
+impl ValidationReport {
+    fn check(&mut self, name: &str, result: bool) {
+        if result {
+            self.passed.push(name.to_string());
+        } else if self.failed.is_none() {
+            self.failed = Some(name.to_string());
+        }
+    }
+}
+
+/// An entry of the transaction history of a public key. It reports the value
+/// the transaction assigned to the key (credited), the value of the key's outputs
+/// it spent (debited), and the balance of the key after the transaction.
+///
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TxHistoryEntry {
+    pub height: Height,
+    pub tx_hash: Hash,
+    pub credited: Value,
+    pub debited: Value,
+    pub balance_after: Value,
+}
+
+/// Overall statistics of a chain, see [stats](Chain::stats).
+///
+/// The total supply is the value of the unspent outputs, the fees collected are the
+/// fees paid by all the non coinbase transactions, and the unique addresses are the
+/// public keys that received at least one output.
+///
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChainStats {
+    pub height: Height,
+    pub total_blocks: u64,
+    pub total_txs: u64,
+    pub total_supply: Value,
+    pub total_fees_collected: Value,
+    pub unique_addresses: usize,
 }
 
 /// A consensus-following, full validating blockchain.
@@ -105,46 +391,155 @@ pub enum ChainOpError {
 ///
 /// The chain maintains a pool of current unspent UTXOs for faster validation.
 ///
+/// The last validated height is the number of leading blocks known to be valid, so that
+/// [validate_chain_incrementally](Chain::validate_chain_incrementally) can skip them.
+/// It's stored in chain files, so that validation can be resumed after a restart.
+///
+/// Blocks whose parent is unknown are kept in an orphan pool, keyed by their hash and
+/// indexed by their previous hash, and added as soon as their parent becomes the last
+/// block.
+///
 #[derive(Debug, Clone)]
 pub struct Chain {
     pub rules: ConsensusRules,
     pub chain: Blockchain,
     utxos: UtxoPool,
+    last_validated_height: Height,
+    orphans: HashMap<Hash, Block>,
+    orphan_children: HashMap<Hash, Vec<Hash>>,
 }
 
 impl Chain {
     fn init(rules: ConsensusRules, chain: Blockchain) -> Chain {
-        let utxos = UtxoPool::new(&chain);
+        let utxos = UtxoPool::from_blocks_parallel(&chain.list);
+        Self::init_with_utxos(rules, chain, utxos)
+    }
+
+    fn init_with_utxos(rules: ConsensusRules, chain: Blockchain, utxos: UtxoPool) -> Chain {
         Chain {
             rules,
             chain,
             utxos,
+            last_validated_height: Height::from(0),
+            orphans: HashMap::new(),
+            orphan_children: HashMap::new(),
         }
     }
 
+    fn init_with_genesis(rules: ConsensusRules, genesis: Block) -> Chain {
+        let mut chain = Self::init(rules, Blockchain::new(genesis));
+        chain.last_validated_height = chain.height();
+        chain
+    }
+
     pub fn new(pubkey: &PublicKey) -> Chain {
         let rules = ConsensusRules::default();
         let genesis = new_genesis_block(pubkey, rules.base_coins);
-        Self::init(rules, Blockchain::new(genesis))
+        Self::init_with_genesis(rules, genesis)
     }
 
     pub fn new_with_consensus(pubkey: &PublicKey, rules: ConsensusRules) -> Chain {
-        let genesis = new_genesis_block(pubkey, rules.base_coins);
-        Self::init(rules, Blockchain::new(genesis))
+        let genesis = new_genesis_block(pubkey, rules.reward(Height::from(0)));
+        Self::init_with_genesis(rules, genesis)
     }
 
+    /// Loads a chain, validating it from the genesis block
+    ///
     pub fn from_serializable(chain: SerializableChain) -> Result<Chain, ChainOpError> {
-        let chain = Self::init(chain.rules, chain.chain);
-        if !chain.validate_chain() {
+        let mut chain = Self::from_serializable_unchecked(chain);
+        if !chain.validate_chain_incrementally(Height::from(0)) {
             return Err(ChainOpError::InvalidChain);
         }
         Ok(chain)
     }
 
+    /// Loads a chain saved by this node, validating only the blocks after the stored
+    /// [last validated height](Chain::last_validated_height).
+    ///
+    /// The UTXO pool is always rebuilt by replaying the chain, see
+    /// [repair_utxo_pool_from_scratch](Blockchain::repair_utxo_pool_from_scratch), and a
+    /// warning is printed if the stored one differs, e.g. because the chain file was
+    /// edited or written by an incompatible version.
+    ///
+    pub fn from_serializable_with_repair(chain: SerializableChain) -> Result<Chain, ChainOpError> {
+        let utxos = chain.chain.repair_utxo_pool_from_scratch();
+        if chain.is_utxo_pool_stale(&utxos) {
+            println!("Warning: stored UTXO pool is stale, using the pool rebuilt from the chain");
+        }
+        let height = chain.chain.height();
+        let mut loaded = Self::init_with_utxos(chain.rules, chain.chain, utxos);
+        loaded.last_validated_height = chain.last_validated_height.min(height);
+        let validated_height = loaded.last_validated_height();
+        if !loaded.validate_chain_incrementally(validated_height) {
+            return Err(ChainOpError::InvalidChain);
+        }
+        Ok(loaded)
+    }
+
+    /// Same as [from_serializable](Chain::from_serializable) without validating the
+    /// chain, for inspecting chains that may be invalid. The stored
+    /// [last validated height](Chain::last_validated_height) is kept, so that validation
+    /// can be resumed with [validate_chain_incrementally](Chain::validate_chain_incrementally).
+    ///
+    pub fn from_serializable_unchecked(chain: SerializableChain) -> Chain {
+        let height = chain.chain.height();
+        let mut loaded = Self::init(chain.rules, chain.chain);
+        loaded.last_validated_height = chain.last_validated_height.min(height);
+        loaded
+    }
+
     pub fn get_block(&self, height: usize) -> Option<&Block> {
         self.chain.list.get(height)
     }
 
+    /// Iterates over all the transactions in block order, with the height of their block
+    ///
+    pub fn iter_transactions(&self) -> impl Iterator<Item = (Height, &Transaction)> {
+        self.chain.list.iter().enumerate().flat_map(|(i, block)| {
+            block
+                .data
+                .transactions
+                .iter()
+                .map(move |tx| (Height::from(i), tx))
+        })
+    }
+
+    /// Returns the height and the block containing the transaction, together with the
+    /// transaction itself
+    ///
+    pub fn find_tx(&self, hash: &Hash) -> Option<(Height, &Block, &Transaction)> {
+        let (height, tx) = self.chain.query_tx(hash)?;
+        Some((Height::from(height), &self.chain.list[height], tx))
+    }
+
+    /// Returns up to count blocks starting at the given height, fewer if the chain
+    /// ends before
+    ///
+    pub fn get_block_range(&self, from: Height, count: usize) -> Vec<&Block> {
+        let list = &self.chain.list;
+        let start = usize::from(from).min(list.len());
+        let end = start.saturating_add(count).min(list.len());
+        list[start..end].iter().collect()
+    }
+
+    /// Returns the blocks from start to end, both included, without copying. Returns None
+    /// if any of the heights is out of the chain or start is after end.
+    ///
+    pub fn get_blocks_in_range(&self, start: usize, end: usize) -> Option<&[Block]> {
+        if start > end {
+            return None;
+        }
+        self.chain.list.get(start..=end)
+    }
+
+    /// Returns the last count blocks, or all of them if the chain is shorter, from
+    /// the oldest to the newest
+    ///
+    pub fn latest_blocks(&self, count: usize) -> Vec<&Block> {
+        let list = &self.chain.list;
+        list[list.len().saturating_sub(count)..].iter().collect()
+    }
+
     pub fn get_last_block(&self) -> &Block {
         self.chain.get_last_block()
     }
@@ -153,6 +548,14 @@ impl Chain {
         self.chain.height()
     }
 
+    pub fn last_validated_height(&self) -> Height {
+        self.last_validated_height.clone()
+    }
+
+    pub fn utxo_pool(&self) -> &UtxoPool {
+        &self.utxos
+    }
+
     pub fn find_all_utxos(&self) -> Vec<Utxo> {
         self.utxos.get_all()
     }
@@ -161,29 +564,199 @@ impl Chain {
         self.utxos.get_for_key(pubkey)
     }
 
-    /// Verifies if the signatures provided in all the transaction inputs are actually
-    /// associated to the public keys defined in the referenced outputs.
+    pub fn utxo_count(&self) -> usize {
+        self.utxos.total_utxo_count()
+    }
+
+    /// Returns the coins in circulation at the chain tip
     ///
-    fn verify_tx_signatures(&self, tx: &Transaction) -> bool {
-        for input in &tx.data.inputs {
-            let idx = input.index as usize;
-            let (_, input_tx) = match self.chain.query_tx(&input.hash) {
-                Some(result) => result,
-                None => return false,
-            };
+    pub fn total_supply(&self) -> Value {
+        self.utxos.total_value()
+    }
 
-            if input_tx.data.outputs.len() <= idx {
-                return false;
+    /// Returns the total value of the unspent outputs owned by a public key, or None
+    /// if it overflows
+    ///
+    pub fn get_balance(&self, pubkey: &PublicKey) -> Option<Value> {
+        self.utxos
+            .utxos
+            .values()
+            .filter(|output| output.pubkey == *pubkey)
+            .try_fold(0 as Value, |acc, output| acc.checked_add(output.value))
+    }
+
+    /// Returns the balances of a list of public keys, computed in a single
+    /// pass over the UTXO pool, or None if any of them overflows
+    ///
+    pub fn get_balances(&self, pubkeys: &[PublicKey]) -> Option<HashMap<PublicKey, Value>> {
+        let mut balances: HashMap<PublicKey, Value> =
+            pubkeys.iter().map(|pubkey| (pubkey.clone(), 0)).collect();
+        for output in self.utxos.utxos.values() {
+            if let Some(balance) = balances.get_mut(&output.pubkey) {
+                *balance = balance.checked_add(output.value)?;
             }
+        }
+        Some(balances)
+    }
 
-            if !input_tx.data.outputs[idx]
-                .pubkey
-                .verify(input_tx.hash.digest(), &input.signature)
-            {
-                return false;
+    /// Returns all the public keys that received coins in any block. The outputs
+    /// removed by [gc_spent_data](Chain::gc_spent_data) are not taken into account.
+    ///
+    pub fn get_all_addresses(&self) -> HashSet<PublicKey> {
+        self.chain
+            .iter()
+            .flat_map(|block| block.data.transactions.iter())
+            .flat_map(|tx| tx.data.outputs.iter())
+            .map(|output| output.pubkey.clone())
+            .collect()
+    }
+
+    /// Computes the [chain statistics](ChainStats) in a single pass over the blocks
+    ///
+    pub fn stats(&self) -> ChainStats {
+        let mut utxos = UtxoPool::default();
+        let mut total_txs: u64 = 0;
+        let mut total_fees_collected: Value = 0;
+        let mut addresses = HashSet::<&PublicKey>::new();
+        for (_, tx) in self.iter_transactions() {
+            total_txs += 1;
+            if !tx.is_coinbase() {
+                let input = utxos.total_input_value(tx).unwrap_or(0);
+                let output = Blockchain::get_tx_output_value(&tx.data.outputs);
+                total_fees_collected =
+                    total_fees_collected.saturating_add(input.saturating_sub(output));
             }
+            addresses.extend(tx.data.outputs.iter().map(|output| &output.pubkey));
+            utxos.update_tx(tx);
         }
-        return true;
+
+        ChainStats {
+            height: self.height(),
+            total_blocks: self.chain.list.len() as u64,
+            total_txs,
+            total_supply: self.total_supply(),
+            total_fees_collected,
+            unique_addresses: addresses.len(),
+        }
+    }
+
+    /// Returns the public keys owning at least one unspent output
+    ///
+    pub fn get_active_addresses(&self) -> HashSet<PublicKey> {
+        self.utxos
+            .utxos
+            .values()
+            .map(|output| output.pubkey.clone())
+            .collect()
+    }
+
+    pub fn address_count(&self) -> usize {
+        self.get_all_addresses().len()
+    }
+
+    pub fn active_address_count(&self) -> usize {
+        self.get_active_addresses().len()
+    }
+
+    /// Returns, in block order, all the transactions that credited or debited coins
+    /// to/from a public key
+    ///
+    pub fn transaction_history_for_key(&self, pubkey: &PublicKey) -> Vec<TxHistoryEntry> {
+        let mut owned = HashMap::<(Hash, u32), Value>::new();
+        let mut balance: Value = 0;
+        let mut history = Vec::new();
+        for (height, block) in self.chain.iter().enumerate() {
+            for tx in block.transactions() {
+                let debited: Value = tx
+                    .data
+                    .inputs
+                    .iter()
+                    .filter_map(|input| owned.remove(&(input.hash.clone(), input.index)))
+                    .sum();
+                let mut credited: Value = 0;
+                for (index, output) in tx.data.outputs.iter().enumerate() {
+                    if output.pubkey == *pubkey {
+                        owned.insert((tx.hash.clone(), index as u32), output.value);
+                        credited += output.value;
+                    }
+                }
+                if credited == 0 && debited == 0 {
+                    continue;
+                }
+                balance = balance + credited - debited;
+                history.push(TxHistoryEntry {
+                    height: Height::from(height),
+                    tx_hash: tx.hash.clone(),
+                    credited,
+                    debited,
+                    balance_after: balance,
+                });
+            }
+        }
+        history
+    }
+
+    /// Maps every transaction in the chain to its number of confirmations: a transaction
+    /// in the last block has one confirmation, and every following block adds one.
+    ///
+    /// The map is built with a single scan of the chain, callers should cache it and
+    /// rebuild it only when a block is added.
+    ///
+    pub fn get_tx_confirmations_map(&self) -> HashMap<Hash, u64> {
+        let height: u64 = self.height().into();
+        let mut confirmations = HashMap::new();
+        for (block_height, block) in self.chain.iter().enumerate() {
+            for tx in block.transactions() {
+                confirmations.insert(tx.hash.clone(), height - block_height as u64);
+            }
+        }
+        confirmations
+    }
+
+    /// Returns the fraction of the target height already reached, in the range [0, 1].
+    /// A chain at or above the target height is fully synced.
+    ///
+    pub fn sync_progress(&self, target_height: Height) -> f64 {
+        let current: u64 = self.height().into();
+        let target: u64 = target_height.into();
+        if current >= target {
+            return 1.0;
+        }
+        current as f64 / target as f64
+    }
+
+    pub fn is_synced(&self, target_height: Height) -> bool {
+        self.height() >= target_height
+    }
+
+    /// Estimates the time needed to reach the target height, given the number of
+    /// blocks downloaded and validated per second. Returns Duration::MAX if the
+    /// throughput is not positive and there are blocks left.
+    ///
+    pub fn estimate_sync_time(&self, target_height: Height, blocks_per_second: f64) -> Duration {
+        let current: u64 = self.height().into();
+        let remaining = u64::from(target_height).saturating_sub(current);
+        if remaining == 0 {
+            return Duration::ZERO;
+        }
+        if blocks_per_second <= 0.0 || blocks_per_second.is_nan() {
+            return Duration::MAX;
+        }
+        Duration::try_from_secs_f64(remaining as f64 / blocks_per_second).unwrap_or(Duration::MAX)
+    }
+
+    /// Verifies if the signatures provided in all the transaction inputs are actually
+    /// associated to the public keys defined in the referenced outputs, or satisfy the
+    /// scripts of the referenced P2SH outputs.
+    ///
+    fn verify_tx_signatures(&self, tx: &Transaction) -> bool {
+        tx.data
+            .inputs
+            .iter()
+            .all(|input| match self.chain.query_tx(&input.hash) {
+                Some((_, input_tx)) => input.verify_signature(input_tx),
+                None => false,
+            })
     }
 
     /// The genesis block is the first block of the blockchain.
@@ -192,7 +765,9 @@ impl Chain {
     /// - It contains only 1 coinbase transaction
     /// - The transaction has 0 input and at least 1 output
     /// - The value of the tx outputs must be less or equal to the
-    ///   base_coins value
+    ///   reward at height 0, i.e. the base_coins value unless the halving is custom
+    /// - The transaction timestamp is 0, i.e. the genesis height
+    /// - The Unix timestamp, if present, is not in the future
     ///
     fn validate_genesis(&self) -> bool {
         let genesis = &self.chain.list[0];
@@ -200,8 +775,13 @@ impl Chain {
             && genesis.data.transactions.len() == 1
             && genesis.data.transactions[0].data.inputs.len() == 0
             && genesis.data.transactions[0].data.outputs.len() > 0
+            && genesis.data.transactions[0].data.timestamp == Some(0)
+            && genesis
+                .data
+                .unix_timestamp
+                .is_none_or(|timestamp| timestamp <= unix_now())
             && match self.chain.get_tx_value(&genesis.data.transactions[0]) {
-                Some(value) => value.output <= self.rules.base_coins,
+                Some(value) => value.output <= self.rules.reward(Height::from(0)),
                 None => false,
             };
     }
@@ -212,27 +792,150 @@ impl Chain {
     /// - There is at least 1 output
     /// - For each input, its signature is valid (using the referenced output pubkey)
     /// - The inputs are unspent
+    /// - The coinbase outputs it spends are mature, assuming it is included in the next block
+    /// - The chain height reached the locktime of the outputs it spends
     /// - For each output, its value is greater than zero
+    /// - Its data outputs have no value and at most
+    ///   [MAX_OUTPUT_DATA](crate::core::transaction::MAX_OUTPUT_DATA) bytes
     /// - The total input value is greater than or equal to the total ouput value
     /// - It doesn't have a timestamp
+    /// - Its inputs and outputs are normalized, if required by the consensus rules
+    /// - It has no dust outputs, if a dust threshold is set by the consensus rules
     ///
-    fn validate_tx(&self, tx: &Transaction, utxos: &UtxoPool) -> bool {
-        return tx.is_hash_valid()
-            && tx.data.inputs.len() > 0
-            && tx.data.outputs.len() > 0
-            && self.verify_tx_signatures(tx)
-            && utxos.is_unspent(tx)
-            && match self.chain.get_tx_value(tx) {
-                Some(value) => value.output > 0 && value.input >= value.output,
-                None => false,
+    /// Pruned transactions are invalid, since their hash covers the outputs that are
+    /// gone. They are only accepted in the blocks below the
+    /// [last validated height](Chain::last_validated_height), see
+    /// [validate_block](Chain::validate_block).
+    ///
+    /// The UTXO pool is provided by the caller, so that transactions can be validated
+    /// against a state other than the current tip, e.g. when validating a block that
+    /// is not the next one, or when replaying the chain.
+    ///
+    pub fn validate_tx(&self, tx: &Transaction, utxos: &UtxoPool) -> bool {
+        self.check_tx(tx, utxos).is_ok()
+    }
+
+    /// Runs the [validate_tx](Chain::validate_tx) checks, returning the reason of the
+    /// first failing one
+    ///
+    fn check_tx(&self, tx: &Transaction, utxos: &UtxoPool) -> Result<(), &'static str> {
+        if !tx.is_hash_valid() {
+            return Err("invalid transaction hash");
+        }
+        if !tx.data.outputs.iter().all(|output| output.is_data_valid()) {
+            return Err("invalid data output");
+        }
+        if tx.data.inputs.is_empty() {
+            return Err("no inputs");
+        }
+        if tx.data.outputs.is_empty() {
+            return Err("no outputs");
+        }
+        if !self.verify_tx_signatures(tx) {
+            return Err("invalid input signature");
+        }
+        if !utxos.is_unspent(tx) {
+            return Err("inputs already spent");
+        }
+        if !self.are_coinbase_inputs_mature(tx, self.chain.list.len()) {
+            return Err("immature coinbase spent");
+        }
+        if self.check_locktimes(tx, utxos).is_err() {
+            return Err("locktime not reached");
+        }
+        match self.chain.get_tx_value(tx) {
+            Some(value) if value.output > 0 && value.input >= value.output => (),
+            _ => return Err("invalid input or output value"),
+        }
+        if self.check_min_fee(tx).is_err() {
+            return Err("fee too low");
+        }
+        if tx.data.timestamp.is_some() {
+            return Err("unexpected timestamp");
+        }
+        if self.rules.require_normalized_txs && !tx.is_normalized() {
+            return Err("transaction not normalized");
+        }
+        if self
+            .rules
+            .dust_threshold
+            .is_some_and(|threshold| tx.is_dust(threshold))
+        {
+            return Err("dust output");
+        }
+        Ok(())
+    }
+
+    /// Checks that the coinbase transaction can be spent in the next block, according
+    /// to the consensus block maturity. Returns true for regular transactions, and
+    /// false if the transaction is not in the chain.
+    ///
+    pub fn is_coinbase_mature(&self, coinbase_tx_hash: &Hash) -> bool {
+        self.is_coinbase_mature_at(coinbase_tx_hash, self.chain.list.len())
+    }
+
+    fn is_coinbase_mature_at(&self, tx_hash: &Hash, spending_height: usize) -> bool {
+        match self.chain.query_tx(tx_hash) {
+            Some((height, tx)) => {
+                !tx.is_coinbase()
+                    || spending_height.saturating_sub(height) as u64 >= self.rules.block_maturity
             }
-            && tx.data.timestamp.is_none();
+            None => false,
+        }
+    }
+
+    /// Checks that the chain reached the locktime of every output spent by the
+    /// transaction. Inputs that are not in the pool are ignored.
+    ///
+    pub fn check_locktimes(&self, tx: &Transaction, utxos: &UtxoPool) -> Result<(), ChainOpError> {
+        Self::check_locktimes_at(tx, utxos, &self.height())
+    }
+
+    fn check_locktimes_at(
+        tx: &Transaction,
+        utxos: &UtxoPool,
+        height: &Height,
+    ) -> Result<(), ChainOpError> {
+        let locked = tx.data.inputs.iter().any(|input| {
+            utxos
+                .utxos
+                .get(&(input.hash.clone(), input.index))
+                .and_then(|output| output.locktime.as_ref())
+                .is_some_and(|locktime| height < locktime)
+        });
+        if locked {
+            return Err(ChainOpError::LocktimeNotReached);
+        }
+        Ok(())
+    }
+
+    /// Checks that the transaction pays at least the min fee of the consensus rules.
+    /// Transactions whose fee can't be computed are rejected if a min fee is set.
+    ///
+    pub fn check_min_fee(&self, tx: &Transaction) -> Result<(), ChainOpError> {
+        if self.rules.min_fee.is_none() {
+            return Ok(());
+        }
+        match self.chain.get_tx_value(tx).map(|value| value.fees) {
+            Some(fees) if self.rules.validate_min_fee(fees) => Ok(()),
+            _ => Err(ChainOpError::FeeTooLow),
+        }
+    }
+
+    fn are_coinbase_inputs_mature(&self, tx: &Transaction, spending_height: usize) -> bool {
+        self.rules.block_maturity == 0
+            || tx
+                .data
+                .inputs
+                .iter()
+                .all(|input| self.is_coinbase_mature_at(&input.hash, spending_height))
     }
 
-    /// Validates a transaction using the current UTXO pool as base
+    /// Validates a transaction using the current UTXO pool as base.
+    /// Pruned and expired transactions are rejected.
     ///
     pub fn validate_new_tx(&self, tx: &Transaction) -> bool {
-        self.validate_tx(tx, &self.utxos)
+        !tx.is_pruned() && !tx.is_expired(&self.height()) && self.validate_tx(tx, &self.utxos)
     }
 
     /// A coinbase transaction is valid on a collection of transactions if:
@@ -242,7 +945,9 @@ impl Chain {
     /// - The coinbase transaction timestamp must be equal to the provided block height
     /// - The total output value is less than or equal to the consensus reward + fees on the tx collection
     ///
-    fn validate_coinbase_tx(
+    /// Useful for checking a hand-crafted coinbase before building a block around it.
+    ///
+    pub fn validate_coinbase_tx(
         &self,
         prev_block_hash: &Hash,
         txs: &[Transaction],
@@ -271,7 +976,11 @@ impl Chain {
                     value.input == 0
                         && value.output > 0
                         && value.output
-                            <= (self.rules.reward(Height::from(height)) + txs_value.unwrap().fees)
+                            <= Block::compute_expected_coinbase_value(
+                                &self.rules,
+                                Height::from(height),
+                                txs_value.unwrap().fees,
+                            )
                 }
                 None => false,
             };
@@ -282,7 +991,10 @@ impl Chain {
     /// transactions in a block which spend the same utxo. This function checks
     /// for double spends in a list of transactions.
     ///
-    fn validate_double_spend(&self, transactions: &[Transaction]) -> bool {
+    /// It only looks at the list itself, so it can be used on a candidate list of
+    /// transactions before they are collected into a block.
+    ///
+    pub fn validate_double_spend(&self, transactions: &[Transaction]) -> bool {
         let mut inputs = HashSet::<(Hash, u32)>::new();
         for tx in transactions {
             for input in &tx.data.inputs {
@@ -304,8 +1016,22 @@ impl Chain {
     /// - The top hash is valid
     /// - All the transactions except the last one are valid regular transactions
     /// - The last transaction is a valid coinbase transaction or a valid regular transaction
+    /// - The coinbase outputs spent by the transactions are mature at the block height
+    /// - The block height reached the locktime of the outputs spent by the transactions
+    /// - None of the transactions is expired at the block height
+    /// - Its Unix timestamp, if present, is valid, see
+    ///   [is_block_timestamp_valid](Chain::is_block_timestamp_valid)
+    /// - Its version is at least the minimum version at the block height
+    /// - Its transactions don't exceed the max block size
     ///
-    fn validate_block(&self, block: &Block, previous: &Block, utxos: &UtxoPool) -> bool {
+    /// The transaction checks are skipped for the blocks affected by pruning, see
+    /// [is_pruned_block_trusted](Chain::is_pruned_block_trusted).
+    ///
+    /// The previous block and the UTXO pool are provided by the caller, which allows
+    /// validating blocks against a state other than the current tip. The consensus
+    /// target is not checked, see [add_block](Chain::add_block).
+    ///
+    pub fn validate_block(&self, block: &Block, previous: &Block, utxos: &UtxoPool) -> bool {
         return block.is_hash_valid()
             && block.data.prev_hash == previous.hash
             && block.data.transactions.len() > 0
@@ -314,60 +1040,745 @@ impl Chain {
                 _ => true,
             }
             && block.is_top_hash_valid()
-            && block.data.transactions[..block.data.transactions.len() - 1]
-                .iter()
-                .fold(true, |acc, tx| acc && self.validate_tx(tx, utxos))
-            && (self.validate_coinbase_tx(
-                block.prev_hash(),
-                block.transactions(),
-                block.transactions().last().unwrap(),
-            ) || self.validate_tx(block.transactions().last().unwrap(), utxos))
-            && self.validate_double_spend(&block.data.transactions);
+            && (self.is_pruned_block_trusted(block, previous)
+                || self.validate_block_transactions(block, previous, utxos))
+            && self.validate_double_spend(&block.data.transactions)
+            && self.is_block_unexpired(block, previous)
+            && self.is_block_timestamp_valid(block, previous)
+            && self.is_block_version_valid(block, previous)
+            && self.rules.validate_block_size(block);
     }
 
-    /// Validates a block using the current last block and UTXO pool as base
+    /// A block Unix timestamp is valid if it's not earlier than the previous block's one,
+    /// and not later than the current time plus the max clock drift of the consensus
+    /// rules. Blocks without timestamp are always valid.
     ///
-    pub fn validate_new_block(&self, block: &Block) -> bool {
-        self.validate_block(block, self.chain.get_last_block(), &self.utxos)
+    pub fn is_block_timestamp_valid(&self, block: &Block, previous: &Block) -> bool {
+        let timestamp = match block.data.unix_timestamp {
+            Some(timestamp) => timestamp,
+            None => return true,
+        };
+        let not_before_previous = previous
+            .data
+            .unix_timestamp
+            .is_none_or(|previous| timestamp >= previous);
+        let not_in_future = self
+            .rules
+            .max_clock_drift_secs
+            .is_none_or(|drift| timestamp <= unix_now().saturating_add(drift));
+        not_before_previous && not_in_future
     }
 
-    /// A chain is valid if:
-    /// - The genesis block is valid
-    /// - All the remaining blocks are valid
-    ///
+    fn is_block_version_valid(&self, block: &Block, previous: &Block) -> bool {
+        match self.chain.query_block(&previous.hash) {
+            Some((height, _)) => {
+                block.data.version >= self.rules.min_block_version_at(&Height::from(height + 1))
+            }
+            None => false,
+        }
+    }
+
+    /// Validates the transactions of a block against the UTXO pool, at the block height
+    ///
+    fn validate_block_transactions(
+        &self,
+        block: &Block,
+        previous: &Block,
+        utxos: &UtxoPool,
+    ) -> bool {
+        let coinbase = block.transactions().last().unwrap();
+        block.data.transactions[..block.data.transactions.len() - 1]
+            .iter()
+            .all(|tx| self.validate_tx(tx, utxos))
+            && (self.validate_coinbase_tx(block.prev_hash(), block.transactions(), coinbase)
+                || self.validate_tx(coinbase, utxos))
+            && self.is_block_mature(block, previous)
+            && self.is_block_unlocked(block, previous, utxos)
+    }
+
+    /// The transactions of a block can't be validated if any of them was pruned or spends
+    /// the outputs of a pruned transaction, since the values and the owners of the pruned
+    /// outputs are gone. Such a block is trusted only if it's below the
+    /// [last validated height](Chain::last_validated_height), i.e. this node validated it
+    /// before pruning.
+    ///
+    pub fn is_pruned_block_trusted(&self, block: &Block, previous: &Block) -> bool {
+        let affected = block.transactions().iter().any(|tx| {
+            tx.is_pruned()
+                || tx.data.inputs.iter().any(|input| {
+                    self.chain
+                        .query_tx(&input.hash)
+                        .is_some_and(|(_, spent)| spent.is_pruned())
+                })
+        });
+        affected
+            && match self.chain.query_block(&previous.hash) {
+                Some((height, _)) => height + 1 < self.last_validated_height.clone().into(),
+                None => false,
+            }
+    }
+
+    fn is_block_unexpired(&self, block: &Block, previous: &Block) -> bool {
+        let height = match self.chain.query_block(&previous.hash) {
+            Some((height, _)) => Height::from(height + 1),
+            None => return false,
+        };
+        !block.transactions().iter().any(|tx| tx.is_expired(&height))
+    }
+
+    /// Checks the output locktimes at the height of the block, since
+    /// [validate_tx](Chain::validate_tx) checks them at the next height, which is not
+    /// the block height when validating old blocks
+    ///
+    fn is_block_unlocked(&self, block: &Block, previous: &Block, utxos: &UtxoPool) -> bool {
+        let height = match self.chain.query_block(&previous.hash) {
+            Some((height, _)) => Height::from(height + 1),
+            None => return false,
+        };
+        block
+            .transactions()
+            .iter()
+            .all(|tx| Self::check_locktimes_at(tx, utxos, &height).is_ok())
+    }
+
+    /// Checks the coinbase maturity at the height of the block, since
+    /// [validate_tx](Chain::validate_tx) assumes the transactions are spent in the next
+    /// block, which is not the case when validating old blocks
+    ///
+    fn is_block_mature(&self, block: &Block, previous: &Block) -> bool {
+        if self.rules.block_maturity == 0 {
+            return true;
+        }
+        let height = match self.chain.query_block(&previous.hash) {
+            Some((height, _)) => height + 1,
+            None => return false,
+        };
+        block
+            .transactions()
+            .iter()
+            .all(|tx| self.are_coinbase_inputs_mature(tx, height))
+    }
+
+    /// Validates a block using the current last block and UTXO pool as base.
+    /// Blocks containing pruned transactions are rejected.
+    ///
+    pub fn validate_new_block(&self, block: &Block) -> bool {
+        !block.transactions().iter().any(|tx| tx.is_pruned())
+            && self.validate_block(block, self.chain.get_last_block(), &self.utxos)
+    }
+
+    /// Prunes the spent transaction outputs of the chain, see
+    /// [gc_spent_data](Blockchain::gc_spent_data)
+    ///
+    pub fn gc_spent_data(&mut self) -> usize {
+        let utxos = &self.utxos.utxos;
+        self.chain
+            .gc_spent_data(|hash, index| utxos.contains_key(&(hash.clone(), index)))
+    }
+
+    /// A chain is valid if:
+    /// - The genesis block is valid
+    /// - The blocks at checkpoint heights match the checkpoint hashes
+    /// - The blocks up to the last checkpoint have a valid hash and point to the previous block
+    /// - All the remaining blocks are valid and satisfy the [target](Chain::target_at)
+    ///   at their height
+    ///
     pub fn validate_chain(&self) -> bool {
-        let mut utxos = UtxoPool::default();
-        utxos.update(&self.chain.list[0]);
-        return self.validate_genesis()
-            && self.chain.list[1..]
+        self.find_invalid_block().is_none()
+    }
+
+    /// Returns the height of the first block that makes the chain invalid, see
+    /// [validate_chain](Chain::validate_chain), or None if the chain is valid.
+    ///
+    pub fn find_invalid_block(&self) -> Option<Height> {
+        self.find_invalid_block_from(0)
+    }
+
+    /// Same as [find_invalid_block](Chain::find_invalid_block), assuming that the blocks
+    /// below the given height are valid
+    ///
+    fn find_invalid_block_from(&self, from_height: usize) -> Option<Height> {
+        if from_height == 0 {
+            let genesis = &self.chain.list[0];
+            if !self.validate_genesis()
+                || !self
+                    .rules
+                    .validate_checkpoint(&Height::from(0), &genesis.hash)
+                || !Self::is_coinbase_timestamp_valid(0, genesis)
+            {
+                return Some(Height::from(0));
+            }
+        }
+
+        let start = from_height.clamp(1, self.chain.list.len());
+        let mut utxos = UtxoPool::from_blocks_parallel(&self.chain.list[..start]);
+        let mut target = self.target_at(&Height::from(start - 1));
+        let trusted_height = self.rules.last_checkpoint_height();
+        for (i, block) in self.chain.list[start..].iter().enumerate() {
+            let height = start + i;
+            let previous = &self.chain.list[height - 1];
+            target = self.retarget(target, height);
+            let trusted = match &trusted_height {
+                Some(trusted_height) => *trusted_height >= height,
+                None => false,
+            };
+            let valid = self
+                .rules
+                .validate_checkpoint(&Height::from(height), &block.hash)
+                && Self::is_coinbase_timestamp_valid(height, block)
+                && if trusted {
+                    block.is_hash_valid() && block.data.prev_hash == previous.hash
+                } else {
+                    Target::from_hash(&block.hash) <= target
+                        && self.validate_block(block, previous, &utxos)
+                };
+            if !valid {
+                return Some(Height::from(height));
+            }
+            utxos.update(block);
+        }
+        None
+    }
+
+    /// Validates the blocks from the given height onward, as done by
+    /// [validate_chain](Chain::validate_chain), assuming that the previous blocks are
+    /// valid. Usually called with the [last validated height](Chain::last_validated_height),
+    /// which is then updated: on success to the chain height, on failure to the height
+    /// of the invalid block.
+    ///
+    pub fn validate_chain_incrementally(&mut self, from_height: Height) -> bool {
+        match self.find_invalid_block_from(from_height.into()) {
+            None => {
+                self.last_validated_height = self.height();
+                true
+            }
+            Some(height) => {
+                self.last_validated_height = height;
+                false
+            }
+        }
+    }
+
+    /// Validates the block at the given height against the previous blocks, as done by
+    /// [validate_chain](Chain::validate_chain) but without trusting checkpoints.
+    /// Returns None if there is no block at that height.
+    ///
+    pub fn validate_block_at(&self, height: usize) -> Option<bool> {
+        let block = self.chain.list.get(height)?;
+        let valid = self
+            .rules
+            .validate_checkpoint(&Height::from(height), &block.hash)
+            && Self::is_coinbase_timestamp_valid(height, block)
+            && match height {
+                0 => self.validate_genesis(),
+                _ => {
+                    let utxos = UtxoPool::from_blocks_parallel(&self.chain.list[..height]);
+                    Target::from_hash(&block.hash) <= self.target_at(&Height::from(height))
+                        && self.validate_block(block, &self.chain.list[height - 1], &utxos)
+                }
+            };
+        Some(valid)
+    }
+
+    /// Verifies that coinbase timestamps, which encode the height of the previous
+    /// block, are consistent across the whole chain:
+    /// - The genesis coinbase has no timestamp or a zero timestamp
+    /// - Every other coinbase has a timestamp equal to its block height minus one
+    ///
+    /// As a consequence, the genesis and block 1 coinbases both carry 0, and from block 1
+    /// on timestamps are strictly increasing without gaps or repeats (blocks that don't
+    /// contain a coinbase transaction are skipped).
+    ///
+    pub fn audit_coinbase_monotonicity(&self) -> bool {
+        self.chain
+            .iter()
+            .enumerate()
+            .all(|(height, block)| Self::is_coinbase_timestamp_valid(height, block))
+    }
+
+    fn is_coinbase_timestamp_valid(height: usize, block: &Block) -> bool {
+        let coinbase = match block.transactions().last() {
+            Some(tx) if tx.is_coinbase() => tx,
+            _ => return true,
+        };
+        let timestamp = match (height, coinbase.data.timestamp) {
+            (0, None) => 0,
+            (_, Some(timestamp)) => timestamp,
+            (_, None) => return false,
+        };
+        timestamp == height.saturating_sub(1) as u64
+    }
+
+    /// A chain is structurally sound if:
+    /// - The genesis block has an all zeroes prev_hash
+    /// - The hash and the top hash of every block are valid
+    /// - Every block points to the previous one
+    ///
+    /// Neither transactions nor consensus rules are checked, so this function only
+    /// detects corrupted data, not invalid blocks.
+    ///
+    pub fn validate_chain_structure(&self) -> bool {
+        self.chain.list[0].data.prev_hash.is_zero()
+            && self
+                .chain
                 .iter()
-                .enumerate()
-                .fold(true, |acc, (i, block)| {
-                    let result = acc && self.validate_block(block, &self.chain.list[i], &utxos);
-                    utxos.update(block);
-                    result
-                });
+                .all(|block| block.is_hash_valid() && block.is_top_hash_valid())
+            && self
+                .chain
+                .list
+                .windows(2)
+                .all(|pair| pair[1].data.prev_hash == pair[0].hash)
+    }
+
+    /// Returns the UTXOs in the pool referencing transactions that are not in the chain,
+    /// e.g. left behind by a faulty reorganization. A consistent chain has none.
+    ///
+    pub fn get_orphan_utxos(&self) -> Vec<Utxo> {
+        self.utxos
+            .get_with_key_pred(|(hash, _)| self.chain.query_tx(hash).is_none())
+    }
+
+    pub fn has_orphan_utxos(&self) -> bool {
+        self.utxos
+            .utxos
+            .keys()
+            .any(|(hash, _)| self.chain.query_tx(hash).is_none())
+    }
+
+    /// Panics if the chain data is inconsistent, meant for tests and debugging.
+    /// Checks that the chain is structurally sound and that the UTXO pool
+    /// doesn't contain orphans.
+    ///
+    pub fn assert_invariants(&self) {
+        assert!(self.validate_chain_structure(), "invalid chain structure");
+        assert!(!self.has_orphan_utxos(), "orphan UTXOs in pool");
     }
 
     /// A block can be added to the blockchain if:
-    /// - Its hash satisfies the consensus target
+    /// - Its hash satisfies the [target](Chain::compute_retarget_target) of the next block
     /// - It's a valid block
     ///
+    /// A block whose parent is not in the chain is kept in the orphan pool and
+    /// [Orphan](ChainOpError::Orphan) is returned, or
+    /// [OrphanPoolFull](ChainOpError::OrphanPoolFull) if the pool is full. After a block
+    /// is added, the orphans pointing to the new last block are added too: when several
+    /// of them compete, the first one received that is valid is added. The orphans that
+    /// can't be added are dropped together with their descendants.
+    ///
     pub fn add_block(&mut self, block: Block) -> Result<Height, ChainOpError> {
-        if !self.rules.validate_target(&block.hash) {
+        if self.chain.query_block(&block.data.prev_hash).is_none() {
+            return self.add_orphan(block);
+        }
+        let height = self.append_block(block)?;
+        self.connect_orphans();
+        Ok(height)
+    }
+
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    fn add_orphan(&mut self, block: Block) -> Result<Height, ChainOpError> {
+        // The height of an orphan is unknown, the target of the next block is checked
+        // as an estimate and the actual one once the orphan is connected
+        if Target::from_hash(&block.hash) > self.compute_retarget_target() {
+            return Err(ChainOpError::TargetNotSatisfied);
+        }
+        if !block.is_hash_valid() {
+            return Err(ChainOpError::InvalidBlock);
+        }
+        if self.orphans.contains_key(&block.hash) {
+            return Err(ChainOpError::Orphan);
+        }
+        if self.orphans.len() >= MAX_ORPHANS {
+            return Err(ChainOpError::OrphanPoolFull);
+        }
+        self.orphan_children
+            .entry(block.data.prev_hash.clone())
+            .or_default()
+            .push(block.hash.clone());
+        self.orphans.insert(block.hash.clone(), block);
+        Err(ChainOpError::Orphan)
+    }
+
+    fn connect_orphans(&mut self) {
+        loop {
+            let last_hash = self.get_last_block().hash.clone();
+            let children = match self.orphan_children.remove(&last_hash) {
+                Some(children) => children,
+                None => break,
+            };
+            let mut connected = false;
+            for hash in children {
+                if let Some(block) = self.orphans.remove(&hash) {
+                    let appended = self.append_block(block).is_ok();
+                    if !appended {
+                        self.drop_orphan_descendants(&hash);
+                    }
+                    connected |= appended;
+                }
+            }
+            if !connected {
+                break;
+            }
+        }
+    }
+
+    /// Removes from the orphan pool all the blocks descending from the given one
+    ///
+    fn drop_orphan_descendants(&mut self, hash: &Hash) {
+        let mut dropped = vec![hash.clone()];
+        while let Some(hash) = dropped.pop() {
+            for child in self.orphan_children.remove(&hash).unwrap_or_default() {
+                if self.orphans.remove(&child).is_some() {
+                    dropped.push(child);
+                }
+            }
+        }
+    }
+
+    fn append_block(&mut self, block: Block) -> Result<Height, ChainOpError> {
+        if Target::from_hash(&block.hash) > self.compute_retarget_target() {
             return Err(ChainOpError::TargetNotSatisfied);
         }
+        if block.data.version < self.rules.min_block_version_at(&self.height()) {
+            return Err(ChainOpError::BlockVersionTooLow);
+        }
+        if !self.rules.validate_block_size(&block) {
+            return Err(ChainOpError::BlockTooLarge);
+        }
         if !self.validate_new_block(&block) {
             return Err(ChainOpError::InvalidBlock);
         }
+        if !self.rules.validate_checkpoint(&self.height(), &block.hash) {
+            return Err(ChainOpError::InvalidBlock);
+        }
+
+        let validated = self.last_validated_height == self.height();
+        let height = self.chain.append(block)?;
+        let block = self.get_last_block();
+        self.utxos.update(&block.clone());
+        if validated {
+            self.last_validated_height = self.height();
+        }
+        #[cfg(debug_assertions)]
+        self.get_last_block().assert_hash_valid();
+        Ok(height)
+    }
+
+    /// Computes the target that the next block must satisfy, see
+    /// [target_at](Chain::target_at)
+    ///
+    pub fn compute_retarget_target(&self) -> Target {
+        self.target_at(&self.height())
+    }
+
+    /// Computes the target that the block at the given height must satisfy from the
+    /// blocks before it. Starting from the target of the consensus rules, the target
+    /// is adjusted every retarget interval blocks from the duration of the previous
+    /// interval, see [next_target](Target::next_target).
+    ///
+    /// Since the target only depends on the chain history, it's always consistent with
+    /// the blocks in the chain, e.g. after truncating it or reloading it from a file.
+    ///
+    pub fn target_at(&self, height: &Height) -> Target {
+        let height = usize::from(height.clone()).min(self.chain.list.len());
+        (1..=height).fold(self.rules.target.clone(), |target, height| {
+            self.retarget(target, height)
+        })
+    }
+
+    /// Computes the target of the block at the given height from the target of the
+    /// previous block. At the start of every retarget interval, the target is scaled by
+    /// the duration of the last interval blocks, see [next_target](Target::next_target).
+    /// The duration is measured between the Unix timestamps of the first and the last
+    /// of them, and is expected to be the target block time for each block after the
+    /// first.
+    ///
+    /// The target is unchanged if retargeting is disabled by the consensus rules, or
+    /// any of the blocks has no Unix timestamp.
+    ///
+    fn retarget(&self, current: Target, height: usize) -> Target {
+        let (interval, block_time) = match (
+            self.rules.retarget_interval,
+            self.rules.target_block_time_secs,
+        ) {
+            (Some(interval), Some(block_time)) if interval > 1 => (interval as usize, block_time),
+            _ => return current,
+        };
+        if height < interval || !height.is_multiple_of(interval) {
+            return current;
+        }
+        let timestamps: Option<Vec<u64>> = self.chain.list[height - interval..height]
+            .iter()
+            .map(|block| block.data.unix_timestamp)
+            .collect();
+        let (first, last) = match timestamps {
+            Some(timestamps) => (timestamps[0], timestamps[timestamps.len() - 1]),
+            None => return current,
+        };
+        let expected = block_time.saturating_mul(interval as u64 - 1);
+        Target::next_target(&current, last.saturating_sub(first), expected)
+    }
+
+    /// Same as [add_block](Chain::add_block), but runs all the validation checks and
+    /// returns a [report](ValidationReport) describing the failing ones if the block
+    /// is rejected. Slower than add_block, meant for diagnosing invalid blocks.
+    ///
+    pub fn add_block_with_validation_report(
+        &mut self,
+        block: Block,
+    ) -> Result<Height, ValidationReport> {
+        let mut report = ValidationReport::default();
+        let txs = block.transactions();
+
+        report.check(
+            "block hash satisfies the target",
+            Target::from_hash(&block.hash) <= self.compute_retarget_target(),
+        );
+        report.check("block hash is valid", block.is_hash_valid());
+        report.check(
+            "block points to the last block",
+            block.data.prev_hash == self.get_last_block().hash,
+        );
+        report.check(
+            "block contains at least one regular transaction",
+            !txs.is_empty() && (txs.len() > 1 || !txs[0].is_coinbase()),
+        );
+        report.check("top hash is valid", block.is_top_hash_valid());
+        report.check(
+            "block timestamp is valid",
+            self.is_block_timestamp_valid(&block, self.get_last_block()),
+        );
+        report.check(
+            "block version is valid",
+            block.data.version >= self.rules.min_block_version_at(&self.height()),
+        );
+        report.check(
+            "block size is valid",
+            self.rules.validate_block_size(&block),
+        );
+
+        if let Some((last, others)) = txs.split_last() {
+            for tx in others {
+                if let Err(reason) = self.check_tx(tx, &self.utxos) {
+                    report.details.insert(tx.hash.clone(), reason.to_string());
+                }
+            }
+            if !self.validate_coinbase_tx(block.prev_hash(), txs, last) {
+                if let Err(reason) = self.check_tx(last, &self.utxos) {
+                    let reason = match last.is_coinbase() {
+                        true => "invalid coinbase transaction",
+                        false => reason,
+                    };
+                    report.details.insert(last.hash.clone(), reason.to_string());
+                }
+            }
+        }
+        report.check("transactions are valid", report.details.is_empty());
+        report.check("no double spending", self.validate_double_spend(txs));
+        report.check(
+            "block matches the checkpoint",
+            self.rules.validate_checkpoint(&self.height(), &block.hash),
+        );
+
+        if report.failed.is_some() {
+            return Err(report);
+        }
+        self.add_block(block).map_err(|err| ValidationReport {
+            failed: Some(err.to_string()),
+            ..report
+        })
+    }
+
+    /// Saves the chain to a temporary file next to the destination, then renames it
+    /// to the destination path. The rename is atomic, so the destination file always
+    /// contains either the previous or the new chain, even if the process crashes
+    /// while writing.
+    ///
+    pub fn save_atomic(&self, path: &Path) -> Result<(), IOError> {
+        let tmp_path = path.with_extension("tmp");
+        if let Err(err) = self.to_serializable().to_file(&tmp_path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+        if fs::rename(&tmp_path, path).is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(IOError::FileOperationFailed);
+        }
+        Ok(())
+    }
+
+    fn to_serializable(&self) -> SerializableChain {
+        SerializableChain {
+            rules: self.rules.clone(),
+            chain: self.chain.clone(),
+            last_validated_height: self.last_validated_height.clone(),
+            utxos: Some(self.utxos.clone()),
+        }
+    }
+
+    /// Saves the chain atomically, then reads it back to make sure the file contains
+    /// exactly the serialized chain, and that it can be loaded as the same, structurally
+    /// sound, chain.
+    ///
+    pub fn save_and_verify(&self, path: &Path) -> Result<(), IOError> {
+        self.save_atomic(path)?;
+        if Hash::from_file(path)? != Hash::new(&self.to_serializable().into_bytes()) {
+            return Err(IOError::DeserializationFailed);
+        }
+        let saved = SerializableChain::from_file(path)?;
+        let saved = Chain::init(saved.rules, saved.chain);
+        if !saved.validate_chain_structure()
+            || saved.height() != self.height()
+            || saved.get_last_block().hash != self.get_last_block().hash
+        {
+            return Err(IOError::DeserializationFailed);
+        }
+        Ok(())
+    }
+
+    /// Saves the UTXO pool with the current height, so that it can be loaded instead
+    /// of being rebuilt from the blocks
+    ///
+    pub fn save_utxo_snapshot(&self, path: &Path) -> Result<(), IOError> {
+        let snapshot = UtxoSnapshot {
+            snapshot_height: self.height(),
+            pool: self.utxos.clone(),
+        };
+        snapshot.to_file(path)?;
+        Ok(())
+    }
+
+    /// Loads a UTXO pool saved by [save_utxo_snapshot](Chain::save_utxo_snapshot).
+    /// Fails if the snapshot wasn't taken at the expected height.
+    ///
+    pub fn load_utxo_snapshot(path: &Path, expected_height: Height) -> Result<UtxoPool, IOError> {
+        let snapshot = UtxoSnapshot::from_file(path)?;
+        if snapshot.snapshot_height != expected_height {
+            return Err(IOError::DeserializationFailed);
+        }
+        Ok(snapshot.pool)
+    }
+
+    /// Returns the height of the common prefix of two chains, i.e. the number of
+    /// leading blocks they share. Returns None if the chains have different genesis blocks.
+    ///
+    pub fn find_fork_point(&self, other: &Chain) -> Option<Height> {
+        let common = self
+            .chain
+            .iter()
+            .zip(other.chain.iter())
+            .take_while(|(block, other_block)| block.hash == other_block.hash)
+            .count();
+        if common == 0 {
+            return None;
+        }
+        Some(Height::from(common))
+    }
+
+    /// Returns the most recent block that is an ancestor of both blocks, or one of them
+    /// if it's an ancestor of the other. Returns None if any of the blocks is not in
+    /// the chain.
+    ///
+    /// The chain stores a single branch, so the common ancestor is always the lower
+    /// of the two blocks. Forks between different chains are found with
+    /// [find_fork_point](Chain::find_fork_point).
+    ///
+    pub fn find_common_ancestor(&self, hash_a: &Hash, hash_b: &Hash) -> Option<(Height, &Block)> {
+        let (height_a, _) = self.chain.query_block(hash_a)?;
+        let (height_b, _) = self.chain.query_block(hash_b)?;
+        let height = height_a.min(height_b);
+        Some((Height::from(height), &self.chain.list[height]))
+    }
 
-        match self.chain.append(block) {
-            Err(BlockchainError::InvalidPrevHash) => Err(ChainOpError::InvalidPrevHash),
-            Ok(value) => {
-                let block = self.get_last_block();
-                self.utxos.update(&block.clone());
-                Ok(value)
+    /// Returns true if the ancestor block is reached by walking back from the descendant
+    /// block through the prev_hash links. A block is not a descendant of itself.
+    ///
+    pub fn is_descendant(&self, ancestor_hash: &Hash, descendant_hash: &Hash) -> bool {
+        let (mut height, mut block) = match self.chain.query_block(descendant_hash) {
+            Some(result) => result,
+            None => return false,
+        };
+        while height > 0 {
+            let previous = &self.chain.list[height - 1];
+            if previous.hash != block.data.prev_hash {
+                return false;
             }
+            if previous.hash == *ancestor_hash {
+                return true;
+            }
+            height -= 1;
+            block = previous;
+        }
+        false
+    }
+
+    /// Returns the hash of the block found walking back the given number of blocks from
+    /// a block, or None if the block is not in the chain or the walk goes past genesis.
+    ///
+    pub fn ancestor_at_depth(&self, from_hash: &Hash, depth: usize) -> Option<&Hash> {
+        let (height, _) = self.chain.query_block(from_hash)?;
+        let ancestor = self.chain.list.get(height.checked_sub(depth)?)?;
+        Some(&ancestor.hash)
+    }
+
+    /// Removes all the blocks from the given height onwards and rebuilds the UTXO pool.
+    /// The blocks added later are not validated, so the last validated height can't
+    /// exceed the new height.
+    ///
+    fn truncate(&mut self, height: Height) {
+        self.chain.list.truncate(height.clone().into());
+        self.utxos = UtxoPool::new(&self.chain);
+        self.last_validated_height = self.last_validated_height.clone().min(height);
+    }
+}
+
+/// Simulates block propagation between two chains, as two nodes would do when syncing.
+/// Only meant for testing multi-node scenarios.
+///
+pub struct PeerSync<'a> {
+    local: &'a mut Chain,
+    remote: &'a Chain,
+}
+
+impl<'a> PeerSync<'a> {
+    pub fn new(local: &'a mut Chain, remote: &'a Chain) -> PeerSync<'a> {
+        PeerSync { local, remote }
+    }
+
+    /// Brings the local chain up to date with the remote one, if the remote chain is longer.
+    /// Blocks of the local chain following the fork point are replaced by the remote ones.
+    ///
+    /// Every remote block is validated before being added; if any of them is invalid,
+    /// the local chain is left untouched. Returns the number of blocks added.
+    ///
+    pub fn sync_from_remote(&mut self) -> Result<usize, ChainOpError> {
+        let fork_point = self
+            .local
+            .find_fork_point(self.remote)
+            .ok_or(ChainOpError::InvalidChain)?;
+        if self.remote.height() <= self.local.height() {
+            return Ok(0);
+        }
+
+        let mut synced = self.local.clone();
+        synced.truncate(fork_point.clone());
+        let missing = &self.remote.chain.list[usize::from(fork_point)..];
+        for block in missing {
+            synced.add_block(block.clone())?;
+        }
+        *self.local = synced;
+        Ok(missing.len())
+    }
+
+    /// Syncs the shorter chain from the longer one. Returns the number of blocks added.
+    ///
+    pub fn sync_bidirectional(a: &mut Chain, b: &mut Chain) -> Result<usize, ChainOpError> {
+        if a.height() >= b.height() {
+            PeerSync::new(b, a).sync_from_remote()
+        } else {
+            PeerSync::new(a, b).sync_from_remote()
         }
     }
 }
@@ -378,6 +1789,12 @@ impl Chain {
 pub struct SerializableChain {
     pub rules: ConsensusRules,
     pub chain: Blockchain,
+    #[serde(default)]
+    pub last_validated_height: Height,
+    /// UTXO pool cached by the node that saved the chain, see
+    /// [from_serializable_with_repair](Chain::from_serializable_with_repair)
+    #[serde(default)]
+    pub utxos: Option<UtxoPool>,
 }
 
 impl SerializableChain {
@@ -385,52 +1802,1418 @@ impl SerializableChain {
         SerializableChain {
             rules: chain.rules,
             chain: chain.chain,
+            last_validated_height: chain.last_validated_height,
+            utxos: Some(chain.utxos),
         }
     }
+
+    /// Returns true if the stored UTXO pool differs from the given one, usually rebuilt
+    /// from the blocks. Chains saved without a pool are never stale.
+    ///
+    pub fn is_utxo_pool_stale(&self, utxos: &UtxoPool) -> bool {
+        self.utxos
+            .as_ref()
+            .is_some_and(|stored| stored.utxos != utxos.utxos)
+    }
 }
 
 impl ByteIO for SerializableChain {}
 impl FileIO for SerializableChain {}
 impl JsonIO for SerializableChain {}
 
+impl ByteIO for UtxoPool {}
+impl FileIO for UtxoPool {}
+
+/// Helper struct for saving a [UTXO pool](UtxoPool) together with the height of the
+/// chain it was computed from, see [save_utxo_snapshot](Chain::save_utxo_snapshot)
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UtxoSnapshot {
+    pub snapshot_height: Height,
+    pub pool: UtxoPool,
+}
+
+impl ByteIO for UtxoSnapshot {}
+impl FileIO for UtxoSnapshot {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::consensus::{Halving, Target};
+    use crate::consensus::{ConsensusRulesBuilder, Halving, Target, DEFAULT_MAX_CLOCK_DRIFT_SECS};
     use crate::core::block::{Block, BlockData};
     use crate::core::hash::Hash;
     use crate::core::keys::KeyPair;
-    use crate::core::transaction::{Input, Output, TransactionData, Value};
+    use crate::core::script::Script;
+    use crate::core::testing::BlockGen;
+    use crate::core::transaction::{Input, Output, TransactionData, Value, MAX_OUTPUT_DATA};
+    use crate::mining::miner::{Miner, DEFAULT_POOL_CAPACITY};
     use ethnum::U256;
 
     #[test]
-    fn validate_genesis() {
-        let key = KeyPair::new();
-        let mut chain = Chain::new(&key.public_key());
-        assert!(chain.validate_genesis());
+    fn validate_genesis() {
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        assert!(chain.validate_genesis());
+
+        chain.chain.list[0].data.transactions[0].data.outputs[0].value -= 100;
+        assert!(chain.validate_genesis());
+
+        chain.chain.list[0].data.transactions[0].data.outputs[0].value += 101;
+        assert!(!chain.validate_genesis());
+    }
+
+    #[test]
+    fn validate_genesis_timestamp() {
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        assert!(chain.get_last_block().data.unix_timestamp.is_none());
+
+        chain.chain.list[0] =
+            new_genesis_block_with_timestamp(&key.public_key(), chain.rules.base_coins, 1700000000);
+        assert_eq!(chain.get_last_block().data.unix_timestamp, Some(1700000000));
+        assert!(chain.get_last_block().is_hash_valid());
+        assert!(chain.validate_genesis());
+
+        chain.chain.list[0].data.unix_timestamp = Some(unix_now() + 3600);
+        assert!(!chain.validate_genesis());
+
+        chain.chain.list[0] = Block::new(BlockData::new(
+            Hash::default(),
+            0,
+            vec![new_coinbase_tx(&key.public_key(), 100, 1)],
+        ));
+        assert!(!chain.validate_genesis());
+    }
+
+    #[test]
+    fn errors() {
+        use std::error::Error;
+
+        fn add_block(chain: &mut Chain, block: Block) -> Result<Height, Box<dyn Error>> {
+            Ok(chain.add_block(block)?)
+        }
+
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        let block = Block::new(BlockData::new(Hash::new(b"test"), 0, vec![]));
+
+        let err = add_block(&mut chain, block).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Chain error: block parent is unknown, kept as orphan"
+        );
+
+        let last_hash = chain.get_last_block().hash.clone();
+        let block = Block::new(BlockData::new(last_hash, 0, vec![]));
+
+        let err = add_block(&mut chain, block).unwrap_err();
+        assert_eq!(err.to_string(), "Chain error: invalid block");
+        assert!(err.source().is_none());
+        assert_eq!(
+            err.downcast_ref::<ChainOpError>(),
+            Some(&ChainOpError::InvalidBlock)
+        );
+
+        let err = ChainOpError::from(BlockchainError::InvalidPrevHash);
+        assert_eq!(err, ChainOpError::InvalidPrevHash);
+        assert_eq!(
+            err.to_string(),
+            "Chain error: block doesn't point to the last block"
+        );
+    }
+
+    #[test]
+    fn find_utxos() {
+        let key = KeyPair::new();
+        let chain = Chain::new(&key.public_key());
+
+        let utxos = chain.find_all_utxos();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].value, chain.rules.base_coins);
+
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].value, chain.rules.base_coins);
+
+        let utxos = chain.find_utxos_for_key(&KeyPair::new().public_key());
+        assert_eq!(utxos.len(), 0);
+    }
+
+    #[test]
+    fn balance() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let key_3 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+
+        assert_eq!(
+            chain.get_balance(&key_1.public_key()).unwrap(),
+            chain.rules.base_coins
+        );
+        assert_eq!(chain.get_balance(&key_2.public_key()).unwrap(), 0);
+
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(3000, key_2.public_key())]).unwrap();
+        let block = new_block(&chain, 0, vec![tx]);
+        assert!(chain.add_block(block).is_ok());
+
+        assert_eq!(chain.get_balance(&key_1.public_key()).unwrap(), 7000);
+        assert_eq!(chain.get_balance(&key_2.public_key()).unwrap(), 3000);
+
+        let balances = chain
+            .get_balances(&[key_1.public_key(), key_2.public_key(), key_3.public_key()])
+            .unwrap();
+        assert_eq!(balances.len(), 3);
+        assert_eq!(balances[&key_1.public_key()], 7000);
+        assert_eq!(balances[&key_2.public_key()], 3000);
+        assert_eq!(balances[&key_3.public_key()], 0);
+
+        // Balances overflowing a value are not returned
+        let overflow = Output::new(Value::MAX, key_3.public_key());
+        chain
+            .utxos
+            .utxos
+            .insert((Hash::new(b"overflow"), 0), overflow);
+        assert_eq!(chain.get_balance(&key_3.public_key()), Some(Value::MAX));
+        chain.utxos.utxos.insert(
+            (Hash::new(b"overflow"), 1),
+            Output::new(1, key_3.public_key()),
+        );
+        assert_eq!(chain.get_balance(&key_3.public_key()), None);
+        assert!(chain.get_balances(&[key_3.public_key()]).is_none());
+        assert!(chain.get_balances(&[key_1.public_key()]).is_some());
+    }
+
+    #[test]
+    fn utxo_pool_parallel() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+
+        for _ in 0..20 {
+            let tx = new_tx(
+                &key_1,
+                &chain.find_utxos_for_key(&key_1.public_key()),
+                vec![Output::new(100, key_2.public_key())],
+            )
+            .unwrap();
+            assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        }
+        assert_eq!(chain.height(), 21);
+
+        let sequential = UtxoPool::new(&chain.chain);
+        let parallel = UtxoPool::from_blocks_parallel(&chain.chain.list);
+        assert_eq!(parallel.utxos, sequential.utxos);
+        assert_eq!(parallel.utxo_ages, sequential.utxo_ages);
+        assert_eq!(parallel.height, sequential.height);
+        assert_eq!(parallel.get_for_key(&key_2.public_key()).len(), 20);
+        assert_eq!(parallel.get_for_key(&key_1.public_key()).len(), 1);
+
+        assert!(UtxoPool::from_blocks_parallel(&[]).utxos.is_empty());
+    }
+
+    #[test]
+    fn utxo_pool_rollback() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+
+        let before = chain.utxo_pool().clone();
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        let mut pool = chain.utxo_pool().clone();
+        assert_ne!(pool.utxos, before.utxos);
+
+        pool.rollback(chain.get_last_block(), &chain.chain);
+        assert_eq!(pool.utxos, before.utxos);
+        assert_eq!(pool.utxo_ages, before.utxo_ages);
+        assert_eq!(pool.height, before.height);
+    }
+
+    #[test]
+    fn utxo_ages() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let genesis_hash = chain.get_block(0).unwrap().transactions()[0].hash.clone();
+        for _ in 0..3 {
+            add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        }
+
+        let pool = chain.utxo_pool();
+        assert_eq!(pool.utxo_age(&genesis_hash, 0), None);
+        let tx_hash = chain.get_block(1).unwrap().transactions()[0].hash.clone();
+        assert_eq!(pool.utxo_age(&tx_hash, 0), Some(Height::from(1)));
+        assert_eq!(pool.utxo_age(&tx_hash, 5), None);
+
+        let pubkey = key_2.public_key();
+        assert_eq!(pool.get_by_min_age(Height::from(0), &pubkey).len(), 3);
+        assert_eq!(pool.get_by_min_age(Height::from(1), &pubkey).len(), 2);
+        assert_eq!(pool.get_by_min_age(Height::from(2), &pubkey).len(), 1);
+        assert_eq!(
+            pool.get_by_min_age(Height::from(2), &pubkey)[0].hash,
+            tx_hash
+        );
+        assert!(pool.get_by_min_age(Height::from(3), &pubkey).is_empty());
+    }
+
+    #[test]
+    fn tx_confirmations() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let genesis_tx = chain.get_last_block().transactions()[0].hash.clone();
+        assert_eq!(chain.get_tx_confirmations_map().get(&genesis_tx), Some(&1));
+
+        let mut txs = Vec::new();
+        for _ in 0..3 {
+            let tx = new_tx(
+                &key_1,
+                &chain.find_utxos_for_key(&key_1.public_key()),
+                vec![Output::new(100, key_2.public_key())],
+            )
+            .unwrap();
+            txs.push(tx.hash.clone());
+            assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        }
+
+        let confirmations = chain.get_tx_confirmations_map();
+        assert_eq!(confirmations.len(), 4);
+        assert_eq!(confirmations.get(&genesis_tx), Some(&4));
+        assert_eq!(confirmations.get(&txs[0]), Some(&3));
+        assert_eq!(confirmations.get(&txs[1]), Some(&2));
+        assert_eq!(confirmations.get(&txs[2]), Some(&1));
+        assert!(!confirmations.contains_key(&Hash::new(b"missing")));
+    }
+
+    fn add_transfer_block(chain: &mut Chain, key: &KeyPair, recipient: &PublicKey) {
+        let tx = new_tx(
+            key,
+            &chain.find_utxos_for_key(&key.public_key()),
+            vec![Output::new(100, recipient.clone())],
+        )
+        .unwrap();
+        assert!(chain.add_block(new_block(chain, 0, vec![tx])).is_ok());
+    }
+
+    #[test]
+    fn orphan_blocks() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+
+        let mut ahead = chain.clone();
+        add_transfer_block(&mut ahead, &key_1, &key_2.public_key());
+        add_transfer_block(&mut ahead, &key_1, &key_2.public_key());
+        let block_1 = ahead.get_block(1).unwrap().clone();
+        let block_2 = ahead.get_block(2).unwrap().clone();
+
+        assert_eq!(chain.add_block(block_2.clone()), Err(ChainOpError::Orphan));
+        assert_eq!(chain.height(), Height::from(1));
+        assert_eq!(chain.orphan_count(), 1);
+
+        assert_eq!(chain.add_block(block_2.clone()), Err(ChainOpError::Orphan));
+        assert_eq!(chain.orphan_count(), 1);
+
+        assert_eq!(chain.add_block(block_1.clone()), Ok(Height::from(1)));
+        assert_eq!(chain.height(), Height::from(3));
+        assert_eq!(chain.orphan_count(), 0);
+        assert_eq!(*chain.get_last_block(), block_2);
+        add_transfer_block(&mut ahead, &key_1, &key_2.public_key());
+        let block_3 = ahead.get_block(3).unwrap().clone();
+
+        // Competing orphans with the same parent don't replace each other
+        let key_3 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let mut fork = ahead.clone();
+        fork.chain.list.truncate(2);
+        let mut fork = Chain::from_serializable_unchecked(fork.to_serializable());
+        add_transfer_block(&mut fork, &key_1, &key_3.public_key());
+        add_transfer_block(&mut fork, &key_1, &key_3.public_key());
+        let fork_2 = fork.get_block(2).unwrap().clone();
+        let fork_3 = fork.get_block(3).unwrap().clone();
+        assert_ne!(fork_2, block_2);
+
+        assert_eq!(chain.add_block(fork_3.clone()), Err(ChainOpError::Orphan));
+        assert_eq!(chain.add_block(fork_2), Err(ChainOpError::Orphan));
+        assert_eq!(chain.add_block(block_2), Err(ChainOpError::Orphan));
+        assert_eq!(chain.add_block(block_3), Err(ChainOpError::Orphan));
+        assert_eq!(chain.orphan_count(), 4);
+
+        // The losing orphan is dropped with its descendants
+        assert_eq!(chain.add_block(block_1.clone()), Ok(Height::from(1)));
+        assert_eq!(chain.height(), Height::from(4));
+        assert_eq!(chain.orphan_count(), 0);
+        assert!(chain.orphan_children.is_empty());
+        assert_eq!(*chain.get_last_block(), fork_3);
+
+        // Invalid orphans are dropped too, and the others are still tried
+        let mut chain = Chain::new(&key_1.public_key());
+        let mut invalid = fork.get_block(2).unwrap().clone();
+        invalid.data.transactions[0].data.outputs[0].value += 1;
+        invalid.data.nonce += 1;
+        let invalid = Block::new(invalid.data);
+        let invalid_child = Block::new(BlockData::new(invalid.hash.clone(), 0, vec![]));
+        assert_eq!(chain.add_block(invalid_child), Err(ChainOpError::Orphan));
+        assert_eq!(chain.add_block(invalid), Err(ChainOpError::Orphan));
+        assert_eq!(chain.add_block(fork_3.clone()), Err(ChainOpError::Orphan));
+        assert_eq!(
+            chain.add_block(fork.get_block(2).unwrap().clone()),
+            Err(ChainOpError::Orphan)
+        );
+        assert_eq!(chain.add_block(block_1), Ok(Height::from(1)));
+        assert_eq!(*chain.get_last_block(), fork_3);
+        assert_eq!(chain.orphan_count(), 0);
+        assert!(chain.orphan_children.is_empty());
+
+        // A full orphan pool rejects new orphans
+        let mut chain = Chain::new(&key_1.public_key());
+        for i in 0..MAX_ORPHANS as u32 {
+            let orphan = Block::new(BlockData::new(Hash::new(&i.to_le_bytes()), 0, vec![]));
+            assert_eq!(chain.add_block(orphan), Err(ChainOpError::Orphan));
+        }
+        let orphan = Block::new(BlockData::new(Hash::new(b"full"), 0, vec![]));
+        assert_eq!(chain.add_block(orphan), Err(ChainOpError::OrphanPoolFull));
+        assert_eq!(chain.orphan_count(), MAX_ORPHANS);
+    }
+
+    #[test]
+    fn block_timestamps() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let timed_block = |chain: &Chain, timestamp: Option<u64>| {
+            let tx = new_tx(
+                &key_1,
+                &chain.find_utxos_for_key(&key_1.public_key()),
+                vec![Output::new(100, key_2.public_key())],
+            )
+            .unwrap();
+            let last_hash = chain.get_last_block().hash.clone();
+            Block::new(BlockData::new_with_timestamp(
+                last_hash,
+                0,
+                vec![tx],
+                timestamp,
+            ))
+        };
+
+        let block = timed_block(&chain, None);
+        let now = block.data.unix_timestamp.unwrap();
+        assert!(now > 0);
+        assert!(chain.add_block(block).is_ok());
+
+        let drift = DEFAULT_MAX_CLOCK_DRIFT_SECS;
+        assert!(chain.add_block(timed_block(&chain, Some(now - 1))).is_err());
+        let future = timed_block(&chain, Some(unix_now() + drift + 60));
+        assert_eq!(
+            chain.add_block(future.clone()),
+            Err(ChainOpError::InvalidBlock)
+        );
+        assert!(chain.add_block(timed_block(&chain, Some(now))).is_ok());
+
+        chain.rules.max_clock_drift_secs = None;
+        let future = timed_block(&chain, Some(unix_now() + drift + 60));
+        assert!(chain.add_block(future).is_ok());
+    }
+
+    #[test]
+    fn block_versions() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let versioned_block = |chain: &Chain, version: u32| {
+            let tx = new_tx(
+                &key_1,
+                &chain.find_utxos_for_key(&key_1.public_key()),
+                vec![Output::new(100, key_2.public_key())],
+            )
+            .unwrap();
+            let mut data = BlockData::new(chain.get_last_block().hash.clone(), 0, vec![tx]);
+            data.version = version;
+            Block::new(data)
+        };
+
+        let block = versioned_block(&chain, 1);
+        let previous = chain.get_last_block().clone();
+        assert!(chain.validate_block(&block, &previous, &chain.utxos));
+        assert!(chain.add_block(block).is_ok());
+
+        // Version 2 is required from the next block on
+        chain
+            .rules
+            .activate_version_at_height(2, chain.height().clone());
+        let block = versioned_block(&chain, 1);
+        assert!(!chain.validate_block(&block, chain.get_last_block(), &chain.utxos));
+        assert_eq!(
+            chain.add_block(block.clone()),
+            Err(ChainOpError::BlockVersionTooLow)
+        );
+        assert!(chain.add_block_with_validation_report(block).is_err());
+        assert!(chain.add_block(versioned_block(&chain, 2)).is_ok());
+        assert!(chain.add_block(versioned_block(&chain, 3)).is_ok());
+
+        chain.rules.min_block_version = 4;
+        assert_eq!(
+            chain.add_block(versioned_block(&chain, 3)),
+            Err(ChainOpError::BlockVersionTooLow)
+        );
+    }
+
+    #[test]
+    fn max_block_size() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let tx = new_tx(
+            &key_1,
+            &chain.find_utxos_for_key(&key_1.public_key()),
+            vec![Output::new(100, key_2.public_key())],
+        )
+        .unwrap();
+        let block = new_block(&chain, 0, vec![tx.clone()]);
+        assert_eq!(block.transactions_size_bytes(), tx.serialized_size());
+
+        chain.rules.max_block_size = Some(tx.serialized_size() - 1);
+        assert!(!chain.rules.validate_block_size(&block));
+        assert!(!chain.validate_block(&block, chain.get_last_block(), &chain.utxos));
+        assert_eq!(
+            chain.add_block(block.clone()),
+            Err(ChainOpError::BlockTooLarge)
+        );
+
+        chain.rules.max_block_size = Some(tx.serialized_size());
+        assert!(chain.rules.validate_block_size(&block));
+        assert!(chain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn retarget() {
+        let build_chain = |block_time: u64| {
+            let key_1 = KeyPair::new();
+            let key_2 = KeyPair::new();
+            let rules = ConsensusRulesBuilder::default()
+                .retarget_interval(3)
+                .target_block_time(600)
+                .build();
+            let mut chain = Chain::new_with_consensus(&key_1.public_key(), rules);
+            for i in 1..6 {
+                let tx = new_tx(
+                    &key_1,
+                    &chain.find_utxos_for_key(&key_1.public_key()),
+                    vec![Output::new(100, key_2.public_key())],
+                )
+                .unwrap();
+                let mut data = new_block(&chain, 0, vec![tx]).data;
+                data.unix_timestamp = Some(1700000000 + i * block_time);
+                assert!(chain.add_block(Block::new(data)).is_ok());
+            }
+            (chain, key_1)
+        };
+
+        // Blocks twice as fast as expected, the target halves after the second period
+        let (mut chain, key) = build_chain(300);
+        let target = chain.compute_retarget_target();
+        assert_eq!(target.value, Target::MAX.value / 2);
+        assert_eq!(chain.rules.target, Target::MAX);
+        assert_eq!(chain.target_at(&Height::from(5)), Target::MAX);
+        assert_eq!(chain.target_at(&Height::from(6)), target);
+
+        // Only blocks satisfying the target of their height are added
+        let block_with_hash = |chain: &Chain, below_target: bool| {
+            let utxos = chain.find_utxos_for_key(&key.public_key());
+            let tx = new_tx(&key, &utxos, vec![Output::new(100, key.public_key())]).unwrap();
+            let mut data = new_block(chain, 0, vec![tx]).data;
+            data.unix_timestamp = Some(1700000000 + 6 * 300);
+            (0..)
+                .map(|nonce| {
+                    Block::new(BlockData {
+                        nonce,
+                        ..data.clone()
+                    })
+                })
+                .find(|block| (Target::from_hash(&block.hash) <= target) == below_target)
+                .unwrap()
+        };
+        let easy = block_with_hash(&chain, false);
+        assert_eq!(
+            chain.add_block(easy.clone()),
+            Err(ChainOpError::TargetNotSatisfied)
+        );
+        let hard = block_with_hash(&chain, true);
+        assert!(chain.add_block(hard).is_ok());
+        assert!(chain.validate_chain());
+        assert_eq!(chain.validate_block_at(6), Some(true));
+
+        // The target is recomputed from the history after truncating and reloading
+        let mut truncated = chain.clone();
+        truncated.truncate(Height::from(3));
+        assert_eq!(truncated.compute_retarget_target(), Target::MAX);
+        let loaded = Chain::from_serializable(chain.to_serializable()).unwrap();
+        assert_eq!(
+            loaded.compute_retarget_target(),
+            chain.compute_retarget_target()
+        );
+        assert_eq!(loaded.target_at(&Height::from(6)), target);
+
+        // A block that doesn't satisfy the target of its height makes the chain invalid
+        let mut invalid = chain.clone();
+        invalid.truncate(Height::from(6));
+        invalid.chain.list.push(easy);
+        assert_eq!(invalid.find_invalid_block(), Some(Height::from(6)));
+        assert_eq!(invalid.validate_block_at(6), Some(false));
+
+        // The genesis block has no timestamp, the first period keeps the target
+        let (mut chain, _) = build_chain(1200);
+        assert_eq!(chain.compute_retarget_target(), Target::MAX);
+
+        // Slow blocks make the target easier, up to 4 times
+        chain.rules.target = Target::from_leading_zeros(8);
+        let target = chain.compute_retarget_target();
+        assert!(target.is_easier_than(&chain.rules.target));
+        assert_eq!(target.value, chain.rules.target.value * 2);
+
+        chain.rules.retarget_interval = None;
+        assert_eq!(chain.compute_retarget_target(), chain.rules.target);
+    }
+
+    #[test]
+    fn invalid_block() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        for _ in 0..3 {
+            add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        }
+        assert_eq!(chain.find_invalid_block(), None);
+        assert!((0..4).all(|height| chain.validate_block_at(height) == Some(true)));
+        assert_eq!(chain.validate_block_at(4), None);
+
+        // Valid structure, but the second block spends an output that doesn't exist
+        let mut block = chain.chain.list[2].clone();
+        block.data.transactions[0].data.inputs[0].index = 10;
+        block.data.transactions[0] = Transaction::new(block.data.transactions[0].data.clone());
+        block = Block::new(BlockData::new(
+            block.data.prev_hash,
+            0,
+            block.data.transactions,
+        ));
+        chain.chain.list[2] = block;
+        chain.chain.list[3].data.prev_hash = chain.chain.list[2].hash.clone();
+        chain.chain.list[3] = Block::new(chain.chain.list[3].data.clone());
+
+        assert_eq!(chain.find_invalid_block(), Some(Height::from(2)));
+        assert!(!chain.validate_chain());
+        assert!(chain.validate_chain_structure());
+        assert_eq!(chain.validate_block_at(1), Some(true));
+        assert_eq!(chain.validate_block_at(2), Some(false));
+    }
+
+    #[test]
+    fn common_ancestor() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        for _ in 0..4 {
+            add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        }
+        let hash = |height: usize| chain.chain.list[height].hash.clone();
+
+        for (a, b, ancestor) in [(0, 0, 0), (1, 3, 1), (4, 2, 2), (4, 4, 4), (0, 4, 0)] {
+            let (height, block) = chain.find_common_ancestor(&hash(a), &hash(b)).unwrap();
+            assert_eq!(height, Height::from(ancestor));
+            assert_eq!(block.hash, hash(ancestor));
+        }
+
+        let missing = Hash::new(b"missing");
+        assert!(chain.find_common_ancestor(&hash(1), &missing).is_none());
+        assert!(chain.find_common_ancestor(&missing, &hash(1)).is_none());
+        assert!(chain.find_common_ancestor(&missing, &missing).is_none());
+    }
+
+    #[test]
+    fn utxo_pool_repair() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        let utxos = chain.utxos.clone();
+        assert_eq!(
+            chain.chain.repair_utxo_pool_from_scratch().utxos,
+            utxos.utxos
+        );
+
+        // The pool is stored in chain files
+        let bytes = chain.to_serializable().into_bytes();
+        let serializable = SerializableChain::from_bytes(&bytes).unwrap();
+        assert_eq!(serializable.utxos.as_ref().unwrap().utxos, utxos.utxos);
+        assert!(!serializable.is_utxo_pool_stale(&utxos));
+        let repaired = Chain::from_serializable_with_repair(serializable).unwrap();
+        assert_eq!(repaired.utxos.utxos, utxos.utxos);
+        assert_eq!(repaired.last_validated_height(), chain.height());
+
+        // Missing, extra and modified outputs in the stored pool
+        let mut missing = utxos.clone();
+        missing.utxos.clear();
+        let mut extra = utxos.clone();
+        extra.utxos.insert(
+            (Hash::new(b"extra"), 0),
+            Output::new(100, key_1.public_key()),
+        );
+        let mut modified = utxos.clone();
+        modified
+            .utxos
+            .values_mut()
+            .for_each(|output| output.value += 1);
+        for stored in [missing, extra, modified] {
+            let mut serializable = chain.to_serializable();
+            serializable.utxos = Some(stored);
+            assert!(serializable.is_utxo_pool_stale(&utxos));
+            let repaired = Chain::from_serializable_with_repair(serializable).unwrap();
+            assert_eq!(repaired.utxos.utxos, utxos.utxos);
+            assert!(!repaired.has_orphan_utxos());
+        }
+
+        // Chains saved without a pool are loaded as well
+        let mut serializable = chain.to_serializable();
+        serializable.utxos = None;
+        assert!(!serializable.is_utxo_pool_stale(&utxos));
+        let repaired = Chain::from_serializable_with_repair(serializable).unwrap();
+        assert_eq!(repaired.utxos.utxos, utxos.utxos);
+
+        // Invalid blocks after the validated height are still detected
+        let mut serializable = chain.to_serializable();
+        serializable.last_validated_height = Height::from(1);
+        serializable.chain.list[1].data.transactions[0].data.outputs[0].value += 1;
+        assert_eq!(
+            Chain::from_serializable_with_repair(serializable).unwrap_err(),
+            ChainOpError::InvalidChain
+        );
+    }
+
+    #[test]
+    fn pool_inputs_as_utxos() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+        let pool = UtxoPool::new(&chain.chain);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(7000, key_2.public_key())]).unwrap();
+
+        let utxo = || Some(Utxo::new(utxos[0].hash.clone(), 0, 10000));
+        assert_eq!(pool.inputs_as_utxos(&tx), vec![utxo()]);
+        assert_eq!(pool.total_input_value(&tx), Some(10000));
+        assert!(UtxoPool::default().inputs_as_utxos(&tx)[0].is_none());
+        assert!(UtxoPool::default().total_input_value(&tx).is_none());
+
+        let mut data = tx.data.clone();
+        data.inputs.push(Input {
+            hash: Hash::new(b"test"),
+            index: 0,
+            signature: key_1.sign(b"test"),
+            sequence: Input::SEQUENCE_FINAL,
+            reveal: None,
+        });
+        let tx = Transaction::new(data);
+        assert_eq!(pool.inputs_as_utxos(&tx), vec![utxo(), None]);
+        assert!(pool.total_input_value(&tx).is_none());
+    }
+
+    #[test]
+    fn descendants() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        for _ in 0..4 {
+            add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        }
+        let hash = |height: usize| chain.chain.list[height].hash.clone();
+
+        assert!(chain.is_descendant(&hash(0), &hash(4)));
+        assert!(chain.is_descendant(&hash(2), &hash(3)));
+        assert!(!chain.is_descendant(&hash(3), &hash(2)));
+        assert!(!chain.is_descendant(&hash(2), &hash(2)));
+
+        let other = Chain::new(&key_2.public_key());
+        let unrelated = other.chain.list[0].hash.clone();
+        assert!(!chain.is_descendant(&unrelated, &hash(4)));
+        assert!(!chain.is_descendant(&hash(0), &unrelated));
+
+        assert_eq!(chain.ancestor_at_depth(&hash(4), 0), Some(&hash(4)));
+        assert_eq!(chain.ancestor_at_depth(&hash(4), 3), Some(&hash(1)));
+        assert_eq!(chain.ancestor_at_depth(&hash(4), 4), Some(&hash(0)));
+        assert_eq!(chain.ancestor_at_depth(&hash(4), 5), None);
+        assert_eq!(chain.ancestor_at_depth(&unrelated, 0), None);
+    }
+
+    #[test]
+    fn peer_sync() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut node_1 = Chain::new(&key_1.public_key());
+        let mut node_2 = node_1.clone();
+
+        // Already synced
+        assert_eq!(
+            PeerSync::new(&mut node_2, &node_1).sync_from_remote(),
+            Ok(0)
+        );
+
+        // Propagation of new blocks
+        for _ in 0..3 {
+            add_transfer_block(&mut node_1, &key_1, &key_2.public_key());
+        }
+        assert_eq!(node_1.find_fork_point(&node_2), Some(Height::from(1)));
+        assert_eq!(
+            PeerSync::sync_bidirectional(&mut node_1, &mut node_2),
+            Ok(3)
+        );
+        assert_eq!(node_2.height(), 4);
+        assert_eq!(node_2.get_last_block().hash, node_1.get_last_block().hash);
+        assert_eq!(
+            node_2.get_balance(&key_2.public_key()).unwrap(),
+            node_1.get_balance(&key_2.public_key()).unwrap()
+        );
+        assert_eq!(
+            PeerSync::sync_bidirectional(&mut node_2, &mut node_1),
+            Ok(0)
+        );
+
+        // Diverged chains, the longest one wins
+        add_transfer_block(&mut node_1, &key_1, &key_2.public_key());
+        add_transfer_block(&mut node_2, &key_1, &key_1.public_key());
+        add_transfer_block(&mut node_2, &key_1, &key_1.public_key());
+        assert_eq!(node_1.find_fork_point(&node_2), Some(Height::from(4)));
+        assert_eq!(
+            PeerSync::new(&mut node_2, &node_1).sync_from_remote(),
+            Ok(0)
+        );
+        assert_eq!(
+            PeerSync::new(&mut node_1, &node_2).sync_from_remote(),
+            Ok(2)
+        );
+        assert_eq!(node_1.height(), 6);
+        assert_eq!(node_1.get_last_block().hash, node_2.get_last_block().hash);
+        assert_eq!(node_1.get_balance(&key_2.public_key()).unwrap(), 300);
+        assert!(node_1.validate_chain());
+
+        // Incompatible genesis blocks
+        let mut node_3 = Chain::new(&key_2.public_key());
+        assert!(node_3.find_fork_point(&node_1).is_none());
+        assert_eq!(
+            PeerSync::new(&mut node_3, &node_1).sync_from_remote(),
+            Err(ChainOpError::InvalidChain)
+        );
+        assert_eq!(node_3.height(), 1);
+    }
+
+    #[test]
+    fn orphan_utxos() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        assert!(chain.get_orphan_utxos().is_empty());
+        assert!(!chain.has_orphan_utxos());
+        chain.assert_invariants();
+
+        let orphan = Hash::new(b"orphan");
+        chain
+            .utxos
+            .utxos
+            .insert((orphan.clone(), 1), Output::new(10, key_2.public_key()));
+        assert!(chain.has_orphan_utxos());
+        assert_eq!(chain.get_orphan_utxos(), vec![Utxo::new(orphan, 1, 10)]);
+        assert!(std::panic::catch_unwind(|| chain.assert_invariants()).is_err());
+    }
+
+    #[test]
+    fn validation_report() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let valid_tx = new_tx(&key_1, &utxos, vec![Output::new(100, key_2.public_key())]).unwrap();
+        let stolen_tx = new_tx(&key_2, &utxos, vec![Output::new(100, key_2.public_key())]).unwrap();
+
+        let report = chain
+            .add_block_with_validation_report(new_block(
+                &chain,
+                0,
+                vec![valid_tx.clone(), stolen_tx.clone()],
+            ))
+            .unwrap_err();
+        assert_eq!(report.failed, Some("transactions are valid".to_string()));
+        assert_eq!(report.details.len(), 1);
+        assert_eq!(
+            report.details.get(&stolen_tx.hash),
+            Some(&"invalid input signature".to_string())
+        );
+        assert!(report.passed.contains(&"top hash is valid".to_string()));
+        assert!(!report.passed.contains(&"no double spending".to_string()));
+
+        let mut block = new_block(&chain, 0, vec![valid_tx.clone()]);
+        block.data.prev_hash = Hash::new(b"test");
+        let report = chain.add_block_with_validation_report(block).unwrap_err();
+        assert_eq!(report.failed, Some("block hash is valid".to_string()));
+        assert!(!report
+            .passed
+            .contains(&"block points to the last block".to_string()));
+        assert!(report.details.is_empty());
+
+        assert_eq!(
+            chain.add_block_with_validation_report(new_block(&chain, 0, vec![valid_tx])),
+            Ok(Height::from(1))
+        );
+    }
+
+    #[test]
+    fn p2sh() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+
+        let script = Script::p2ms(2, &[key_1.public_key(), key_2.public_key()]);
+        let tx = new_tx(
+            &key_1,
+            &chain.find_utxos_for_key(&key_1.public_key()),
+            vec![Output::with_script(script.clone(), 100)],
+        )
+        .unwrap();
+        let address = tx.data.outputs[0].pubkey.clone();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+
+        let utxos = chain.find_utxos_for_key(&address);
+        assert_eq!(utxos.len(), 1);
+        let spend = |input: Input| {
+            Transaction::new(TransactionData::new(
+                vec![input],
+                vec![Output::new(100, key_2.public_key())],
+            ))
+        };
+
+        // Not enough signatures
+        let mut input = Input::with_script_reveal(&utxos[0], script.clone(), &key_1);
+        assert!(!chain.validate_new_tx(&spend(input.clone())));
+
+        // Missing or different script
+        assert!(!chain.validate_new_tx(&spend(Input {
+            reveal: None,
+            ..input.clone()
+        })));
+        let other = Script::p2ms(1, &[key_1.public_key(), key_2.public_key()]);
+        let other_input = Input::with_script_reveal(&utxos[0], other, &key_1);
+        assert!(!chain.validate_new_tx(&spend(other_input)));
+
+        assert!(input.add_script_signature(&key_2));
+        let tx = spend(input);
+        assert!(chain.validate_new_tx(&tx));
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        assert_eq!(chain.get_balance(&address).unwrap(), 0);
+        assert_eq!(chain.get_balance(&key_2.public_key()).unwrap(), 100);
+    }
+
+    #[test]
+    fn utxo_count() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        assert_eq!(chain.utxo_count(), 1);
+        assert_eq!(chain.utxos.total_utxo_count_for_key(&key_1.public_key()), 1);
+        assert_eq!(chain.utxos.total_utxo_count_for_key(&key_2.public_key()), 0);
+
+        // One output to the recipient and one change output
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        assert_eq!(chain.utxo_count(), 3);
+        assert_eq!(chain.utxos.total_utxo_count_for_key(&key_1.public_key()), 1);
+        assert_eq!(chain.utxos.total_utxo_count_for_key(&key_2.public_key()), 2);
+        assert_eq!(
+            chain.utxos.total_utxo_count_for_key(&key_2.public_key()),
+            chain.find_utxos_for_key(&key_2.public_key()).len()
+        );
+    }
+
+    #[test]
+    fn addresses() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let key_3 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        assert_eq!(chain.address_count(), 1);
+        assert_eq!(chain.active_address_count(), 1);
+
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        assert_eq!(
+            chain.get_all_addresses(),
+            HashSet::from([key_1.public_key(), key_2.public_key()])
+        );
+        assert_eq!(chain.active_address_count(), 2);
+
+        // Move all the coins of the second key to the third one
+        let utxos = chain.find_utxos_for_key(&key_2.public_key());
+        let tx = new_tx(&key_2, &utxos, vec![Output::new(100, key_3.public_key())]).unwrap();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        assert_eq!(chain.address_count(), 3);
+        assert_eq!(
+            chain.get_active_addresses(),
+            HashSet::from([key_1.public_key(), key_3.public_key()])
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let stats = chain.stats();
+        assert_eq!(stats.total_supply, chain.rules.base_coins);
+        assert_eq!(stats.height, Height::from(1));
+        assert_eq!(stats.total_blocks, 1);
+        assert_eq!(stats.total_txs, 1);
+        assert_eq!(stats.total_fees_collected, 0);
+        assert_eq!(stats.unique_addresses, 1);
+
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(50, key_2.public_key())],
+            30,
+        )
+        .unwrap();
+        let coinbase = new_coinbase_tx(&key_1.public_key(), chain.rules.base_coins + 30, 1);
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![tx, coinbase]))
+            .is_ok());
+
+        let stats = chain.stats();
+        assert_eq!(stats.height, Height::from(3));
+        assert_eq!(stats.total_blocks, 3);
+        assert_eq!(stats.total_txs, 4);
+        assert_eq!(stats.total_fees_collected, 30);
+        assert_eq!(stats.total_supply, 2 * chain.rules.base_coins);
+        assert_eq!(stats.unique_addresses, 2);
+    }
+
+    #[test]
+    fn total_supply() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let max_supply = |chain: &Chain| chain.rules.base_coins * u64::from(chain.height());
+        assert_eq!(chain.total_supply(), chain.rules.base_coins);
+        assert_eq!(chain.utxo_pool().total_utxo_count(), 1);
+
+        for i in 0..4 {
+            if i % 2 == 0 {
+                add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+            } else {
+                let coinbase = new_coinbase_tx(
+                    &key_2.public_key(),
+                    chain.rules.base_coins,
+                    u64::from(chain.height()) - 1,
+                );
+                let utxos = chain.find_utxos_for_key(&key_1.public_key());
+                let tx = new_tx(&key_1, &utxos, vec![Output::new(10, key_2.public_key())]).unwrap();
+                assert!(chain
+                    .add_block(new_block(&chain, 0, vec![tx, coinbase]))
+                    .is_ok());
+            }
+            assert!(chain.total_supply() <= max_supply(&chain));
+        }
+        assert_eq!(chain.total_supply(), 3 * chain.rules.base_coins);
+        assert_eq!(chain.utxo_pool().total_utxo_count(), chain.utxo_count());
+    }
+
+    #[test]
+    fn iter_transactions() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        for _ in 0..3 {
+            add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        }
+        let coinbase = new_coinbase_tx(&key_2.public_key(), 100, 3);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(10, key_2.public_key())]).unwrap();
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![tx, coinbase]))
+            .is_ok());
+
+        let expected: usize = chain
+            .chain
+            .list
+            .iter()
+            .map(|block| block.transactions().len())
+            .sum();
+        let txs: Vec<(Height, &Transaction)> = chain.iter_transactions().collect();
+        assert_eq!(txs.len(), expected);
+        assert_eq!(txs.len(), 6);
+        assert_eq!(txs[0].0, Height::from(0));
+        assert_eq!(txs[5].0, Height::from(4));
+        assert!(txs.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(
+            txs[5].1,
+            chain.get_last_block().transactions().last().unwrap()
+        );
+    }
+
+    #[test]
+    fn find_tx() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let genesis = chain.get_block(0).unwrap().clone();
+        let (height, block, tx) = chain.find_tx(&genesis.transactions()[0].hash).unwrap();
+        assert_eq!(height, Height::from(0));
+        assert_eq!(block.hash, genesis.hash);
+        assert_eq!(*tx, genesis.transactions()[0]);
+
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        let last = chain.get_last_block().clone();
+        let (height, block, tx) = chain.find_tx(&last.transactions()[0].hash).unwrap();
+        assert_eq!(height, Height::from(1));
+        assert_eq!(*block, last);
+        assert_eq!(tx.hash, last.transactions()[0].hash);
+
+        assert!(chain.find_tx(&Hash::new(b"missing")).is_none());
+    }
+
+    #[test]
+    fn block_range() {
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        for _ in 0..4 {
+            add_transfer_block(&mut chain, &key, &key.public_key());
+        }
+        let hashes = |blocks: Vec<&Block>| -> Vec<Hash> {
+            blocks.iter().map(|block| block.hash.clone()).collect()
+        };
+        let all = hashes(chain.chain.list.iter().collect());
+
+        assert_eq!(hashes(chain.get_block_range(Height::from(0), 2)), all[..2]);
+        assert_eq!(hashes(chain.get_block_range(Height::from(1), 3)), all[1..4]);
+        assert_eq!(hashes(chain.get_block_range(Height::from(0), 10)), all);
+        assert_eq!(hashes(chain.get_block_range(Height::from(3), 10)), all[3..]);
+        assert_eq!(hashes(chain.get_block_range(Height::from(4), 1)), all[4..]);
+        assert!(chain.get_block_range(Height::from(5), 1).is_empty());
+        assert!(chain.get_block_range(Height::from(2), 0).is_empty());
+        assert_eq!(chain.get_block_range(Height::from(1), usize::MAX).len(), 4);
+
+        let slice = chain.get_blocks_in_range(1, 3).unwrap();
+        assert_eq!(hashes(slice.iter().collect()), all[1..4]);
+        assert_eq!(chain.get_blocks_in_range(0, 4).unwrap().len(), 5);
+        assert_eq!(chain.get_blocks_in_range(4, 4).unwrap().len(), 1);
+        assert!(chain.get_blocks_in_range(3, 5).is_none());
+        assert!(chain.get_blocks_in_range(5, 5).is_none());
+        assert!(chain.get_blocks_in_range(3, 2).is_none());
+
+        assert_eq!(hashes(chain.latest_blocks(2)), all[3..]);
+        assert_eq!(hashes(chain.latest_blocks(1)), all[4..]);
+        assert_eq!(hashes(chain.latest_blocks(10)), all);
+        assert!(chain.latest_blocks(0).is_empty());
+    }
+
+    #[test]
+    fn coinbase_maturity() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let key_3 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.block_maturity = 2;
+        let genesis_hash = chain.get_block(0).unwrap().data.transactions[0]
+            .hash
+            .clone();
+        let spend = |chain: &Chain, key: &KeyPair| {
+            new_tx(
+                key,
+                &chain.find_utxos_for_key(&key.public_key()),
+                vec![Output::new(10, key_2.public_key())],
+            )
+            .unwrap()
+        };
+
+        // The genesis coinbase can't be spent in block 1
+        assert!(!chain.is_coinbase_mature(&genesis_hash));
+        let tx = spend(&chain, &key_1);
+        assert!(!chain.validate_new_tx(&tx));
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_err());
+
+        chain.rules.block_maturity = 0;
+        let coinbase = new_coinbase_tx(&key_3.public_key(), 50, 0);
+        let block = new_block(&chain, 0, vec![spend(&chain, &key_1), coinbase.clone()]);
+        assert!(chain.add_block(block).is_ok());
+        chain.rules.block_maturity = 2;
+        assert!(chain.is_coinbase_mature(&genesis_hash));
+        assert!(!chain.is_coinbase_mature(&coinbase.hash));
+
+        // The coinbase of block 1 can't be spent in block 2
+        let tx = spend(&chain, &key_3);
+        assert!(!chain.validate_new_tx(&tx));
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![tx.clone()]))
+            .is_err());
+
+        // Blocks already in the chain are validated at their height
+        let mut immature = chain.clone();
+        immature.rules.block_maturity = 0;
+        assert!(immature
+            .add_block(new_block(&immature, 0, vec![tx]))
+            .is_ok());
+        immature.rules.block_maturity = 2;
+        assert_eq!(immature.validate_block_at(2), Some(false));
+
+        // Regular transactions are not affected
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        assert!(chain.is_coinbase_mature(&coinbase.hash));
+        let tx = spend(&chain, &key_3);
+        assert!(chain.validate_new_tx(&tx));
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        assert_eq!(chain.validate_block_at(3), Some(true));
+        assert!(!chain.is_coinbase_mature(&Hash::new(b"nothing")));
+    }
+
+    #[test]
+    fn incremental_validation() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        assert_eq!(chain.last_validated_height(), Height::from(1));
+        for _ in 0..3 {
+            add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        }
+        assert_eq!(chain.last_validated_height(), Height::from(4));
+
+        // The validated height is stored in chain files
+        let bytes = chain.to_serializable().into_bytes();
+        let loaded =
+            Chain::from_serializable_unchecked(SerializableChain::from_bytes(&bytes).unwrap());
+        assert_eq!(loaded.last_validated_height(), Height::from(4));
+
+        let mut serializable = chain.to_serializable();
+        serializable.last_validated_height = Height::from(0);
+        let mut loaded = Chain::from_serializable_unchecked(serializable.clone());
+        assert_eq!(loaded.last_validated_height(), Height::from(0));
+        assert!(loaded.validate_chain_incrementally(Height::from(2)));
+        assert_eq!(loaded.last_validated_height(), Height::from(4));
+        assert!(loaded.validate_chain_incrementally(Height::from(10)));
+
+        // A stored height above the chain height is capped
+        serializable.last_validated_height = Height::from(10);
+        let loaded = Chain::from_serializable_unchecked(serializable.clone());
+        assert_eq!(loaded.last_validated_height(), Height::from(4));
+
+        // Blocks added to a chain that was not validated don't count as validated
+        serializable.last_validated_height = Height::from(0);
+        let mut unchecked = Chain::from_serializable_unchecked(serializable);
+        add_transfer_block(&mut unchecked, &key_1, &key_2.public_key());
+        assert_eq!(unchecked.last_validated_height(), Height::from(0));
+
+        // Truncating the chain lowers the validated height, re-added blocks are validated
+        let mut truncated = chain.clone();
+        truncated.truncate(Height::from(2));
+        assert_eq!(truncated.last_validated_height(), Height::from(2));
+        add_transfer_block(&mut truncated, &key_1, &key_2.public_key());
+        assert_eq!(truncated.last_validated_height(), Height::from(3));
+
+        // Same invalid block as in invalid_block, not detected when trusted
+        let mut block = chain.chain.list[2].clone();
+        block.data.transactions[0].data.inputs[0].index = 10;
+        block.data.transactions[0] = Transaction::new(block.data.transactions[0].data.clone());
+        block = Block::new(BlockData::new(
+            block.data.prev_hash,
+            0,
+            block.data.transactions,
+        ));
+        chain.chain.list[2] = block;
+        chain.chain.list[3].data.prev_hash = chain.chain.list[2].hash.clone();
+        chain.chain.list[3] = Block::new(chain.chain.list[3].data.clone());
+
+        assert!(chain.validate_chain_incrementally(Height::from(4)));
+        assert!(!chain.validate_chain_incrementally(Height::from(3)));
+        assert_eq!(chain.last_validated_height(), Height::from(3));
+        assert!(!chain.validate_chain_incrementally(Height::from(1)));
+        assert_eq!(chain.last_validated_height(), Height::from(2));
+        assert!(!chain.validate_chain_incrementally(Height::from(0)));
+    }
+
+    #[test]
+    fn sync_status() {
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        add_transfer_block(&mut chain, &key, &key.public_key());
+
+        assert_eq!(chain.sync_progress(Height::from(0)), 1.0);
+        assert_eq!(chain.sync_progress(Height::from(2)), 1.0);
+        assert_eq!(chain.sync_progress(Height::from(1)), 1.0);
+        assert_eq!(chain.sync_progress(Height::from(8)), 0.25);
+
+        assert!(chain.is_synced(Height::from(2)));
+        assert!(!chain.is_synced(Height::from(3)));
+
+        assert_eq!(
+            chain.estimate_sync_time(Height::from(2), 10.0),
+            Duration::ZERO
+        );
+        assert_eq!(
+            chain.estimate_sync_time(Height::from(12), 2.0),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            chain.estimate_sync_time(Height::from(12), 0.0),
+            Duration::MAX
+        );
+        assert_eq!(
+            chain.estimate_sync_time(Height::from(12), f64::NAN),
+            Duration::MAX
+        );
+    }
+
+    #[test]
+    fn gc_spent_data() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
 
-        chain.chain.list[0].data.transactions[0].data.outputs[0].value -= 100;
-        assert!(chain.validate_genesis());
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        let tx_2 = new_tx(
+            &key_2,
+            &chain.find_utxos_for_key(&key_2.public_key()),
+            vec![Output::new(100, key_1.public_key())],
+        )
+        .unwrap();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx_2])).is_ok());
 
-        chain.chain.list[0].data.transactions[0].data.outputs[0].value += 101;
-        assert!(!chain.validate_genesis());
+        // Spend both the change of the first transaction and the output of the second
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx_3 = new_tx(
+            &key_1,
+            &utxos,
+            vec![Output::new(
+                utxos.iter().map(|utxo| utxo.value).sum(),
+                key_2.public_key(),
+            )],
+        )
+        .unwrap();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx_3])).is_ok());
+        let utxos = chain.find_all_utxos().len();
+
+        assert_eq!(chain.gc_spent_data(), 2);
+        assert_eq!(chain.gc_spent_data(), 0);
+        assert!(chain.chain.list[1].transactions()[0].is_pruned());
+        assert!(chain.chain.list[2].transactions()[0].is_pruned());
+        assert!(!chain.chain.list[3].transactions()[0].is_pruned());
+        assert!(!chain.chain.list[0].transactions()[0].is_pruned());
+
+        assert!(chain.validate_chain_structure());
+        assert!(chain
+            .chain
+            .iter()
+            .all(|block| block.is_hash_valid() && block.is_top_hash_valid()));
+        assert_eq!(UtxoPool::new(&chain.chain).utxos.len(), utxos);
+
+        // Blocks affected by pruning are trusted, since this node validated them
+        assert!(chain.validate_chain());
+        assert_eq!(chain.find_invalid_block(), None);
+        assert_eq!(chain.validate_block_at(1), Some(true));
+        assert_eq!(chain.validate_block_at(3), Some(true));
+
+        // Also after reloading the chain, since the validated height is stored
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chain.bin");
+        chain.save_atomic(&path).unwrap();
+        let loaded =
+            Chain::from_serializable(SerializableChain::from_file(&path).unwrap()).unwrap();
+        assert!(loaded.validate_chain());
+        assert_eq!(loaded.validate_block_at(3), Some(true));
+
+        // But not in blocks that were never validated
+        let mut serializable = chain.to_serializable();
+        serializable.last_validated_height = Height::from(0);
+        let loaded = Chain::from_serializable_unchecked(serializable);
+        assert_eq!(loaded.validate_block_at(1), Some(false));
+        assert_eq!(loaded.validate_block_at(3), Some(false));
+        assert!(!loaded.validate_chain());
+
+        let pruned = chain.chain.list[1].transactions()[0].clone();
+        assert!(!chain.validate_new_tx(&pruned));
+        assert_eq!(
+            chain.add_block(new_block(&chain, 0, vec![pruned])),
+            Err(ChainOpError::InvalidBlock)
+        );
     }
 
     #[test]
-    fn find_utxos() {
-        let key = KeyPair::new();
-        let chain = Chain::new(&key.public_key());
+    fn transaction_history() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
 
-        let utxos = chain.find_all_utxos();
-        assert_eq!(utxos.len(), 1);
-        assert_eq!(utxos[0].value, chain.rules.base_coins);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx_1 = new_tx(&key_1, &utxos, vec![Output::new(3000, key_2.public_key())]).unwrap();
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![tx_1.clone()]))
+            .is_ok());
+
+        let utxos = chain.find_utxos_for_key(&key_2.public_key());
+        let tx_2 = new_tx(&key_2, &utxos, vec![Output::new(1000, key_1.public_key())]).unwrap();
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![tx_2.clone()]))
+            .is_ok());
+
+        let genesis_tx = &chain.get_block(0).unwrap().transactions()[0];
+        assert_eq!(
+            chain.transaction_history_for_key(&key_1.public_key()),
+            vec![
+                TxHistoryEntry {
+                    height: Height::from(0),
+                    tx_hash: genesis_tx.hash.clone(),
+                    credited: 10000,
+                    debited: 0,
+                    balance_after: 10000,
+                },
+                TxHistoryEntry {
+                    height: Height::from(1),
+                    tx_hash: tx_1.hash.clone(),
+                    credited: 7000,
+                    debited: 10000,
+                    balance_after: 7000,
+                },
+                TxHistoryEntry {
+                    height: Height::from(2),
+                    tx_hash: tx_2.hash.clone(),
+                    credited: 1000,
+                    debited: 0,
+                    balance_after: 8000,
+                },
+            ]
+        );
 
-        let utxos = chain.find_utxos_for_key(&key.public_key());
-        assert_eq!(utxos.len(), 1);
-        assert_eq!(utxos[0].value, chain.rules.base_coins);
+        assert_eq!(
+            chain.transaction_history_for_key(&key_2.public_key()),
+            vec![
+                TxHistoryEntry {
+                    height: Height::from(1),
+                    tx_hash: tx_1.hash,
+                    credited: 3000,
+                    debited: 0,
+                    balance_after: 3000,
+                },
+                TxHistoryEntry {
+                    height: Height::from(2),
+                    tx_hash: tx_2.hash,
+                    credited: 2000,
+                    debited: 3000,
+                    balance_after: 2000,
+                },
+            ]
+        );
 
-        let utxos = chain.find_utxos_for_key(&KeyPair::new().public_key());
-        assert_eq!(utxos.len(), 0);
+        assert!(chain
+            .transaction_history_for_key(&KeyPair::new().public_key())
+            .is_empty());
     }
 
     #[test]
@@ -447,11 +3230,10 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(5000, key.public_key())],
         ));
 
         assert!(chain.utxos.is_unspent(&tx));
@@ -475,26 +3257,11 @@ mod tests {
         let chain = Chain::new(&key_1.public_key());
         let utxos = chain.find_utxos_for_key(&key_1.public_key());
 
-        let tx = new_tx(
-            &key_1,
-            &utxos,
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
-        );
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]);
 
         assert!(chain.verify_tx_signatures(&tx.unwrap()));
 
-        let tx = new_tx(
-            &key_2,
-            &utxos,
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
-        )
-        .unwrap();
+        let tx = new_tx(&key_2, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
 
         assert!(!chain.verify_tx_signatures(&tx));
     }
@@ -511,20 +3278,16 @@ mod tests {
                 hash: coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         assert!(chain.validate_new_tx(&tx));
 
         let tx = Transaction::new(TransactionData::new(
             vec![],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         assert!(!chain.validate_new_tx(&tx));
 
@@ -533,6 +3296,8 @@ mod tests {
                 hash: coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
             vec![],
         ));
@@ -543,11 +3308,10 @@ mod tests {
                 hash: coinbase.hash.clone(),
                 index: 0,
                 signature: key_2.sign(coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         assert!(!chain.validate_new_tx(&tx));
 
@@ -556,11 +3320,10 @@ mod tests {
                 hash: coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 0,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(0, key_2.public_key())],
         ));
         assert!(!chain.validate_new_tx(&tx));
 
@@ -569,15 +3332,195 @@ mod tests {
                 hash: coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: chain.rules.base_coins + 1,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(chain.rules.base_coins + 1, key_2.public_key())],
         ));
         assert!(!chain.validate_new_tx(&tx));
     }
 
+    #[test]
+    fn validate_normalized_tx() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.require_normalized_txs = true;
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(9000, key_2.public_key())]).unwrap();
+        assert!(!tx.is_normalized());
+        assert!(!chain.validate_new_tx(&tx));
+
+        let tx = tx.normalize();
+        assert!(chain.validate_new_tx(&tx));
+
+        chain.rules.require_normalized_txs = false;
+        assert!(chain.validate_new_tx(&tx));
+    }
+
+    #[test]
+    fn locktime() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(
+            &key_1,
+            &utxos,
+            vec![Output {
+                locktime: Some(Height::from(4)),
+                ..Output::new(1000, key_2.public_key())
+            }],
+        )
+        .unwrap();
+        let locked_hash = tx.hash.clone();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+
+        let spend = new_tx(
+            &key_2,
+            &[Utxo::new(locked_hash, 0, 1000)],
+            vec![Output::new(1000, key_1.public_key())],
+        )
+        .unwrap();
+        assert_eq!(
+            chain.check_locktimes(&spend, chain.utxo_pool()),
+            Err(ChainOpError::LocktimeNotReached)
+        );
+        assert!(!chain.validate_new_tx(&spend));
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![spend.clone()]))
+            .is_err());
+
+        // A block spending the output too early stays invalid once the tip is past the locktime
+        let mut early = chain.clone();
+        early
+            .chain
+            .append(new_block(&early, 0, vec![spend.clone()]))
+            .unwrap();
+        let mut early = Chain::from_serializable_unchecked(early.to_serializable());
+        while early.height() < 5 {
+            add_transfer_block(&mut early, &key_1, &key_2.public_key());
+        }
+        assert_eq!(early.validate_block_at(2), Some(false));
+        assert_eq!(early.find_invalid_block(), Some(Height::from(2)));
+
+        while chain.height() < 4 {
+            add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        }
+        assert_eq!(chain.check_locktimes(&spend, chain.utxo_pool()), Ok(()));
+        assert!(chain.validate_new_tx(&spend));
+        assert!(chain.add_block(new_block(&chain, 0, vec![spend])).is_ok());
+    }
+
+    #[test]
+    fn data_output() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.dust_threshold = Some(100);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let payment = Output::new(1000, key_2.public_key());
+        let document_hash = Hash::new(b"document").digest().to_vec();
+
+        let tx = new_tx(
+            &key_1,
+            &utxos,
+            vec![payment.clone(), Output::with_data(document_hash)],
+        )
+        .unwrap();
+        assert!(chain.validate_new_tx(&tx));
+
+        let mut with_value = Output::with_data(vec![0; 32]);
+        with_value.value = 1;
+        let invalid = new_tx(&key_1, &utxos, vec![payment.clone(), with_value]).unwrap();
+        assert!(!chain.validate_new_tx(&invalid));
+
+        let too_long = Output::with_data(vec![0; MAX_OUTPUT_DATA + 1]);
+        let invalid = new_tx(&key_1, &utxos, vec![payment, too_long]).unwrap();
+        assert!(!chain.validate_new_tx(&invalid));
+
+        let tx_hash = tx.hash.clone();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        assert!(chain.utxo_pool().utxos.contains_key(&(tx_hash.clone(), 0)));
+        assert!(!chain.utxo_pool().utxos.contains_key(&(tx_hash.clone(), 1)));
+        assert!(chain.utxo_pool().utxos.contains_key(&(tx_hash, 2)));
+        assert_eq!(chain.find_invalid_block(), None);
+    }
+
+    #[test]
+    fn validate_min_fee_tx() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.min_fee = Some(10);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let spend = |fee: Value| {
+            new_tx_with_fee(
+                &key_1,
+                &utxos,
+                vec![Output::new(5000, key_2.public_key())],
+                fee,
+            )
+            .unwrap()
+        };
+
+        let low_fee = spend(5);
+        assert_eq!(chain.check_min_fee(&low_fee), Err(ChainOpError::FeeTooLow));
+        assert!(!chain.validate_new_tx(&low_fee));
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(!miner.add_tx(&chain, low_fee.clone()));
+        assert_eq!(
+            chain.add_block(new_block(&chain, 0, vec![low_fee])),
+            Err(ChainOpError::InvalidBlock)
+        );
+
+        let tx = spend(10);
+        assert!(chain.check_min_fee(&tx).is_ok());
+        assert!(chain.validate_new_tx(&tx));
+        assert!(miner.add_tx(&chain, tx.clone()));
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+    }
+
+    #[test]
+    fn validate_dust_tx() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.dust_threshold = Some(100);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+
+        let dust = new_tx(&key_1, &utxos, vec![Output::new(99, key_2.public_key())]).unwrap();
+        assert!(!chain.validate_new_tx(&dust));
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(!miner.add_tx(&chain, dust.clone()));
+
+        // Change below the threshold is left as a fee
+        let tx = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(9950, key_2.public_key())],
+            50,
+        )
+        .unwrap();
+        assert_eq!(tx.data.outputs.len(), 1);
+        assert!(chain.validate_new_tx(&tx));
+        assert!(miner.add_tx(&chain, tx));
+
+        chain.rules.dust_threshold = None;
+        assert!(chain.validate_new_tx(&dust));
+
+        assert!(chain.add_block(new_block(&chain, 0, vec![dust])).is_ok());
+        let pool = chain.utxo_pool();
+        assert_eq!(pool.get_for_key(&key_2.public_key()).len(), 1);
+        assert!(pool
+            .get_non_dust_for_key(&key_2.public_key(), 100)
+            .is_empty());
+        assert_eq!(pool.get_non_dust_for_key(&key_1.public_key(), 100).len(), 1);
+    }
+
     #[test]
     fn validate_coinbase_tx() {
         let key = KeyPair::new();
@@ -598,28 +3541,19 @@ mod tests {
 
         let tx = Transaction::new(TransactionData::new(
             vec![],
-            vec![Output {
-                value: 0,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(0, key.public_key())],
         ));
         assert!(!chain.validate_coinbase_tx(genesis.prev_hash(), genesis.transactions(), &tx));
 
         let tx = Transaction::new(TransactionData::new(
             vec![],
-            vec![Output {
-                value: chain.rules.base_coins,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(chain.rules.base_coins, key.public_key())],
         ));
         assert!(chain.validate_coinbase_tx(genesis.prev_hash(), genesis.transactions(), &tx));
 
         let tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: chain.rules.base_coins + 5000,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(chain.rules.base_coins + 5000, key.public_key())],
             0,
         ));
         let block = Block::new(BlockData::new(
@@ -631,11 +3565,10 @@ mod tests {
                         hash: coinbase.hash.clone(),
                         index: 0,
                         signature: key.sign(coinbase.hash.digest()),
+                        sequence: Input::SEQUENCE_FINAL,
+                        reveal: None,
                     }],
-                    vec![Output {
-                        value: 5000,
-                        pubkey: key.public_key(),
-                    }],
+                    vec![Output::new(5000, key.public_key())],
                 )),
                 tx.clone(),
             ],
@@ -645,10 +3578,7 @@ mod tests {
 
         let tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: chain.rules.base_coins,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(chain.rules.base_coins, key.public_key())],
             0,
         ));
 
@@ -656,10 +3586,7 @@ mod tests {
 
         let tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: chain.rules.base_coins + 5001,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(chain.rules.base_coins + 5001, key.public_key())],
             0,
         ));
 
@@ -681,17 +3608,18 @@ mod tests {
                     hash: last_coinbase.hash.clone(),
                     index: 0,
                     signature: key_1.sign(last_coinbase.hash.digest()),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 },
                 Input {
                     hash: last_coinbase.hash.clone(),
                     index: 0,
                     signature: key_1.sign(last_coinbase.hash.digest()),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 },
             ],
-            vec![Output {
-                value: 20000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(20000, key_2.public_key())],
         ));
 
         let result = chain.add_block(Block::new(BlockData::new(
@@ -720,11 +3648,10 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
 
         let block = Block {
@@ -748,18 +3675,14 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         let valid_coinbase_tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: chain.rules.base_coins,
-                pubkey: key_1.public_key(),
-            }],
+            vec![Output::new(chain.rules.base_coins, key_1.public_key())],
             0,
         ));
 
@@ -788,10 +3711,7 @@ mod tests {
             0,
             vec![Transaction::new(TransactionData::new(
                 vec![],
-                vec![Output {
-                    value: 5000,
-                    pubkey: key_2.public_key(),
-                }],
+                vec![Output::new(5000, key_2.public_key())],
             ))],
         ));
         assert!(!chain.validate_new_block(&block));
@@ -832,18 +3752,14 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         let valid_coinbase_tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: chain.rules.base_coins,
-                pubkey: key_1.public_key(),
-            }],
+            vec![Output::new(chain.rules.base_coins, key_1.public_key())],
             0,
         ));
 
@@ -857,6 +3773,206 @@ mod tests {
         assert!(chain.validate_chain());
     }
 
+    #[test]
+    fn validate_chain_checkpoints() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+
+        let mut chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
+        let block = new_block(&chain, 0, vec![tx]);
+        let valid_hash = block.hash.clone();
+        assert!(chain.add_block(block).is_ok());
+
+        // A block with a forged signature is linked to the chain bypassing validation
+        let utxos = chain.find_utxos_for_key(&key_2.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_1.public_key())]).unwrap();
+        let block = new_block(&chain, 0, vec![tx]);
+        let invalid_hash = block.hash.clone();
+        assert!(chain.chain.append(block).is_ok());
+        assert!(!chain.validate_chain());
+
+        // Trusted blocks are not fully validated
+        assert!(chain
+            .rules
+            .add_checkpoint(Height::from(2), invalid_hash)
+            .is_ok());
+        assert!(chain.validate_chain());
+
+        // Checkpoint hashes must match
+        chain.rules.checkpoints.clear();
+        assert!(chain
+            .rules
+            .add_checkpoint(Height::from(1), Hash::new(b"test"))
+            .is_ok());
+        assert!(!chain.validate_chain());
+
+        chain.rules.checkpoints.clear();
+        assert!(chain
+            .rules
+            .add_checkpoint(Height::from(1), valid_hash)
+            .is_ok());
+        assert!(!chain.validate_chain());
+
+        // Checkpoints above the current height are ignored
+        assert!(chain
+            .rules
+            .add_checkpoint(Height::from(10), Hash::new(b"test"))
+            .is_ok());
+        chain.chain.list.pop();
+        assert!(chain.validate_chain());
+    }
+
+    #[test]
+    fn coinbase_monotonicity() {
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        assert!(chain.audit_coinbase_monotonicity());
+
+        let append_coinbase = |chain: &mut Chain, timestamp: u64| {
+            let block = new_block(
+                chain,
+                0,
+                vec![new_coinbase_tx(&key.public_key(), 1, timestamp)],
+            );
+            chain.chain.append(block).unwrap();
+        };
+
+        let mut valid = chain.clone();
+        append_coinbase(&mut valid, 0);
+        append_coinbase(&mut valid, 1);
+        append_coinbase(&mut valid, 2);
+        assert!(valid.audit_coinbase_monotonicity());
+
+        // Genesis and block 1 both carry 0, the only allowed repeat
+        let coinbase_timestamp = |chain: &Chain, height: usize| {
+            chain.get_block(height).unwrap().transactions()[0]
+                .data
+                .timestamp
+        };
+        assert_eq!(coinbase_timestamp(&valid, 0).unwrap_or(0), 0);
+        assert_eq!(coinbase_timestamp(&valid, 1), Some(0));
+        let mut zero_genesis = valid.clone();
+        zero_genesis.chain.list[0].data.transactions[0]
+            .data
+            .timestamp = Some(0);
+        assert!(zero_genesis.audit_coinbase_monotonicity());
+        let mut no_repeat = chain.clone();
+        append_coinbase(&mut no_repeat, 1);
+        assert!(!no_repeat.audit_coinbase_monotonicity());
+
+        // Gap
+        let mut gap = chain.clone();
+        append_coinbase(&mut gap, 0);
+        append_coinbase(&mut gap, 2);
+        assert!(!gap.audit_coinbase_monotonicity());
+        assert!(!gap.validate_chain());
+
+        // Repeat
+        let mut repeat = chain.clone();
+        append_coinbase(&mut repeat, 0);
+        append_coinbase(&mut repeat, 0);
+        assert!(!repeat.audit_coinbase_monotonicity());
+
+        // Missing timestamp after genesis
+        let block = new_block(
+            &chain,
+            0,
+            vec![Transaction::new(TransactionData::new(
+                vec![],
+                vec![Output::new(1, key.public_key())],
+            ))],
+        );
+        chain.chain.append(block).unwrap();
+        assert!(!chain.audit_coinbase_monotonicity());
+    }
+
+    #[test]
+    fn add_block_checkpoint() {
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        assert!(chain
+            .rules
+            .add_checkpoint(Height::from(1), Hash::new(b"test"))
+            .is_ok());
+
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        let tx = new_tx(&key, &utxos, vec![Output::new(5000, key.public_key())]).unwrap();
+        let block = new_block(&chain, 0, vec![tx]);
+        assert_eq!(chain.add_block(block), Err(ChainOpError::InvalidBlock));
+    }
+
+    #[test]
+    fn validate_chain_structure() {
+        let mut block_gen = BlockGen::default();
+        let key = KeyPair::new();
+        let mut chain = Chain::new(&key.public_key());
+        assert!(chain.validate_chain_structure());
+
+        // Blocks with invalid transactions are structurally sound
+        let mut block = block_gen.next().unwrap();
+        block.data.prev_hash = chain.get_last_block().hash.clone();
+        let block = Block::new(block.data);
+        assert!(chain.chain.append(block).is_ok());
+        assert!(chain.validate_chain_structure());
+        assert!(!chain.validate_chain());
+
+        chain.chain.list[1].data.nonce += 1;
+        assert!(!chain.validate_chain_structure());
+    }
+
+    #[test]
+    fn utxo_snapshot() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        add_transfer_block(&mut chain, &key_1, &key_2.public_key());
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("utxos.bin");
+        assert!(chain.save_utxo_snapshot(&path).is_ok());
+
+        let pool = Chain::load_utxo_snapshot(&path, chain.height()).unwrap();
+        assert_eq!(pool.utxos, chain.utxo_pool().utxos);
+
+        assert!(Chain::load_utxo_snapshot(&path, Height::from(1)).is_err());
+        assert!(Chain::load_utxo_snapshot(&dir.path().join("missing"), chain.height()).is_err());
+    }
+
+    #[test]
+    fn save_atomic() {
+        let key = KeyPair::new();
+        let chain = Chain::new(&key.public_key());
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("chain.bin");
+        assert!(chain.save_atomic(&path).is_ok());
+        assert!(path.exists());
+        assert!(!path.with_extension("tmp").exists());
+
+        let saved = Chain::from_serializable(SerializableChain::from_file(&path).unwrap());
+        assert_eq!(
+            saved.unwrap().get_last_block().hash,
+            chain.get_last_block().hash
+        );
+
+        assert!(chain.save_and_verify(&path).is_ok());
+
+        // The destination can't be replaced
+        let path = dir.path().join("dir");
+        fs::create_dir(&path).unwrap();
+        assert!(chain.save_atomic(&path).is_err());
+        assert!(!path.with_extension("tmp").exists());
+
+        // The temporary file can't be created
+        let path = dir.path().join("missing").join("chain.bin");
+        assert!(chain.save_atomic(&path).is_err());
+        assert!(!path.with_extension("tmp").exists());
+
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
     #[test]
     fn add_block() {
         let key_1 = KeyPair::new();
@@ -864,7 +3980,11 @@ mod tests {
 
         let mut chain = Chain::new_with_consensus(
             &key_1.public_key(),
-            ConsensusRules::new(Target::from_leading_zeros(254), 10000, Halving::None),
+            ConsensusRulesBuilder::default()
+                .target(Target::from_leading_zeros(254))
+                .coins_per_block(10000)
+                .halving(Halving::None)
+                .build(),
         );
         let last_block = chain.chain.get_last_block();
         let last_coinbase = &last_block.data.transactions[0];
@@ -875,18 +3995,17 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         let valid_coinbase_tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: chain.rules.base_coins + 5000,
-                pubkey: key_1.public_key(),
-            }],
+            vec![Output::new(
+                chain.rules.base_coins + 5000,
+                key_1.public_key(),
+            )],
             0,
         ));
 
@@ -927,7 +4046,11 @@ mod tests {
 
         let mut chain = Chain::new_with_consensus(
             &key_1.public_key(),
-            ConsensusRules::new(Target::MAX, 10000, Halving::Inf),
+            ConsensusRulesBuilder::default()
+                .target(Target::MAX)
+                .coins_per_block(10000)
+                .halving(Halving::Inf)
+                .build(),
         );
         let last_block = chain.chain.get_last_block();
         let last_coinbase = &last_block.data.transactions[0];
@@ -938,18 +4061,14 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         let invalid_coinbase_tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: 5001,
-                pubkey: key_1.public_key(),
-            }],
+            vec![Output::new(5001, key_1.public_key())],
             0,
         ));
 
@@ -964,10 +4083,7 @@ mod tests {
 
         let valid_coinbase_tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: 5000,
-                pubkey: key_1.public_key(),
-            }],
+            vec![Output::new(5000, key_1.public_key())],
             0,
         ));
 
@@ -997,7 +4113,11 @@ mod tests {
 
         let mut chain = Chain::new_with_consensus(
             &key_1.public_key(),
-            ConsensusRules::new(Target::MAX, 10000, Halving::Height(1)),
+            ConsensusRulesBuilder::default()
+                .target(Target::MAX)
+                .coins_per_block(10000)
+                .halving(Halving::Height(1))
+                .build(),
         );
         let last_block = chain.chain.get_last_block();
         let last_coinbase = &last_block.data.transactions[0];
@@ -1008,18 +4128,14 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
         let invalid_coinbase_tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: 10001,
-                pubkey: key_1.public_key(),
-            }],
+            vec![Output::new(10001, key_1.public_key())],
             0,
         ));
 
@@ -1034,10 +4150,7 @@ mod tests {
 
         let valid_coinbase_tx = Transaction::new(TransactionData::new_with_timestamp(
             vec![],
-            vec![Output {
-                value: 10000,
-                pubkey: key_1.public_key(),
-            }],
+            vec![Output::new(10000, key_1.public_key())],
             0,
         ));
 
@@ -1102,13 +4215,13 @@ mod tests {
                     &account.0,
                     &account.1,
                     (0..tx_count)
-                        .map(|id| Output {
-                            value: if id != tx_count - 1 {
+                        .map(|id| {
+                            let value = if id != tx_count - 1 {
                                 tx_value
                             } else {
                                 tx_value + tx_rem
-                            },
-                            pubkey: accounts.choose(&mut rng).unwrap().0.public_key(),
+                            };
+                            Output::new(value, accounts.choose(&mut rng).unwrap().0.public_key())
                         })
                         .collect(),
                 );
@@ -3,10 +3,11 @@
 //! Consensus is the set of rules that drive the operation of the coin's blockchain.
 //!
 
+use crate::core::block::Block;
 use crate::core::blockchain::Height;
 use crate::core::hash::Hash;
 use crate::core::transaction::Value;
-use crate::traits::io::{ByteIO, FileIO};
+use crate::traits::io::{ByteIO, FileIO, JsonIO};
 use core::fmt::Display;
 use ethnum::U256;
 use serde::de::{self, Visitor};
@@ -27,7 +28,7 @@ impl Target {
 
     pub fn from_hash(hash: &Hash) -> Target {
         Target {
-            value: U256::from_be_bytes(hash.digest().clone()),
+            value: hash.to_u256(),
         }
     }
 
@@ -40,6 +41,93 @@ impl Target {
     pub fn leading_zeros(&self) -> u32 {
         self.value.leading_zeros()
     }
+
+    /// Encodes the target in the compact format of Bitcoin's nBits: the highest byte
+    /// is the length in bytes of the value, the lower 3 bytes are its most significant
+    /// bytes. The encoding is lossy, only the 3 most significant bytes are kept.
+    ///
+    pub fn to_bits(&self) -> u32 {
+        let mut size = (256 - self.value.leading_zeros()).div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            self.value.as_u32() << (8 * (3 - size))
+        } else {
+            (self.value >> (8 * (size - 3))).as_u32()
+        };
+        // The highest mantissa bit is the sign in Bitcoin's format
+        if mantissa & 0x00800000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        mantissa | (size << 24)
+    }
+
+    /// Decodes a target in the compact format, see [to_bits](Target::to_bits).
+    /// The sign bit is ignored, a zero mantissa decodes to a zero target whatever
+    /// the size, and values overflowing 256 bits saturate to [MAX](Target::MAX).
+    ///
+    pub fn from_bits(bits: u32) -> Target {
+        let size = bits >> 24;
+        let mantissa = bits & 0x007fffff;
+        if mantissa == 0 {
+            return Target { value: U256::ZERO };
+        }
+        if size <= 3 {
+            return Target {
+                value: U256::from(mantissa >> (8 * (3 - size))),
+            };
+        }
+        let shift = 8 * (size - 3);
+        let value = U256::from(mantissa);
+        if value.leading_zeros() < shift {
+            return Target::MAX;
+        }
+        Target {
+            value: value << shift,
+        }
+    }
+
+    /// Returns how much harder the target is to satisfy than [MAX](Target::MAX).
+    /// A zero target has infinite difficulty.
+    ///
+    pub fn difficulty(&self) -> f64 {
+        if self.value == U256::ZERO {
+            return f64::INFINITY;
+        }
+        Target::MAX.value.as_f64() / self.value.as_f64()
+    }
+
+    /// A higher target is easier to satisfy
+    ///
+    pub fn is_easier_than(&self, other: &Target) -> bool {
+        self.value > other.value
+    }
+
+    /// Computes the target for the next period by scaling the current one by the ratio
+    /// between the actual and the expected duration of the last period: faster periods
+    /// make the target harder. The new target is clamped between a quarter and four
+    /// times the current one, and never exceeds [MAX](Target::MAX).
+    ///
+    /// The target is unchanged if the expected duration is zero.
+    ///
+    pub fn next_target(current: &Target, actual_seconds: u64, target_seconds: u64) -> Target {
+        if target_seconds == 0 {
+            return current.clone();
+        }
+        // Durations above the upper bound don't change the result
+        let actual = U256::from(actual_seconds.min(target_seconds.saturating_mul(4)));
+        let expected = U256::from(target_seconds);
+        // current * actual / expected, split to avoid overflowing the product
+        let quotient = current.value / expected;
+        let remainder = current.value % expected;
+        let scaled = quotient
+            .saturating_mul(actual)
+            .saturating_add(remainder * actual / expected);
+        let min = current.value / 4;
+        let max = current.value.saturating_mul(U256::from(4_u8));
+        Target {
+            value: scaled.clamp(min, max),
+        }
+    }
 }
 
 impl Binary for Target {
@@ -49,6 +137,12 @@ impl Binary for Target {
     }
 }
 
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x} (difficulty {:.2})", self.value, self.difficulty())
+    }
+}
+
 impl Serialize for Target {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -110,21 +204,141 @@ where
 ///   half every N blocks mined.
 /// - Infinite: supply of coins is fixed and determined from the start. Coins are only generated
 ///   in the genesis block, no other block can generate coins
+/// - Custom: a list of (from height, reward) pairs sorted by height, the reward of a block is
+///   the one of the last pair starting at or below its height
 ///
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Halving {
     None,
     Height(u64),
     Inf,
+    Custom(Vec<(u64, Value)>),
 }
 
+impl Halving {
+    /// A custom schedule is valid if it's not empty, the heights are strictly increasing
+    /// and the rewards are non-increasing. The other rules are always valid, except for
+    /// height-based halving every 0 blocks.
+    ///
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Halving::Height(value) => *value > 0,
+            Halving::Custom(schedule) => {
+                !schedule.is_empty()
+                    && schedule
+                        .windows(2)
+                        .all(|pair| pair[0].0 < pair[1].0 && pair[0].1 >= pair[1].1)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Rejects [invalid](Halving::is_valid) halving rules when loading consensus rules,
+/// e.g. from a rules file or a chain file
+///
+fn deserialize_valid_halving<'de, D>(deserializer: D) -> Result<Halving, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let halving = Halving::deserialize(deserializer)?;
+    if !halving.is_valid() {
+        return Err(de::Error::custom(ConsensusRulesError::InvalidHalving));
+    }
+    Ok(halving)
+}
+
+/// Errors returned when building or modifying consensus rules
+#[derive(PartialEq, Debug)]
+pub enum ConsensusRulesError {
+    InvalidCheckpointHeight,
+    InvalidHalving,
+}
+
+impl fmt::Display for ConsensusRulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Consensus rules error: {}",
+            match self {
+                ConsensusRulesError::InvalidCheckpointHeight => "invalid checkpoint height",
+                ConsensusRulesError::InvalidHalving => "invalid halving schedule",
+            }
+        )
+    }
+}
+
+impl std::error::Error for ConsensusRulesError {}
+
 /// Struct used for storing the current consensus
 ///
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Checkpoints are known-good block hashes at fixed heights, sorted by height.
+/// Blocks up to the last checkpoint are trusted and only their hash chain is verified.
+///
+/// If a dust threshold is set, transactions with outputs below it are rejected.
+///
+/// If RBF signaling is enforced, final transactions can't be replaced in the pool, see
+/// [replace_in_pool](crate::mining::miner::Miner::replace_in_pool).
+///
+/// The outputs of a coinbase transaction can only be spent in blocks at least
+/// block maturity blocks higher than the coinbase block.
+///
+/// If a retarget interval and a target block time are set, the target is the initial one
+/// and it's adjusted every retarget interval blocks, see
+/// [target_at](crate::chain::Chain::target_at).
+///
+/// Block Unix timestamps can't be earlier than the previous block's, nor later than the
+/// current time plus the max clock drift, if set.
+///
+/// Blocks with a version lower than the min block version are rejected. Version
+/// activations raise the minimum from the given height on, see
+/// [min_block_version_at](ConsensusRules::min_block_version_at).
+///
+/// If a max block size is set, blocks whose transactions take more bytes are rejected.
+///
+/// If a min fee is set, transactions paying a lower fee are rejected.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ConsensusRules {
     pub target: Target,
     pub base_coins: Value,
+    #[serde(deserialize_with = "deserialize_valid_halving")]
     pub halving: Halving,
+    #[serde(default)]
+    pub require_normalized_txs: bool,
+    #[serde(default)]
+    pub checkpoints: Vec<(Height, Hash)>,
+    #[serde(default)]
+    pub dust_threshold: Option<Value>,
+    #[serde(default)]
+    pub enforce_rbf_signaling: bool,
+    #[serde(default)]
+    pub block_maturity: u64,
+    #[serde(default)]
+    pub retarget_interval: Option<u64>,
+    #[serde(default)]
+    pub target_block_time_secs: Option<u64>,
+    #[serde(default = "default_max_clock_drift_secs")]
+    pub max_clock_drift_secs: Option<u64>,
+    #[serde(default = "default_min_block_version")]
+    pub min_block_version: u32,
+    #[serde(default)]
+    pub version_activations: Vec<(Height, u32)>,
+    #[serde(default)]
+    pub max_block_size: Option<usize>,
+    #[serde(default)]
+    pub min_fee: Option<Value>,
+}
+
+fn default_min_block_version() -> u32 {
+    1
+}
+
+/// Default maximum number of seconds a block timestamp can be ahead of the current time
+pub const DEFAULT_MAX_CLOCK_DRIFT_SECS: u64 = 7200;
+
+fn default_max_clock_drift_secs() -> Option<u64> {
+    Some(DEFAULT_MAX_CLOCK_DRIFT_SECS)
 }
 
 impl Default for ConsensusRules {
@@ -133,25 +347,112 @@ impl Default for ConsensusRules {
             target: Target::MAX,
             base_coins: 10000,
             halving: Halving::None,
+            require_normalized_txs: false,
+            checkpoints: Vec::new(),
+            dust_threshold: None,
+            enforce_rbf_signaling: false,
+            block_maturity: 0,
+            retarget_interval: None,
+            target_block_time_secs: None,
+            max_clock_drift_secs: default_max_clock_drift_secs(),
+            min_block_version: default_min_block_version(),
+            version_activations: Vec::new(),
+            max_block_size: None,
+            min_fee: None,
         }
     }
 }
 
 impl ConsensusRules {
-    pub fn new(target: Target, base_coins: Value, halving: Halving) -> ConsensusRules {
-        ConsensusRules {
-            target,
-            base_coins,
-            halving,
+    /// Creates the rules with the default values for everything but the target, the
+    /// reward and the halving rule. Fails if the halving rule is not
+    /// [valid](Halving::is_valid)
+    ///
+    pub fn new(
+        target: Target,
+        base_coins: Value,
+        halving: Halving,
+    ) -> Result<ConsensusRules, ConsensusRulesError> {
+        if !halving.is_valid() {
+            return Err(ConsensusRulesError::InvalidHalving);
         }
+        Ok(ConsensusRulesBuilder::default()
+            .target(target)
+            .coins_per_block(base_coins)
+            .halving(halving)
+            .build())
+    }
+
+    /// Adds a checkpoint, which must be higher than every checkpoint already defined
+    ///
+    pub fn add_checkpoint(
+        &mut self,
+        height: Height,
+        hash: Hash,
+    ) -> Result<(), ConsensusRulesError> {
+        if let Some((last, _)) = self.checkpoints.last() {
+            if height <= *last {
+                return Err(ConsensusRulesError::InvalidCheckpointHeight);
+            }
+        }
+        self.checkpoints.push((height, hash));
+        Ok(())
+    }
+
+    pub fn last_checkpoint_height(&self) -> Option<Height> {
+        self.checkpoints.last().map(|(height, _)| height.clone())
+    }
+
+    /// Returns false only if a checkpoint exists at the given height and its
+    /// hash differs from the provided one
+    ///
+    pub fn validate_checkpoint(&self, height: &Height, hash: &Hash) -> bool {
+        self.checkpoints
+            .iter()
+            .all(|(cp_height, cp_hash)| cp_height != height || cp_hash == hash)
+    }
+
+    /// Requires blocks from the given height on to have at least the given version.
+    /// Activations are kept sorted by height.
+    ///
+    pub fn activate_version_at_height(&mut self, version: u32, height: Height) {
+        let index = self
+            .version_activations
+            .partition_point(|(activation, _)| *activation <= height);
+        self.version_activations.insert(index, (height, version));
+    }
+
+    /// Returns the minimum version of a block at the given height: the highest among
+    /// the min block version and the versions activated at or below that height
+    ///
+    pub fn min_block_version_at(&self, height: &Height) -> u32 {
+        self.version_activations
+            .iter()
+            .take_while(|(activation, _)| activation <= height)
+            .map(|(_, version)| *version)
+            .fold(self.min_block_version, u32::max)
+    }
+
+    /// Returns false if the transactions of the block exceed the max block size
+    ///
+    pub fn validate_block_size(&self, block: &Block) -> bool {
+        self.max_block_size
+            .is_none_or(|max| block.transactions_size_bytes() <= max)
+    }
+
+    pub fn validate_min_fee(&self, fee: Value) -> bool {
+        self.min_fee.is_none_or(|min| fee >= min)
     }
 
     pub fn validate_target(&self, hash: &Hash) -> bool {
         Target::from_hash(hash) <= self.target
     }
 
+    /// Returns the block reward at the given height. The base coins are ignored by
+    /// custom schedules, which give no reward below their first height.
+    ///
     pub fn reward(&self, height: Height) -> Value {
-        match self.halving {
+        match &self.halving {
             Halving::None => self.base_coins,
             Halving::Height(0) => panic!("Invalid halving value"),
             Halving::Height(value) => self.base_coins / ((Into::<u64>::into(height) / value) + 1),
@@ -162,12 +463,136 @@ impl ConsensusRules {
                     0
                 }
             }
+            Halving::Custom(schedule) => {
+                let height: u64 = height.into();
+                schedule
+                    .iter()
+                    .take_while(|(from, _)| *from <= height)
+                    .last()
+                    .map_or(0, |(_, reward)| *reward)
+            }
+        }
+    }
+
+    /// Iterates over the heights where the block reward changes, with the new reward,
+    /// until the reward drops to zero or stays constant forever
+    ///
+    fn reward_changes(&self) -> impl Iterator<Item = (u64, Value)> + '_ {
+        std::iter::successors(Some(0_u64), move |&height| {
+            let reward = self.reward(Height::from(height));
+            match &self.halving {
+                // Custom schedules can start with no reward
+                Halving::Custom(schedule) => schedule
+                    .iter()
+                    .map(|(from, _)| *from)
+                    .find(|from| *from > height),
+                _ if reward == 0 => None,
+                Halving::None => None,
+                // The reward of era e is base_coins / (e + 1), it changes at era base_coins / reward
+                Halving::Height(value) => (self.base_coins / reward).checked_mul(*value),
+                Halving::Inf => Some(1),
+            }
+        })
+        .map(|height| (height, self.reward(Height::from(height))))
+    }
+
+    /// Returns the heights up to max_height where the block reward changes, each one
+    /// with the total supply issued up to and including the block at that height.
+    ///
+    pub fn initial_supply_schedule(&self, max_height: Height) -> Vec<(Height, Value)> {
+        let max_height: u64 = max_height.into();
+        let mut schedule = Vec::new();
+        let mut issued: Value = 0;
+        let mut last: Option<(u64, Value)> = None;
+        for (height, reward) in self
+            .reward_changes()
+            .take_while(|(height, _)| *height <= max_height)
+        {
+            if let Some((last_height, last_reward)) = last {
+                issued = issued.saturating_add(last_reward.saturating_mul(height - last_height));
+            }
+            schedule.push((Height::from(height), issued.saturating_add(reward)));
+            last = Some((height, reward));
+        }
+        schedule
+    }
+
+    /// Returns the total supply issued once the reward drops to zero, or None if the
+    /// reward never drops to zero or the supply overflows.
+    ///
+    pub fn max_possible_supply(&self) -> Option<Value> {
+        let mut supply: Value = 0;
+        let mut last: Option<(u64, Value)> = None;
+        for (height, reward) in self.reward_changes() {
+            if let Some((last_height, last_reward)) = last {
+                supply = supply.checked_add(last_reward.checked_mul(height - last_height)?)?;
+            }
+            last = Some((height, reward));
+        }
+        match last {
+            Some((_, 0)) => Some(supply),
+            _ => None,
         }
     }
 }
 
+/// Builder for consensus rules. Fields that are not set keep the values of
+/// [ConsensusRules::default].
+///
+#[derive(Debug, Clone, Default)]
+pub struct ConsensusRulesBuilder {
+    rules: ConsensusRules,
+}
+
+impl ConsensusRulesBuilder {
+    pub fn target(mut self, target: Target) -> Self {
+        self.rules.target = target;
+        self
+    }
+
+    pub fn coins_per_block(mut self, coins: Value) -> Self {
+        self.rules.base_coins = coins;
+        self
+    }
+
+    pub fn halving(mut self, halving: Halving) -> Self {
+        self.rules.halving = halving;
+        self
+    }
+
+    pub fn dust_limit(mut self, limit: Value) -> Self {
+        self.rules.dust_threshold = Some(limit);
+        self
+    }
+
+    pub fn retarget_interval(mut self, interval: u64) -> Self {
+        self.rules.retarget_interval = Some(interval);
+        self
+    }
+
+    pub fn target_block_time(mut self, seconds: u64) -> Self {
+        self.rules.target_block_time_secs = Some(seconds);
+        self
+    }
+
+    pub fn max_block_size(mut self, bytes: usize) -> Self {
+        self.rules.max_block_size = Some(bytes);
+        self
+    }
+
+    pub fn min_fee(mut self, fee: Value) -> Self {
+        self.rules.min_fee = Some(fee);
+        self
+    }
+
+    pub fn build(self) -> ConsensusRules {
+        self.rules
+    }
+}
+
 impl ByteIO for ConsensusRules {}
 impl FileIO for ConsensusRules {}
+impl JsonIO for ConsensusRules {}
 
 #[cfg(test)]
 mod tests {
@@ -191,7 +616,11 @@ mod tests {
         ])
         .unwrap();
 
-        let cr = ConsensusRules::new(Target::from_leading_zeros(255), 10000, Halving::None);
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::from_leading_zeros(255))
+            .coins_per_block(10000)
+            .halving(Halving::None)
+            .build();
         assert!(!cr.validate_target(&hash));
 
         let hash = Hash::from_bytes(&[
@@ -200,15 +629,122 @@ mod tests {
         ])
         .unwrap();
 
-        let cr = ConsensusRules::new(Target::from_leading_zeros(198), 10000, Halving::None);
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::from_leading_zeros(198))
+            .coins_per_block(10000)
+            .halving(Halving::None)
+            .build();
         assert!(cr.validate_target(&hash));
-        let cr = ConsensusRules::new(Target::from_leading_zeros(200), 10000, Halving::None);
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::from_leading_zeros(200))
+            .coins_per_block(10000)
+            .halving(Halving::None)
+            .build();
         assert!(!cr.validate_target(&hash));
     }
 
+    #[test]
+    fn bits() {
+        // Vectors from Bitcoin's arith_uint256 tests
+        let vectors = [
+            (0x1d00ffff, U256::from(0xffff_u32) << 208),
+            (0x1b0404cb, U256::from(0x0404cb_u32) << 192),
+            (0x05009234, U256::from(0x92340000_u32)),
+            (0x04123456, U256::from(0x12345600_u32)),
+            (0x03123456, U256::from(0x123456_u32)),
+            (0x02008000, U256::from(0x80_u32)),
+            (0x01003456, U256::from(0_u32)),
+            (0x00000000, U256::from(0_u32)),
+        ];
+        for (bits, value) in vectors {
+            assert_eq!(Target::from_bits(bits).value, value);
+        }
+        assert_eq!(
+            Target {
+                value: U256::from(0x80_u32)
+            }
+            .to_bits(),
+            0x02008000
+        );
+        assert_eq!(
+            Target {
+                value: U256::from(0x12345600_u32)
+            }
+            .to_bits(),
+            0x04123456
+        );
+        assert_eq!(Target { value: U256::ZERO }.to_bits(), 0);
+
+        for zeros in [0, 1, 8, 20, 128, 255] {
+            let target = Target::from_leading_zeros(zeros);
+            let decoded = Target::from_bits(target.to_bits());
+            // Only the 3 most significant bytes are kept
+            assert!(decoded <= target);
+            assert_eq!(decoded.leading_zeros(), target.leading_zeros());
+            assert_eq!(decoded.to_bits(), target.to_bits());
+        }
+        assert_eq!(Target::MAX.to_bits(), 0x2100ffff);
+        assert_eq!(Target::from_bits(0xff123456), Target::MAX);
+        assert_eq!(Target::from_bits(0xff000000).value, U256::ZERO);
+        assert_eq!(Target::from_bits(0xff800000).value, U256::ZERO);
+    }
+
+    #[test]
+    fn difficulty() {
+        assert_eq!(Target::MAX.difficulty(), 1.0);
+        assert_eq!(Target::from_leading_zeros(1).difficulty(), 2.0);
+        assert_eq!(
+            Target::from_leading_zeros(20).difficulty(),
+            (1 << 20) as f64
+        );
+        assert_eq!(Target { value: U256::ZERO }.difficulty(), f64::INFINITY);
+
+        let target = Target::from_leading_zeros(4);
+        assert_eq!(
+            target.to_string(),
+            format!("{:#x} (difficulty 16.00)", target.value)
+        );
+    }
+
+    #[test]
+    fn next_target() {
+        let current = Target::from_leading_zeros(20);
+        let scaled = |num: u64, den: u64| Target {
+            value: current.value * U256::from(num) / U256::from(den),
+        };
+
+        assert_eq!(Target::next_target(&current, 600, 600), current);
+        assert_eq!(Target::next_target(&current, 0, 600), scaled(1, 4));
+        assert_eq!(Target::next_target(&current, 150, 600), scaled(1, 4));
+        assert_eq!(Target::next_target(&current, 60, 600), scaled(1, 4));
+        assert_eq!(Target::next_target(&current, 300, 600), scaled(1, 2));
+        assert_eq!(Target::next_target(&current, 900, 600), scaled(3, 2));
+        assert_eq!(Target::next_target(&current, 2400, 600), scaled(4, 1));
+        assert_eq!(Target::next_target(&current, 6000, 600), scaled(4, 1));
+        assert_eq!(Target::next_target(&current, u64::MAX, u64::MAX), current);
+        assert_eq!(Target::next_target(&current, 600, 0), current);
+
+        assert!(Target::next_target(&current, 900, 600).is_easier_than(&current));
+        assert!(!Target::next_target(&current, 300, 600).is_easier_than(&current));
+        assert!(!current.is_easier_than(&current));
+
+        // Clamped at the maximum
+        let easy = Target::from_leading_zeros(1);
+        assert_eq!(Target::next_target(&easy, 2400, 600), Target::MAX);
+        assert_eq!(Target::next_target(&Target::MAX, 6000, 600), Target::MAX);
+        assert_eq!(
+            Target::next_target(&Target::MAX, 1, u64::MAX / 2).value,
+            U256::MAX / 4
+        );
+    }
+
     #[test]
     fn halving() {
-        let cr = ConsensusRules::new(Target::MAX, 10000, Halving::None);
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(10000)
+            .halving(Halving::None)
+            .build();
 
         assert_eq!(cr.reward(Height::from(0)), cr.base_coins);
         assert_eq!(cr.reward(Height::from(1)), cr.base_coins);
@@ -216,7 +752,11 @@ mod tests {
         assert_eq!(cr.reward(Height::from(101)), cr.base_coins);
         assert_eq!(cr.reward(Height::from(10000000)), cr.base_coins);
 
-        let cr = ConsensusRules::new(Target::MAX, 10000, Halving::Inf);
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(10000)
+            .halving(Halving::Inf)
+            .build();
 
         assert_eq!(cr.reward(Height::from(0)), cr.base_coins);
         assert_eq!(cr.reward(Height::from(1)), 0);
@@ -224,7 +764,11 @@ mod tests {
         assert_eq!(cr.reward(Height::from(101)), 0);
         assert_eq!(cr.reward(Height::from(10000000)), 0);
 
-        let cr = ConsensusRules::new(Target::MAX, 10000, Halving::Height(10));
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(10000)
+            .halving(Halving::Height(10))
+            .build();
 
         assert_eq!(cr.reward(Height::from(0)), cr.base_coins);
         assert_eq!(cr.reward(Height::from(1)), cr.base_coins);
@@ -232,7 +776,11 @@ mod tests {
         assert_eq!(cr.reward(Height::from(101)), cr.base_coins / 11);
         assert_eq!(cr.reward(Height::from(10000000)), 0);
 
-        let cr = ConsensusRules::new(Target::MAX, 500, Halving::Height(200000));
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(500)
+            .halving(Halving::Height(200000))
+            .build();
 
         assert_eq!(cr.reward(Height::from(0)), cr.base_coins);
         assert_eq!(cr.reward(Height::from(200000)), cr.base_coins / 2);
@@ -240,6 +788,204 @@ mod tests {
         assert_eq!(cr.reward(Height::from(100000000)), 0);
     }
 
+    #[test]
+    fn custom_halving() {
+        let schedule = vec![(0, 100), (10, 50), (20, 20), (30, 0)];
+        let cr = ConsensusRules::new(Target::MAX, 10000, Halving::Custom(schedule)).unwrap();
+
+        assert_eq!(cr.reward(Height::from(0)), 100);
+        assert_eq!(cr.reward(Height::from(9)), 100);
+        assert_eq!(cr.reward(Height::from(10)), 50);
+        assert_eq!(cr.reward(Height::from(19)), 50);
+        assert_eq!(cr.reward(Height::from(20)), 20);
+        assert_eq!(cr.reward(Height::from(29)), 20);
+        assert_eq!(cr.reward(Height::from(30)), 0);
+        assert_eq!(cr.reward(Height::from(10000000)), 0);
+
+        assert_eq!(
+            cr.initial_supply_schedule(Height::from(100)),
+            vec![
+                (Height::from(0), 100),
+                (Height::from(10), 1050),
+                (Height::from(20), 1520),
+                (Height::from(30), 1700),
+            ]
+        );
+        assert_eq!(cr.max_possible_supply(), Some(1700));
+
+        // No reward before the first height, and forever after the last one
+        let cr = ConsensusRules::new(Target::MAX, 0, Halving::Custom(vec![(5, 10)])).unwrap();
+        assert_eq!(cr.reward(Height::from(4)), 0);
+        assert_eq!(cr.reward(Height::from(5)), 10);
+        assert_eq!(
+            cr.initial_supply_schedule(Height::from(10)),
+            vec![(Height::from(0), 0), (Height::from(5), 10)]
+        );
+        assert_eq!(cr.max_possible_supply(), None);
+
+        let json = serde_json::to_string(&cr).unwrap();
+        assert_eq!(serde_json::from_str::<ConsensusRules>(&json).unwrap(), cr);
+        assert_eq!(ConsensusRules::from_bytes(&cr.into_bytes()).unwrap(), cr);
+
+        for invalid in [
+            vec![],
+            vec![(10, 50), (0, 100)],
+            vec![(0, 100), (0, 50)],
+            vec![(0, 50), (10, 100)],
+        ] {
+            assert_eq!(
+                ConsensusRules::new(Target::MAX, 0, Halving::Custom(invalid)),
+                Err(ConsensusRulesError::InvalidHalving)
+            );
+        }
+        assert_eq!(
+            ConsensusRules::new(Target::MAX, 0, Halving::Height(0)),
+            Err(ConsensusRulesError::InvalidHalving)
+        );
+
+        // Invalid rules can't be loaded either
+        let invalid = ConsensusRulesBuilder::default()
+            .halving(Halving::Custom(vec![]))
+            .build();
+        assert!(ConsensusRules::from_json(&invalid.to_json().unwrap()).is_err());
+        assert!(ConsensusRules::from_bytes(&invalid.into_bytes()).is_err());
+    }
+
+    #[test]
+    fn supply_schedule() {
+        let max_height = Height::from(1000000);
+
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(50)
+            .halving(Halving::None)
+            .build();
+        assert_eq!(
+            cr.initial_supply_schedule(max_height.clone()),
+            vec![(Height::from(0), 50)]
+        );
+        assert_eq!(cr.max_possible_supply(), None);
+
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(50)
+            .halving(Halving::Inf)
+            .build();
+        assert_eq!(
+            cr.initial_supply_schedule(max_height.clone()),
+            vec![(Height::from(0), 50), (Height::from(1), 50)]
+        );
+        assert_eq!(cr.initial_supply_schedule(Height::from(0)).len(), 1);
+        assert_eq!(cr.max_possible_supply(), Some(50));
+
+        // Bitcoin-like schedule, with rewards 50, 25, 16, 12, ... every 210000 blocks
+        let era = 210000;
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(50)
+            .halving(Halving::Height(era))
+            .build();
+        assert_eq!(
+            cr.initial_supply_schedule(Height::from(3 * era)),
+            vec![
+                (Height::from(0), 50),
+                (Height::from(era), 50 * era + 25),
+                (Height::from(2 * era), 75 * era + 16),
+                (Height::from(3 * era), 91 * era + 12),
+            ]
+        );
+        let schedule = cr.initial_supply_schedule(Height::from(u64::MAX));
+        assert_eq!(schedule.len(), 14);
+        assert_eq!(schedule.last(), Some(&(Height::from(50 * era), 207 * era)));
+        assert_eq!(cr.max_possible_supply(), Some(207 * era));
+
+        let cr = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(Value::MAX)
+            .halving(Halving::Height(era))
+            .build();
+        assert_eq!(cr.max_possible_supply(), None);
+    }
+
+    #[test]
+    fn checkpoints() {
+        let mut cr = ConsensusRules::default();
+        assert!(cr.last_checkpoint_height().is_none());
+
+        assert!(cr
+            .add_checkpoint(Height::from(10), Hash::new(b"10"))
+            .is_ok());
+        assert!(cr
+            .add_checkpoint(Height::from(20), Hash::new(b"20"))
+            .is_ok());
+        assert_eq!(
+            cr.add_checkpoint(Height::from(20), Hash::new(b"20")),
+            Err(ConsensusRulesError::InvalidCheckpointHeight)
+        );
+        assert_eq!(
+            cr.add_checkpoint(Height::from(5), Hash::new(b"5")),
+            Err(ConsensusRulesError::InvalidCheckpointHeight)
+        );
+        assert_eq!(cr.checkpoints.len(), 2);
+        assert_eq!(cr.last_checkpoint_height(), Some(Height::from(20)));
+
+        assert!(cr.validate_checkpoint(&Height::from(10), &Hash::new(b"10")));
+        assert!(!cr.validate_checkpoint(&Height::from(10), &Hash::new(b"20")));
+        assert!(cr.validate_checkpoint(&Height::from(15), &Hash::new(b"15")));
+    }
+
+    #[test]
+    fn builder() {
+        assert_eq!(
+            ConsensusRulesBuilder::default().build(),
+            ConsensusRules::default()
+        );
+
+        let rules = ConsensusRulesBuilder::default()
+            .target(Target::from_leading_zeros(10))
+            .coins_per_block(50)
+            .halving(Halving::Height(100))
+            .dust_limit(5)
+            .retarget_interval(10)
+            .target_block_time(600)
+            .max_block_size(1000)
+            .min_fee(2)
+            .build();
+        assert_eq!(rules.target, Target::from_leading_zeros(10));
+        assert_eq!(rules.base_coins, 50);
+        assert_eq!(rules.halving, Halving::Height(100));
+        assert_eq!(rules.dust_threshold, Some(5));
+        assert_eq!(rules.retarget_interval, Some(10));
+        assert_eq!(rules.target_block_time_secs, Some(600));
+        assert_eq!(rules.max_block_size, Some(1000));
+        assert_eq!(rules.min_fee, Some(2));
+        assert!(!rules.validate_min_fee(1));
+        assert!(rules.validate_min_fee(2));
+        assert!(ConsensusRules::default().validate_min_fee(0));
+        assert_eq!(rules.block_maturity, 0);
+    }
+
+    #[test]
+    fn version_activations() {
+        let mut cr = ConsensusRules::default();
+        assert_eq!(cr.min_block_version_at(&Height::from(0)), 1);
+
+        cr.activate_version_at_height(3, Height::from(20));
+        cr.activate_version_at_height(2, Height::from(10));
+        assert_eq!(
+            cr.version_activations,
+            vec![(Height::from(10), 2), (Height::from(20), 3)]
+        );
+        assert_eq!(cr.min_block_version_at(&Height::from(9)), 1);
+        assert_eq!(cr.min_block_version_at(&Height::from(10)), 2);
+        assert_eq!(cr.min_block_version_at(&Height::from(19)), 2);
+        assert_eq!(cr.min_block_version_at(&Height::from(100)), 3);
+
+        cr.min_block_version = 4;
+        assert_eq!(cr.min_block_version_at(&Height::from(0)), 4);
+        assert_eq!(cr.min_block_version_at(&Height::from(100)), 4);
+    }
+
     #[test]
     fn serde() {
         let target = Target::MAX;
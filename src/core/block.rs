@@ -4,50 +4,158 @@
 //! Additional data is used both for tampering prevention and for enabling Proof of Work.
 //!
 
+use crate::consensus::ConsensusRules;
+use crate::core::blockchain::{Blockchain, Height};
 use crate::core::hash::Hash;
-use crate::core::transaction::Transaction;
-use crate::traits::io::{ByteIO, FileIO};
+use crate::core::keys::PublicKey;
+use crate::core::merkle::{MerkleProof, MerkleSide, MerkleTree};
+use crate::core::transaction::{Transaction, Value};
+use crate::traits::io::{ByteIO, FileIO, JsonIO};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub type Nonce = u32;
+pub type NoncePrefix = [u8; 4];
+
+/// The version set in new blocks
+pub const BLOCK_VERSION: u32 = 1;
+
+fn default_block_version() -> u32 {
+    BLOCK_VERSION
+}
 
 /// The block data. It points to the previous block by specifying its hash,
 /// and it contains a list of transactions.
 ///
+/// The version lets miners signal support for new rules: once a rule is activated, blocks
+/// below the [minimum version](crate::consensus::ConsensusRules::min_block_version_at)
+/// are rejected.
+///
 /// The nonce is used by miners for generating new hashes during PoW.
-/// The top hash is computed from the hashes of the list of transactions.
+/// The nonce prefix is hashed together with the nonce: miners working on the
+/// same block can pick different prefixes to search non-overlapping nonce spaces.
+/// The top hash is the [Merkle root](crate::core::merkle) of the hashes of the list of transactions.
 ///
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+/// The Unix timestamp optionally records the block creation time. It's unrelated to
+/// the coinbase transaction timestamp, which encodes the block height.
+///
+/// The author hint is an advisory field that miners can use to identify themselves.
+/// It is not serialized, so it doesn't affect the block hash, it's not stored in chain
+/// files and it's not relevant for consensus. It should never be trusted, and it's
+/// ignored when comparing blocks.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BlockData {
+    #[serde(default = "default_block_version")]
+    pub version: u32,
     pub prev_hash: Hash,
+    pub nonce_prefix: NoncePrefix,
     pub nonce: Nonce,
+    pub unix_timestamp: Option<u64>,
     pub top_hash: Hash,
     pub transactions: Vec<Transaction>,
+    #[serde(skip)]
+    pub author_hint: Option<PublicKey>,
+}
+
+/// Returns the current Unix time in seconds
+///
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn tx_hashes(transactions: &[Transaction]) -> Vec<Hash> {
+    transactions.iter().map(|tx| tx.hash.clone()).collect()
 }
 
 pub fn compute_top_hash(transactions: &[Transaction]) -> Hash {
-    let digest_list: Vec<Vec<u8>> = transactions
-        .iter()
-        .map(|val| val.hash.digest().to_vec())
-        .collect();
-    Hash::new(&digest_list.concat().as_slice())
+    MerkleTree::new(&tx_hashes(transactions)).root().clone()
 }
 
 impl BlockData {
     pub fn new(prev_hash: Hash, nonce: Nonce, transactions: Vec<Transaction>) -> BlockData {
+        BlockData::new_with_prefix(prev_hash, NoncePrefix::default(), nonce, transactions)
+    }
+
+    /// Same as [new](BlockData::new), also setting the Unix timestamp of the block.
+    /// If no timestamp is given, the current time is used.
+    ///
+    pub fn new_with_timestamp(
+        prev_hash: Hash,
+        nonce: Nonce,
+        transactions: Vec<Transaction>,
+        unix_timestamp: Option<u64>,
+    ) -> BlockData {
+        let mut data = BlockData::new(prev_hash, nonce, transactions);
+        data.unix_timestamp = Some(unix_timestamp.unwrap_or_else(unix_now));
+        data
+    }
+
+    pub fn new_with_prefix(
+        prev_hash: Hash,
+        nonce_prefix: NoncePrefix,
+        nonce: Nonce,
+        transactions: Vec<Transaction>,
+    ) -> BlockData {
         BlockData {
+            version: BLOCK_VERSION,
             prev_hash,
+            nonce_prefix,
             nonce,
+            unix_timestamp: None,
             top_hash: compute_top_hash(&transactions),
             transactions,
+            author_hint: None,
         }
     }
+
+    /// Serializes the fields covered by the block hash. Transactions are covered through
+    /// the top hash, so their data can be pruned without invalidating the block hash.
+    ///
+    pub fn header_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&(
+            &self.version,
+            &self.prev_hash,
+            &self.nonce_prefix,
+            &self.nonce,
+            &self.unix_timestamp,
+            &self.top_hash,
+        ))
+        .unwrap()
+    }
+}
+
+impl PartialEq for BlockData {
+    fn eq(&self, other: &Self) -> bool {
+        let BlockData {
+            version,
+            prev_hash,
+            nonce_prefix,
+            nonce,
+            unix_timestamp,
+            top_hash,
+            transactions,
+            author_hint: _,
+        } = self;
+        *version == other.version
+            && *prev_hash == other.prev_hash
+            && *nonce_prefix == other.nonce_prefix
+            && *nonce == other.nonce
+            && *unix_timestamp == other.unix_timestamp
+            && *top_hash == other.top_hash
+            && *transactions == other.transactions
+    }
 }
 
 impl ByteIO for BlockData {}
 
 /// The Block struct is a wrapper for the [block data](BlockData), it
-/// computes and stores the hash of its contents.
+/// computes and stores the hash of its [header](BlockData::header_bytes).
 ///
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Block {
@@ -57,13 +165,23 @@ pub struct Block {
 
 impl Block {
     pub fn new(block_data: BlockData) -> Block {
-        let bytes: Vec<u8> = bincode::serialize(&block_data).unwrap();
         Block {
-            hash: Hash::new(bytes.as_slice()),
+            hash: Hash::new(&block_data.header_bytes()),
             data: block_data,
         }
     }
 
+    /// Sets the advisory author hint, see [BlockData]. The block hash is not affected.
+    ///
+    pub fn with_author_hint(mut self, pubkey: PublicKey) -> Block {
+        self.data.author_hint = Some(pubkey);
+        self
+    }
+
+    pub fn author_hint(&self) -> Option<&PublicKey> {
+        self.data.author_hint.as_ref()
+    }
+
     pub fn prev_hash(&self) -> &Hash {
         &self.data.prev_hash
     }
@@ -72,27 +190,211 @@ impl Block {
         self.data.transactions.as_slice()
     }
 
+    /// Total serialized size of the transactions, limited by the max block size of the
+    /// consensus rules
+    ///
+    pub fn transactions_size_bytes(&self) -> usize {
+        self.data
+            .transactions
+            .iter()
+            .map(|tx| tx.serialized_size())
+            .sum()
+    }
+
+    #[must_use]
     pub fn is_hash_valid(&self) -> bool {
-        let bytes: Vec<u8> = self.data.into_bytes();
-        return Hash::new(bytes.as_slice()).digest() == self.hash.digest();
+        return Hash::new(&self.data.header_bytes()).digest() == self.hash.digest();
     }
 
     pub fn is_top_hash_valid(&self) -> bool {
         compute_top_hash(&self.data.transactions) == self.data.top_hash
     }
+
+    /// Panics if the hash doesn't match the header, for catching data corruption
+    ///
+    pub fn assert_hash_valid(&self) {
+        if !self.is_hash_valid() {
+            panic!(
+                "Block hash mismatch: expected {}, got {}",
+                Hash::new(&self.data.header_bytes()),
+                self.hash
+            );
+        }
+    }
+
+    /// Panics if the top hash doesn't match the transactions, for catching data corruption
+    ///
+    pub fn assert_top_hash_valid(&self) {
+        if !self.is_top_hash_valid() {
+            panic!(
+                "Block top hash mismatch: expected {}, got {}",
+                compute_top_hash(&self.data.transactions),
+                self.data.top_hash
+            );
+        }
+    }
+
+    /// Returns the Merkle proof for the transaction at the given index, which can be
+    /// checked against the top hash with [verify_tx_inclusion](Block::verify_tx_inclusion).
+    ///
+    pub fn transactions_merkle_proof(&self, tx_index: usize) -> Option<Vec<(Hash, MerkleSide)>> {
+        MerkleTree::new(&tx_hashes(&self.data.transactions))
+            .proof(tx_index)
+            .map(|proof| proof.path)
+    }
+
+    /// Returns the Merkle proof for the transaction with the given hash, whose root is
+    /// the block top hash. Returns None if the transaction is not in the block.
+    ///
+    pub fn proof_for_tx(&self, tx_hash: &Hash) -> Option<MerkleProof> {
+        let hashes = tx_hashes(&self.data.transactions);
+        let index = hashes.iter().position(|hash| hash == tx_hash)?;
+        MerkleTree::new(&hashes).proof(index)
+    }
+
+    /// Verifies that a transaction is included in a block only knowing the block top hash
+    ///
+    pub fn verify_tx_inclusion(
+        tx_hash: &Hash,
+        proof: &[(Hash, MerkleSide)],
+        top_hash: &Hash,
+    ) -> bool {
+        MerkleProof {
+            path: proof.to_vec(),
+            root: top_hash.clone(),
+        }
+        .verify(tx_hash)
+    }
+
+    /// Verifies the input signatures of all the transactions of the blocks in parallel,
+    /// returning for every block whether all its signatures are valid. The spent
+    /// transactions are searched in the chain and in the blocks themselves, so the
+    /// blocks can spend each other's outputs before being added to the chain.
+    ///
+    /// Only signatures are checked, see [validate_block](crate::chain::Chain::validate_block)
+    /// for the full validation. Signatures are verified one by one, since the Ed25519 batch
+    /// verification requires the `batch` feature of ed25519-dalek, which is not enabled.
+    ///
+    pub fn batch_verify_signatures(blocks: &[Block], chain: &Blockchain) -> Vec<bool> {
+        let txs: HashMap<&Hash, &Transaction> = chain
+            .iter()
+            .chain(blocks.iter())
+            .flat_map(|block| block.data.transactions.iter())
+            .map(|tx| (&tx.hash, tx))
+            .collect();
+        blocks
+            .par_iter()
+            .map(|block| {
+                block
+                    .data
+                    .transactions
+                    .par_iter()
+                    .flat_map(|tx| tx.data.inputs.par_iter())
+                    .all(|input| {
+                        txs.get(&input.hash)
+                            .is_some_and(|spent_tx| input.verify_signature(spent_tx))
+                    })
+            })
+            .collect()
+    }
+
+    /// Computes the maximum value a coinbase transaction can claim: the consensus
+    /// reward for the block height plus the fees of the other transactions in the block.
+    ///
+    /// The height is the one the block will occupy in the chain, i.e. the height of the
+    /// previous block + 1 (which is equal to [Blockchain::height](crate::core::blockchain::Blockchain::height)
+    /// of the chain the block is being appended to, not to the height of its last block).
+    ///
+    pub fn compute_expected_coinbase_value(
+        rules: &ConsensusRules,
+        height: Height,
+        tx_fees: Value,
+    ) -> Value {
+        rules.reward(height) + tx_fees
+    }
 }
 
 impl ByteIO for Block {}
 
 impl FileIO for Block {}
 
+impl JsonIO for Block {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chain::Chain;
+    use crate::consensus::{ConsensusRulesBuilder, Halving, Target};
     use crate::core::keys::KeyPair;
     use crate::core::transaction::{Input, Output, TransactionData};
     use tempfile::*;
 
+    #[test]
+    fn batch_verify_signatures() {
+        use crate::utils::{new_block, new_coinbase_tx, new_tx};
+        use crate::utxo::Utxo;
+
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+
+        let tx_1 = new_tx(
+            &key_1,
+            &chain.find_utxos_for_key(&key_1.public_key()),
+            vec![Output::new(5000, key_1.public_key())],
+        )
+        .unwrap();
+        let block_1 = new_block(&chain, 0, vec![tx_1.clone()]);
+        // Spends an output of the previous block, which is not in the chain
+        let tx_2 = new_tx(
+            &key_1,
+            &[Utxo::new(tx_1.hash.clone(), 0, 5000)],
+            vec![Output::new(100, key_1.public_key())],
+        )
+        .unwrap();
+        let block_2 = Block::new(BlockData::new(block_1.hash.clone(), 0, vec![tx_2]));
+        let coinbase = new_coinbase_tx(&key_1.public_key(), 100, 2);
+        let block_3 = Block::new(BlockData::new(block_2.hash.clone(), 0, vec![coinbase]));
+        let blocks = vec![block_1.clone(), block_2, block_3];
+        assert_eq!(
+            Block::batch_verify_signatures(&blocks, &chain.chain),
+            vec![true, true, true]
+        );
+
+        // Signed by the wrong key
+        let tx_3 = new_tx(
+            &key_2,
+            &[Utxo::new(tx_1.hash.clone(), 1, 5000)],
+            vec![Output::new(100, key_1.public_key())],
+        )
+        .unwrap();
+        let block_3 = Block::new(BlockData::new(block_1.hash.clone(), 0, vec![tx_3]));
+        // Spends an unknown transaction
+        let tx_4 = new_tx(
+            &key_1,
+            &[Utxo::new(Hash::new(b"test"), 0, 100)],
+            vec![Output::new(100, key_1.public_key())],
+        )
+        .unwrap();
+        let block_4 = Block::new(BlockData::new(block_1.hash.clone(), 0, vec![tx_4]));
+        assert_eq!(
+            Block::batch_verify_signatures(&[block_1, block_3, block_4], &chain.chain),
+            vec![true, false, false]
+        );
+        assert!(Block::batch_verify_signatures(&[], &chain.chain).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Block hash mismatch")]
+    fn assert_hash_valid() {
+        let key = KeyPair::new();
+        let mut block = crate::utils::new_genesis_block(&key.public_key(), 100);
+        block.assert_hash_valid();
+        block.assert_top_hash_valid();
+        block.data.nonce += 1;
+        block.assert_hash_valid();
+    }
+
     #[test]
     fn hashing_equality() {
         let key = KeyPair::new();
@@ -102,22 +404,20 @@ mod tests {
                     hash: Hash::new(b"test_1"),
                     index: 0,
                     signature: key.sign(b"test_1"),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 }],
-                vec![Output {
-                    value: 1,
-                    pubkey: key.public_key(),
-                }],
+                vec![Output::new(1, key.public_key())],
             )),
             Transaction::new(TransactionData::new(
                 vec![Input {
                     hash: Hash::new(b"test_2"),
                     index: 0,
                     signature: key.sign(b"test_2"),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 }],
-                vec![Output {
-                    value: 1,
-                    pubkey: key.public_key(),
-                }],
+                vec![Output::new(1, key.public_key())],
             )),
         ];
 
@@ -139,22 +439,20 @@ mod tests {
                 hash: Hash::new(b"test_1"),
                 index: 0,
                 signature: key.sign(b"test_1"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         ));
         let tx_2 = Transaction::new(TransactionData::new(
             vec![Input {
                 hash: Hash::new(b"test_2"),
                 index: 0,
                 signature: key.sign(b"test_2"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         ));
 
         let txs_1 = vec![tx_1.clone(), tx_2.clone()];
@@ -166,6 +464,125 @@ mod tests {
         assert_ne!(block_data_1.top_hash, block_data_2.top_hash)
     }
 
+    #[test]
+    fn author_hint() {
+        let key = KeyPair::new();
+        let block = Block::new(BlockData::new(Hash::new(b"test"), 0, vec![]));
+        assert!(block.author_hint().is_none());
+
+        let hash = block.hash.clone();
+        let block = block.with_author_hint(key.public_key());
+        assert_eq!(block.author_hint(), Some(&key.public_key()));
+        assert_eq!(block.hash, hash);
+        assert!(block.is_hash_valid());
+
+        let deserialized = Block::from_bytes(&block.into_bytes()).unwrap();
+        assert!(deserialized.author_hint().is_none());
+        assert_eq!(deserialized.hash, hash);
+        assert_eq!(deserialized, block);
+    }
+
+    #[test]
+    fn merkle_proof() {
+        let key = KeyPair::new();
+        let txs: Vec<Transaction> = (0..5)
+            .map(|i| {
+                Transaction::new(TransactionData::new(
+                    vec![],
+                    vec![Output::new(i, key.public_key())],
+                ))
+            })
+            .collect();
+        let block = Block::new(BlockData::new(Hash::new(b"test"), 0, txs));
+        let top_hash = &block.data.top_hash;
+
+        for index in [0, 2, 4] {
+            let tx_hash = &block.transactions()[index].hash;
+            let proof = block.transactions_merkle_proof(index).unwrap();
+            assert!(Block::verify_tx_inclusion(tx_hash, &proof, top_hash));
+            assert!(!Block::verify_tx_inclusion(
+                &block.transactions()[1].hash,
+                &proof,
+                top_hash
+            ));
+        }
+        assert!(block.transactions_merkle_proof(5).is_none());
+
+        for tx in block.transactions() {
+            let proof = block.proof_for_tx(&tx.hash).unwrap();
+            assert_eq!(proof.root, *top_hash);
+            assert!(proof.verify(&tx.hash));
+        }
+        assert!(block.proof_for_tx(&Hash::new(b"other")).is_none());
+    }
+
+    #[test]
+    fn nonce_prefix() {
+        let block_1 = Block::new(BlockData::new(Hash::new(b"test"), 0, vec![]));
+        let block_2 = Block::new(BlockData::new_with_prefix(
+            Hash::new(b"test"),
+            [0, 0, 0, 1],
+            0,
+            vec![],
+        ));
+        assert_eq!(block_1.data.nonce_prefix, [0; 4]);
+        assert_ne!(block_1.hash, block_2.hash);
+        assert!(block_2.is_hash_valid());
+    }
+
+    #[test]
+    fn expected_coinbase_value() {
+        let rules = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(10000)
+            .halving(Halving::None)
+            .build();
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(1), 0),
+            10000
+        );
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(1000), 250),
+            10250
+        );
+
+        let rules = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(10000)
+            .halving(Halving::Height(10))
+            .build();
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(9), 100),
+            10100
+        );
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(10), 100),
+            5100
+        );
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(20), 1),
+            3334
+        );
+
+        let rules = ConsensusRulesBuilder::default()
+            .target(Target::MAX)
+            .coins_per_block(10000)
+            .halving(Halving::Inf)
+            .build();
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(0), 0),
+            10000
+        );
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(1), 0),
+            0
+        );
+        assert_eq!(
+            Block::compute_expected_coinbase_value(&rules, Height::from(1), 5000),
+            5000
+        );
+    }
+
     #[test]
     fn file_io() {
         let key = KeyPair::new();
@@ -178,11 +595,10 @@ mod tests {
                     hash: Hash::new(b"test_1"),
                     index: 0,
                     signature: key.sign(b"test_1"),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 }],
-                vec![Output {
-                    value: 1,
-                    pubkey: key.public_key(),
-                }],
+                vec![Output::new(1, key.public_key())],
             ))],
         ));
 
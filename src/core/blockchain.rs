@@ -5,22 +5,41 @@
 //! It provides some utility functions for querying the chain, getting transaction value, etc.
 //!
 
+use crate::chain::UtxoPool;
+use crate::consensus::Target;
 use crate::core::block::Block;
 use crate::core::hash::Hash;
 use crate::core::transaction::{Output, Transaction, Value};
 use crate::traits::io::{ByteIO, FileIO};
 use core::cmp::Ordering;
 use core::fmt;
+use ed25519_dalek::SIGNATURE_LENGTH;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::From;
 use std::ops::{Add, Sub};
 use std::slice::Iter;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum BlockchainError {
     InvalidPrevHash,
 }
 
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Blockchain error: {}",
+            match self {
+                BlockchainError::InvalidPrevHash => "block doesn't point to the last block",
+            }
+        )
+    }
+}
+
+impl std::error::Error for BlockchainError {}
+
 /// Utility struct for computing input, output and fee value for a transaction
 /// or group of transactions
 ///
@@ -65,7 +84,7 @@ impl Add for TransactionValue {
 
 /// Helper struct representing the height of a block in the blockchain
 ///
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct Height(u64);
 
 impl From<Height> for u64 {
@@ -162,6 +181,8 @@ impl Blockchain {
     pub fn append(&mut self, block: Block) -> Result<Height, BlockchainError> {
         if block.data.prev_hash == self.list[self.list.len() - 1].hash {
             self.list.push(block);
+            #[cfg(debug_assertions)]
+            self.get_last_block().assert_hash_valid();
             return Ok(Height::from(self.list.len() - 1));
         }
         Err(BlockchainError::InvalidPrevHash)
@@ -178,6 +199,77 @@ impl Blockchain {
         &self.list[self.list.len() - 1]
     }
 
+    /// Builds a new UTXO pool by replaying all the blocks, for replacing a pool that
+    /// may be stale or corrupted
+    ///
+    pub fn repair_utxo_pool_from_scratch(&self) -> UtxoPool {
+        UtxoPool::new(self)
+    }
+
+    /// Removes the outputs of the transactions whose outputs are all spent, i.e. not
+    /// unspent according to the predicate, to reduce the storage used by old blocks.
+    /// Inputs and transaction hashes are kept, so the top hashes and the block hashes
+    /// stay valid.
+    ///
+    /// The genesis block is never pruned. Returns the number of pruned transactions.
+    ///
+    /// The blocks containing pruned transactions, or transactions spending pruned
+    /// outputs, can no longer be fully validated, so they must have been validated
+    /// before pruning.
+    ///
+    pub fn gc_spent_data<P>(&mut self, is_unspent: P) -> usize
+    where
+        P: Fn(&Hash, u32) -> bool,
+    {
+        let mut pruned = 0;
+        for block in self.list.iter_mut().skip(1) {
+            for tx in block.data.transactions.iter_mut() {
+                if tx.is_pruned() {
+                    continue;
+                }
+                let spent =
+                    (0..tx.data.outputs.len() as u32).all(|index| !is_unspent(&tx.hash, index));
+                if spent {
+                    tx.data.outputs.clear();
+                    pruned += 1;
+                }
+            }
+        }
+        pruned
+    }
+
+    /// Computes the mean time between adjacent blocks that both have a Unix timestamp.
+    /// Returns None if there are no such pairs.
+    ///
+    pub fn average_block_time_secs(&self) -> Option<f64> {
+        let deltas: Vec<f64> = self
+            .list
+            .windows(2)
+            .filter_map(
+                |pair| match (pair[0].data.unix_timestamp, pair[1].data.unix_timestamp) {
+                    (Some(prev), Some(next)) => Some(next as f64 - prev as f64),
+                    _ => None,
+                },
+            )
+            .collect();
+        if deltas.is_empty() {
+            return None;
+        }
+        Some(deltas.iter().sum::<f64>() / deltas.len() as f64)
+    }
+
+    /// Roughly estimates the time needed for mining the next block with the given target,
+    /// assuming it takes as long as the average block. A block is found immediately when
+    /// the target is the maximum one, since any hash satisfies it.
+    ///
+    pub fn estimated_time_to_next_block(&self, target: &Target) -> Option<Duration> {
+        if *target == Target::MAX {
+            return Some(Duration::ZERO);
+        }
+        self.average_block_time_secs()
+            .map(|secs| Duration::from_secs_f64(secs.max(0.0)))
+    }
+
     pub fn query_block(&self, hash: &Hash) -> Option<(usize, &Block)> {
         for (i, block) in self.list.iter().enumerate().rev() {
             if block.hash == *hash {
@@ -198,6 +290,131 @@ impl Blockchain {
         return None;
     }
 
+    /// Iterates over the coinbase transactions with the height of their blocks. Since the
+    /// coinbase is always the last transaction of a block, only the last ones are checked.
+    ///
+    pub fn iter_coinbase_transactions(&self) -> impl Iterator<Item = (Height, &Transaction)> {
+        self.list.iter().enumerate().filter_map(|(height, block)| {
+            block
+                .data
+                .transactions
+                .last()
+                .filter(|tx| tx.is_coinbase())
+                .map(|tx| (Height::from(height), tx))
+        })
+    }
+
+    /// Sums the outputs of all the coinbase transactions, i.e. all the coins ever issued.
+    /// Returns None on overflow.
+    ///
+    /// Pruned coinbase transactions have no outputs, so the sum is only a lower bound
+    /// on a [pruned](Blockchain::gc_spent_data) chain.
+    ///
+    pub fn total_coinbase_value(&self) -> Option<Value> {
+        self.iter_coinbase_transactions()
+            .flat_map(|(_, tx)| tx.data.outputs.iter())
+            .try_fold(0 as Value, |acc, output| acc.checked_add(output.value))
+    }
+
+    /// Iterates over the transactions with the hashes of the transactions they spend,
+    /// listing every spent transaction once
+    ///
+    fn iter_tx_dependencies(&self) -> impl Iterator<Item = (&Hash, Vec<&Hash>)> {
+        self.list
+            .iter()
+            .flat_map(|block| block.data.transactions.iter())
+            .map(|tx| {
+                let mut spent = Vec::<&Hash>::new();
+                for input in tx.data.inputs.iter() {
+                    if !spent.contains(&&input.hash) {
+                        spent.push(&input.hash);
+                    }
+                }
+                (&tx.hash, spent)
+            })
+    }
+
+    /// Maps every transaction to the transactions spending its outputs, in chain order
+    ///
+    pub fn compute_tx_graph(&self) -> HashMap<Hash, Vec<Hash>> {
+        let mut graph = HashMap::<Hash, Vec<Hash>>::new();
+        for (hash, spent) in self.iter_tx_dependencies() {
+            graph.entry(hash.clone()).or_default();
+            for spent_hash in spent {
+                graph
+                    .entry(spent_hash.clone())
+                    .or_default()
+                    .push(hash.clone());
+            }
+        }
+        graph
+    }
+
+    /// Maps every transaction to the transactions whose outputs it spends, in input order.
+    /// Coinbase transactions map to an empty list.
+    ///
+    pub fn compute_tx_graph_reverse(&self) -> HashMap<Hash, Vec<Hash>> {
+        self.iter_tx_dependencies()
+            .map(|(hash, spent)| (hash.clone(), spent.into_iter().cloned().collect()))
+            .collect()
+    }
+
+    /// Returns the length of the longest chain of spent transactions leading to a
+    /// transaction: coinbase transactions have depth 0, and every other transaction
+    /// is one level deeper than the deepest transaction it spends. Returns None if the
+    /// transaction is not in the chain.
+    ///
+    pub fn transaction_depth(&self, tx_hash: &Hash) -> Option<usize> {
+        let mut depths = HashMap::<&Hash, usize>::new();
+        for (hash, spent) in self.iter_tx_dependencies() {
+            let depth = spent
+                .iter()
+                .filter_map(|spent_hash| depths.get(spent_hash))
+                .max()
+                .map_or(0, |depth| depth + 1);
+            if hash == tx_hash {
+                return Some(depth);
+            }
+            depths.insert(hash, depth);
+        }
+        None
+    }
+
+    /// Security audit: finds the input signatures appearing in more than one transaction,
+    /// returning every pair of transactions sharing a signature.
+    ///
+    /// Input signatures only sign the hash of the spent transaction, so spending two
+    /// outputs of the same transaction with the same key in different transactions
+    /// produces the same signature, which can then be copied by anyone.
+    ///
+    pub fn detect_reused_signatures(&self) -> Vec<(Hash, Hash)> {
+        let mut signatures: Vec<([u8; SIGNATURE_LENGTH], &Hash)> = self
+            .list
+            .iter()
+            .flat_map(|block| block.data.transactions.iter())
+            .flat_map(|tx| {
+                tx.data
+                    .inputs
+                    .iter()
+                    .map(move |input| (input.signature.to_bytes(), &tx.hash))
+            })
+            .collect();
+        signatures.sort_by_key(|(signature, _)| *signature);
+
+        let mut pairs = Vec::<(Hash, Hash)>::new();
+        for group in signatures.chunk_by(|a, b| a.0 == b.0) {
+            for (i, (_, first)) in group.iter().enumerate() {
+                for (_, second) in group[i + 1..].iter() {
+                    let pair = ((*first).clone(), (*second).clone());
+                    if first != second && !pairs.contains(&pair) {
+                        pairs.push(pair);
+                    }
+                }
+            }
+        }
+        pairs
+    }
+
     pub fn get_tx_input_value(&self, tx: &Transaction) -> Option<Value> {
         let mut value: Value = 0;
         for input in &tx.data.inputs {
@@ -257,6 +474,37 @@ mod tests {
     use crate::core::transaction::{Input, TransactionData};
     use crate::utils::*;
 
+    #[test]
+    fn block_time() {
+        let key = KeyPair::new();
+        let mut chain = Blockchain::new(new_genesis_block(&key.public_key(), 100));
+        let target = Target::from_leading_zeros(8);
+        assert!(chain.average_block_time_secs().is_none());
+        assert!(chain.estimated_time_to_next_block(&target).is_none());
+
+        let mut add_block = |unix_timestamp: Option<u64>| {
+            let mut data = BlockData::new(chain.get_last_block().hash.clone(), 0, vec![]);
+            data.unix_timestamp = unix_timestamp;
+            chain.append(Block::new(data)).unwrap();
+        };
+        add_block(Some(1000));
+        add_block(Some(1060));
+        add_block(None);
+        add_block(Some(2000));
+        add_block(Some(2030));
+        add_block(Some(2060));
+
+        assert_eq!(chain.average_block_time_secs(), Some(40.0));
+        assert_eq!(
+            chain.estimated_time_to_next_block(&target),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(
+            chain.estimated_time_to_next_block(&Target::MAX),
+            Some(Duration::ZERO)
+        );
+    }
+
     #[test]
     fn add_block() {
         let mut block_gen = BlockGen::default();
@@ -347,15 +595,171 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn coinbase_transactions() {
+        let key = KeyPair::new();
+        let mut chain = Blockchain::new(new_genesis_block(&key.public_key(), 100));
+        let genesis_tx = chain.list[0].data.transactions[0].clone();
+        let regular_tx = Transaction::new(TransactionData::new(
+            vec![Input {
+                hash: genesis_tx.hash.clone(),
+                index: 0,
+                signature: key.sign(genesis_tx.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
+            }],
+            vec![Output::new(100, key.public_key())],
+        ));
+
+        let coinbase = new_coinbase_tx(&key.public_key(), 50, 0);
+        let block = Block::new(BlockData::new(
+            chain.get_last_block().hash.clone(),
+            0,
+            vec![regular_tx.clone(), coinbase.clone()],
+        ));
+        chain.append(block).unwrap();
+        let block = Block::new(BlockData::new(
+            chain.get_last_block().hash.clone(),
+            0,
+            vec![regular_tx],
+        ));
+        chain.append(block).unwrap();
+
+        let coinbases: Vec<(Height, &Transaction)> = chain.iter_coinbase_transactions().collect();
+        assert_eq!(
+            coinbases,
+            vec![(Height::from(0), &genesis_tx), (Height::from(1), &coinbase)]
+        );
+        assert_eq!(chain.total_coinbase_value(), Some(150));
+
+        let coinbase = new_coinbase_tx(&key.public_key(), Value::MAX, 2);
+        let block = Block::new(BlockData::new(
+            chain.get_last_block().hash.clone(),
+            0,
+            vec![coinbase],
+        ));
+        chain.append(block).unwrap();
+        assert_eq!(chain.total_coinbase_value(), None);
+    }
+
+    #[test]
+    fn tx_graph() {
+        let key = KeyPair::new();
+        let mut chain = Blockchain::new(new_genesis_block(&key.public_key(), 100));
+        let genesis_tx = chain.list[0].data.transactions[0].clone();
+        let make_tx = |spent: &[(&Hash, u32)], outputs: usize| {
+            Transaction::new(TransactionData::new(
+                spent
+                    .iter()
+                    .map(|(hash, index)| Input {
+                        hash: (*hash).clone(),
+                        index: *index,
+                        signature: key.sign(hash.digest()),
+                        sequence: Input::SEQUENCE_FINAL,
+                        reveal: None,
+                    })
+                    .collect(),
+                (0..outputs)
+                    .map(|_| Output::new(10, key.public_key()))
+                    .collect(),
+            ))
+        };
+        let mut add_block = |txs: Vec<Transaction>| {
+            let block = Block::new(BlockData::new(chain.get_last_block().hash.clone(), 0, txs));
+            chain.append(block).unwrap();
+        };
+
+        // genesis -> tx_1 -> tx_2 -> tx_3, with tx_3 also spending tx_1
+        let tx_1 = make_tx(&[(&genesis_tx.hash, 0)], 3);
+        let tx_2 = make_tx(&[(&tx_1.hash, 0), (&tx_1.hash, 1)], 1);
+        let coinbase = new_coinbase_tx(&key.public_key(), 50, 1);
+        add_block(vec![tx_1.clone(), tx_2.clone(), coinbase.clone()]);
+        let tx_3 = make_tx(&[(&tx_2.hash, 0), (&tx_1.hash, 2)], 1);
+        add_block(vec![tx_3.clone()]);
+
+        let graph = chain.compute_tx_graph();
+        assert_eq!(graph.len(), 5);
+        assert_eq!(graph[&genesis_tx.hash], vec![tx_1.hash.clone()]);
+        assert_eq!(
+            graph[&tx_1.hash],
+            vec![tx_2.hash.clone(), tx_3.hash.clone()]
+        );
+        assert_eq!(graph[&tx_2.hash], vec![tx_3.hash.clone()]);
+        assert!(graph[&tx_3.hash].is_empty());
+        assert!(graph[&coinbase.hash].is_empty());
+
+        let reverse = chain.compute_tx_graph_reverse();
+        assert_eq!(reverse.len(), 5);
+        assert!(reverse[&genesis_tx.hash].is_empty());
+        assert!(reverse[&coinbase.hash].is_empty());
+        assert_eq!(reverse[&tx_2.hash], vec![tx_1.hash.clone()]);
+        assert_eq!(
+            reverse[&tx_3.hash],
+            vec![tx_2.hash.clone(), tx_1.hash.clone()]
+        );
+
+        assert_eq!(chain.transaction_depth(&genesis_tx.hash), Some(0));
+        assert_eq!(chain.transaction_depth(&coinbase.hash), Some(0));
+        assert_eq!(chain.transaction_depth(&tx_1.hash), Some(1));
+        assert_eq!(chain.transaction_depth(&tx_2.hash), Some(2));
+        assert_eq!(chain.transaction_depth(&tx_3.hash), Some(3));
+        assert_eq!(chain.transaction_depth(&Hash::new(b"nothing")), None);
+    }
+
+    #[test]
+    fn reused_signatures() {
+        let key = KeyPair::new();
+        let genesis = new_genesis_block(&key.public_key(), 100);
+        let genesis_tx = genesis.data.transactions[0].clone();
+        let mut chain = Blockchain::new(genesis);
+        let make_tx = |hash: &Hash, indexes: &[u32], value: Value| {
+            Transaction::new(TransactionData::new(
+                indexes
+                    .iter()
+                    .map(|index| Input {
+                        hash: hash.clone(),
+                        index: *index,
+                        signature: key.sign(hash.digest()),
+                        sequence: Input::SEQUENCE_FINAL,
+                        reveal: None,
+                    })
+                    .collect(),
+                vec![Output::new(value, key.public_key())],
+            ))
+        };
+        let add_block = |chain: &mut Blockchain, txs: Vec<Transaction>| {
+            let block = Block::new(BlockData::new(chain.get_last_block().hash.clone(), 0, txs));
+            chain.append(block).unwrap();
+        };
+
+        let tx_1 = make_tx(&genesis_tx.hash, &[0], 100);
+        add_block(&mut chain, vec![tx_1.clone()]);
+        // The same signature twice in a single transaction is not reported
+        let tx_2 = make_tx(&tx_1.hash, &[0, 1], 1);
+        add_block(&mut chain, vec![tx_2.clone()]);
+        assert!(chain.detect_reused_signatures().is_empty());
+
+        // Spending the same transaction again produces the same signature
+        let tx_3 = make_tx(&tx_1.hash, &[2], 2);
+        let tx_4 = make_tx(&tx_1.hash, &[3], 3);
+        add_block(&mut chain, vec![tx_3.clone(), tx_4.clone()]);
+        let pairs = chain.detect_reused_signatures();
+        assert_eq!(pairs.len(), 3);
+        for pair in [
+            (tx_2.hash.clone(), tx_3.hash.clone()),
+            (tx_2.hash.clone(), tx_4.hash.clone()),
+            (tx_3.hash.clone(), tx_4.hash.clone()),
+        ] {
+            assert!(pairs.contains(&pair));
+        }
+    }
+
     #[test]
     fn tx_output_value() {
         let key = KeyPair::new();
         let tx = Transaction::new(TransactionData::new(
             Vec::new(),
-            vec![Output {
-                value: 10000,
-                pubkey: key.public_key().clone(),
-            }],
+            vec![Output::new(10000, key.public_key().clone())],
         ));
 
         assert_eq!(Blockchain::get_tx_output_value(&tx.data.outputs), 10000);
@@ -365,20 +769,13 @@ mod tests {
                 hash: Hash::new(b"test"),
                 index: 0,
                 signature: key.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
             vec![
-                Output {
-                    value: 10,
-                    pubkey: key.public_key(),
-                },
-                Output {
-                    value: 5,
-                    pubkey: key.public_key(),
-                },
-                Output {
-                    value: 62,
-                    pubkey: key.public_key(),
-                },
+                Output::new(10, key.public_key()),
+                Output::new(5, key.public_key()),
+                Output::new(62, key.public_key()),
             ],
         ));
         assert_eq!(Blockchain::get_tx_output_value(&tx.data.outputs), 77);
@@ -397,11 +794,10 @@ mod tests {
                     hash: hash.clone(),
                     index: 0,
                     signature: key.sign(hash.digest()),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 }],
-                vec![Output {
-                    value: value,
-                    pubkey: key.public_key(),
-                }],
+                vec![Output::new(value, key.public_key())],
             ))
         };
 
@@ -454,16 +850,12 @@ mod tests {
                         hash: coinbase_hash.clone(),
                         index: 0,
                         signature: key.sign(coinbase_hash.digest()),
+                        sequence: Input::SEQUENCE_FINAL,
+                        reveal: None,
                     }],
                     vec![
-                        Output {
-                            value: 5000,
-                            pubkey: key.public_key(),
-                        },
-                        Output {
-                            value: 4000,
-                            pubkey: key.public_key(),
-                        },
+                        Output::new(5000, key.public_key()),
+                        Output::new(4000, key.public_key()),
                     ],
                 )),
                 new_coinbase_tx(&key.public_key(), coinbase_value, 1),
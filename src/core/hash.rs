@@ -4,13 +4,18 @@
 //! tampering of the data.
 //!
 
-use crate::traits::io::{ByteIO, JsonIO};
+use crate::consensus::Target;
+use crate::traits::io::{ByteIO, IOError, JsonIO};
 use ethnum::U256;
 use serde::de;
 use serde::ser::SerializeTuple;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha2::{Digest, Sha256};
 use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::ops::Range;
+use std::path::Path;
 
 #[derive(Debug)]
 pub struct HashDeserializeError;
@@ -21,6 +26,8 @@ impl fmt::Display for HashDeserializeError {
     }
 }
 
+impl std::error::Error for HashDeserializeError {}
+
 /// The hash structure is a 32 byte array representing the SHA256 hash value
 /// of some arbitrary data.
 ///
@@ -41,12 +48,95 @@ impl Hash {
         }
     }
 
+    /// Hashes all the data read from the reader, in chunks of 8 KB
+    ///
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Hash, IOError> {
+        let mut hasher = Sha256::new();
+        let mut buffer = [0; 8192];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(count) => hasher.update(&buffer[..count]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return Err(IOError::FileOperationFailed),
+            }
+        }
+        Ok(Hash {
+            value: hasher.finalize().into(),
+        })
+    }
+
+    /// Hashes the contents of a file without loading it entirely in memory
+    ///
+    pub fn from_file(path: &Path) -> Result<Hash, IOError> {
+        match File::open(path) {
+            Ok(file) => Hash::from_reader(file),
+            Err(_) => Err(IOError::FileOperationFailed),
+        }
+    }
+
     pub fn digest(&self) -> &[u8; Hash::SIZE] {
         &self.value
     }
 
     pub fn is_zero(&self) -> bool {
-        U256::from_be_bytes(self.value.clone()) == U256::from(0_u32)
+        self.to_u256() == U256::ZERO
+    }
+
+    /// Interprets the hash value as a big endian 256 bit integer
+    ///
+    pub fn to_u256(&self) -> U256 {
+        U256::from_be_bytes(self.value)
+    }
+
+    pub fn from_u256(n: U256) -> Hash {
+        Hash {
+            value: n.to_be_bytes(),
+        }
+    }
+
+    /// Returns the hash whose integer value is this hash's value + 1,
+    /// or None if the value overflows.
+    ///
+    pub fn add_one(&self) -> Option<Hash> {
+        self.to_u256().checked_add(U256::ONE).map(Hash::from_u256)
+    }
+
+    /// Hashes the inputs built from each counter value in the range, until a hash
+    /// satisfying the target is found. Returns the counter value and the hash, or
+    /// None if the range is exhausted.
+    ///
+    /// This is the Proof of Work primitive: the input function defines how the
+    /// counter (e.g. a nonce) is embedded in the hashed data.
+    ///
+    pub fn find_below_target<F>(
+        target: &Target,
+        counters: Range<u64>,
+        mut input: F,
+    ) -> Option<(u64, Hash)>
+    where
+        F: FnMut(u64) -> Vec<u8>,
+    {
+        for counter in counters {
+            let hash = Hash::new(&input(counter));
+            if Target::from_hash(&hash) <= *target {
+                return Some((counter, hash));
+            }
+        }
+        None
+    }
+
+    /// Searches for an input made of the prefix followed by a big endian u64 counter,
+    /// whose hash satisfies the target. At most max_attempts counter values are tried.
+    ///
+    pub fn find_input_with_prefix(
+        prefix: &[u8],
+        target: &Target,
+        max_attempts: u64,
+    ) -> Option<(Vec<u8>, Hash)> {
+        let input = |counter: u64| [prefix, &counter.to_be_bytes()].concat();
+        Hash::find_below_target(target, 0..max_attempts, input)
+            .map(|(counter, hash)| (input(counter), hash))
     }
 
     pub fn to_hex_str(&self) -> String {
@@ -135,6 +225,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn from_file() {
+        use std::io::Write;
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"abc").unwrap();
+        assert_eq!(
+            Hash::from_file(file.path()).unwrap().to_hex_str(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        // Larger than a chunk
+        let data: Vec<u8> = (0..20000).map(|i| i as u8).collect();
+        file.write_all(&data).unwrap();
+        assert_eq!(
+            Hash::from_file(file.path()).unwrap(),
+            Hash::new(&[b"abc".as_slice(), &data].concat())
+        );
+        assert_eq!(
+            Hash::from_reader(data.as_slice()).unwrap(),
+            Hash::new(&data)
+        );
+        assert_eq!(Hash::from_reader(&[][..]).unwrap(), Hash::new(&[]));
+
+        let missing = file.path().with_extension("missing");
+        assert!(matches!(
+            Hash::from_file(&missing),
+            Err(IOError::FileOperationFailed)
+        ));
+    }
+
     #[test]
     fn byte_io() {
         let bytes = vec![
@@ -158,6 +279,56 @@ mod tests {
         assert!(!Hash::from_bytes(&bytes).unwrap().is_zero());
     }
 
+    #[test]
+    fn u256() {
+        let hash = Hash::new(b"test");
+        assert_eq!(
+            hash.to_u256(),
+            U256::from_str_hex(
+                "0x9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+            )
+            .unwrap()
+        );
+        assert_eq!(Hash::from_u256(hash.to_u256()), hash);
+        assert_eq!(Hash::from_u256(U256::ZERO), Hash::default());
+
+        assert_eq!(
+            hash.add_one().unwrap().to_hex_str(),
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a09"
+        );
+        assert_eq!(
+            Hash::from_u256(U256::from(0xff_u32))
+                .add_one()
+                .unwrap()
+                .digest()[30..],
+            [1, 0]
+        );
+        assert!(Hash::from_u256(U256::MAX).add_one().is_none());
+    }
+
+    #[test]
+    fn find_input_with_prefix() {
+        let target = Target::from_leading_zeros(8);
+        let (input, hash) = Hash::find_input_with_prefix(b"test", &target, 100000).unwrap();
+        assert!(input.starts_with(b"test"));
+        assert_eq!(input.len(), 4 + 8);
+        assert_eq!(Hash::new(&input), hash);
+        assert!(Target::from_hash(&hash) <= target);
+        assert!(Target::from_hash(&hash).leading_zeros() >= 8);
+
+        assert!(Hash::find_input_with_prefix(b"test", &Target::MAX, 0).is_none());
+        assert!(
+            Hash::find_input_with_prefix(b"test", &Target::from_leading_zeros(255), 1000).is_none()
+        );
+
+        let (counter, hash) = Hash::find_below_target(&Target::MAX, 5..10, |counter| {
+            counter.to_be_bytes().to_vec()
+        })
+        .unwrap();
+        assert_eq!(counter, 5);
+        assert_eq!(hash, Hash::new(&5_u64.to_be_bytes()));
+    }
+
     #[test]
     fn hex() {
         let hash = Hash::new(b"test");
@@ -6,35 +6,78 @@
 //! can be assigned.
 //!
 
+use crate::core::mnemonic::{self, MnemonicError};
+use crate::core::script::Script;
 use crate::traits::io::{ByteIO, FileIO, IOError};
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
 use ed25519_dalek::{
     Signature as DalekSignature, Signer, SigningKey, Verifier as DalekVerifier, VerifyingKey,
-    PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH,
+    PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH, SIGNATURE_LENGTH,
 };
 use hex;
 use rand::rngs::OsRng;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fmt;
+use std::fs;
+use std::path::Path;
 
 pub trait Verifier {
     fn verify(&self, message: &[u8], signature: &Signature) -> bool;
 }
 
+/// Version of the encrypted key file format
+const ENCRYPTED_KEY_VERSION: u8 = 1;
+/// Argon2id memory cost in KiB, iterations and parallelism of the key files. Files
+/// with other parameters are rejected, so that they can't make decryption arbitrarily
+/// expensive.
+const ARGON2_PARAMS: (u32, u32, u32) = (65536, 3, 4);
+/// Format version followed by the three Argon2id parameters
+const ENCRYPTED_KEY_HEADER_LENGTH: usize = 1 + 3 * 4;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
+const ENCRYPTED_KEY_LENGTH: usize =
+    ENCRYPTED_KEY_HEADER_LENGTH + SALT_LENGTH + NONCE_LENGTH + SECRET_KEY_LENGTH + TAG_LENGTH;
+
+/// Version byte prepended to public keys in Base58Check addresses
+const ADDRESS_VERSION: u8 = 0x00;
+const ADDRESS_CHECKSUM_LENGTH: usize = 4;
+
 /// A private key representation
 pub type PrivateKey = [u8; SECRET_KEY_LENGTH];
 
 #[derive(Debug)]
 pub struct PubkeyDeserializeError;
 
+impl fmt::Display for PubkeyDeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Public key deserialization error")
+    }
+}
+
+impl std::error::Error for PubkeyDeserializeError {}
+
 /// A public key representation.
 ///
 /// Functions are provided for verifying signatures and for serialization.
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct PublicKey {
     value: [u8; PUBLIC_KEY_LENGTH],
 }
 
 impl PublicKey {
+    pub fn new(value: [u8; PUBLIC_KEY_LENGTH]) -> PublicKey {
+        PublicKey { value }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.value
+    }
+
     pub fn to_hex_str(&self) -> String {
         hex::encode(self.value)
     }
@@ -49,6 +92,51 @@ impl PublicKey {
             Err(_) => Err(PubkeyDeserializeError),
         }
     }
+
+    /// Encodes the key in Base58Check: the version byte and the key are followed by
+    /// the first 4 bytes of their double SHA-256 as checksum
+    ///
+    pub fn to_address(&self) -> String {
+        let mut bytes = vec![ADDRESS_VERSION];
+        bytes.extend_from_slice(&self.value);
+        let checksum = address_checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bs58::encode(bytes).into_string()
+    }
+
+    /// Decodes an address built with [to_address](PublicKey::to_address), verifying
+    /// the version and the checksum
+    ///
+    pub fn from_address(string: &str) -> Result<PublicKey, PubkeyDeserializeError> {
+        let bytes = match bs58::decode(string).into_vec() {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(PubkeyDeserializeError),
+        };
+        if bytes.len() != 1 + PUBLIC_KEY_LENGTH + ADDRESS_CHECKSUM_LENGTH
+            || bytes[0] != ADDRESS_VERSION
+        {
+            return Err(PubkeyDeserializeError);
+        }
+        let (payload, checksum) = bytes.split_at(1 + PUBLIC_KEY_LENGTH);
+        if address_checksum(payload) != checksum {
+            return Err(PubkeyDeserializeError);
+        }
+        Ok(PublicKey {
+            value: payload[1..].try_into().unwrap(),
+        })
+    }
+
+    /// The address of the P2SH output paying to this key, i.e. the hash of the
+    /// [P2PK script](Script::p2pk) of the key
+    ///
+    pub fn to_p2sh_address(&self) -> String {
+        Script::p2pk(self).to_hash().to_hex_str()
+    }
+}
+
+fn address_checksum(payload: &[u8]) -> [u8; ADDRESS_CHECKSUM_LENGTH] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    hash[..ADDRESS_CHECKSUM_LENGTH].try_into().unwrap()
 }
 
 impl Verifier for PublicKey {
@@ -72,6 +160,12 @@ impl fmt::Debug for PublicKey {
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Signature(DalekSignature);
 
+impl Signature {
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_LENGTH] {
+        self.0.to_bytes()
+    }
+}
+
 /// A key pair representation
 ///
 /// It can both sign data and verify such signatures.
@@ -85,6 +179,117 @@ impl KeyPair {
         KeyPair(SigningKey::generate(&mut csprng))
     }
 
+    pub fn from_private_key(key: &PrivateKey) -> KeyPair {
+        KeyPair(SigningKey::from_bytes(key))
+    }
+
+    /// Derives a key pair from a BIP39 mnemonic, through the mnemonic seed.
+    /// See [mnemonic].
+    ///
+    pub fn from_mnemonic(words: &[&str]) -> Result<KeyPair, MnemonicError> {
+        let entropy = mnemonic::mnemonic_to_entropy(words)?;
+        Ok(mnemonic::seed_to_keypair(&mnemonic::entropy_to_seed(
+            &entropy,
+        )?))
+    }
+
+    /// Generates a random 24 words mnemonic and the key pair derived from it with
+    /// [from_mnemonic](KeyPair::from_mnemonic)
+    ///
+    pub fn new_with_mnemonic() -> (KeyPair, Vec<&'static str>) {
+        let mut entropy = [0_u8; 32];
+        OsRng.fill_bytes(&mut entropy);
+        let words = mnemonic::entropy_to_mnemonic(&entropy).unwrap();
+        (KeyPair::from_mnemonic(&words).unwrap(), words)
+    }
+
+    /// Encodes the private key as 24 BIP39 words, using it directly as entropy.
+    /// These are not a mnemonic the key pair is derived from: they can only be
+    /// restored with [from_private_key_words](KeyPair::from_private_key_words).
+    ///
+    pub fn private_key_to_words(&self) -> Vec<&'static str> {
+        mnemonic::entropy_to_mnemonic(&self.private_key()).unwrap()
+    }
+
+    /// Restores a key pair from the words of
+    /// [private_key_to_words](KeyPair::private_key_to_words)
+    ///
+    pub fn from_private_key_words(words: &[&str]) -> Result<KeyPair, MnemonicError> {
+        let entropy = mnemonic::mnemonic_to_entropy(words)?;
+        match PrivateKey::try_from(entropy.as_slice()) {
+            Ok(key) => Ok(KeyPair::from_private_key(&key)),
+            Err(_) => Err(MnemonicError::InvalidLength),
+        }
+    }
+
+    /// Stores the private key encrypted with AES-256-GCM, using a key derived from the
+    /// password with Argon2id. The file contains the format version, the Argon2id
+    /// memory cost, iterations and parallelism as little endian u32, the salt, the
+    /// nonce, the ciphertext and the authentication tag.
+    ///
+    pub fn to_encrypted_file(&self, path: &Path, password: &str) -> Result<(), IOError> {
+        let (memory, iterations, parallelism) = ARGON2_PARAMS;
+        let mut salt = [0_u8; SALT_LENGTH];
+        let mut nonce = [0_u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let cipher = match encryption_cipher(password, &salt) {
+            Some(cipher) => cipher,
+            None => return Err(IOError::SerializationFailed),
+        };
+        let ciphertext =
+            match cipher.encrypt(Nonce::from_slice(&nonce), self.0.as_bytes().as_slice()) {
+                Ok(ciphertext) => ciphertext,
+                Err(_) => return Err(IOError::SerializationFailed),
+            };
+
+        let mut bytes = Vec::with_capacity(ENCRYPTED_KEY_LENGTH);
+        bytes.push(ENCRYPTED_KEY_VERSION);
+        for param in [memory, iterations, parallelism] {
+            bytes.extend_from_slice(&param.to_le_bytes());
+        }
+        bytes.extend_from_slice(&salt);
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+        match fs::write(path, bytes) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(IOError::FileOperationFailed),
+        }
+    }
+
+    /// Reads a private key stored with [to_encrypted_file](KeyPair::to_encrypted_file).
+    /// Fails with DeserializationFailed if the password is wrong, the file is corrupted
+    /// or it uses Argon2id parameters other than the ones of its format version.
+    ///
+    pub fn from_encrypted_file(path: &Path, password: &str) -> Result<KeyPair, IOError> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(IOError::FileOperationFailed),
+        };
+        if bytes.len() != ENCRYPTED_KEY_LENGTH || bytes[0] != ENCRYPTED_KEY_VERSION {
+            return Err(IOError::DeserializationFailed);
+        }
+        let param = |index: usize| {
+            let start = 1 + 4 * index;
+            u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap())
+        };
+        if (param(0), param(1), param(2)) != ARGON2_PARAMS {
+            return Err(IOError::DeserializationFailed);
+        }
+        let (salt, rest) = bytes[ENCRYPTED_KEY_HEADER_LENGTH..].split_at(SALT_LENGTH);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+
+        let cipher = match encryption_cipher(password, salt) {
+            Some(cipher) => cipher,
+            None => return Err(IOError::DeserializationFailed),
+        };
+        match cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+            Ok(private_key) => KeyPair::from_bytes(&private_key),
+            Err(_) => Err(IOError::DeserializationFailed),
+        }
+    }
+
     pub fn private_key(&self) -> PrivateKey {
         self.0.as_bytes().clone()
     }
@@ -100,6 +305,18 @@ impl KeyPair {
     }
 }
 
+/// Derives the AES-256-GCM key from the password with Argon2id
+///
+fn encryption_cipher(password: &str, salt: &[u8]) -> Option<Aes256Gcm> {
+    let (memory, iterations, parallelism) = ARGON2_PARAMS;
+    let params = Params::new(memory, iterations, parallelism, Some(32)).ok()?;
+    let mut key = [0_u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .ok()?;
+    Aes256Gcm::new_from_slice(&key).ok()
+}
+
 impl Verifier for KeyPair {
     fn verify(&self, message: &[u8], signature: &Signature) -> bool {
         match self.0.verify(message, &signature.0) {
@@ -128,6 +345,51 @@ impl ByteIO for KeyPair {
 
 impl FileIO for KeyPair {}
 
+/// A list of key pairs stored in a single file, e.g. for wallets using many addresses
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Keyring {
+    keys: Vec<KeyPair>,
+}
+
+impl Keyring {
+    pub fn new() -> Keyring {
+        Keyring::default()
+    }
+
+    pub fn add(&mut self, key: KeyPair) {
+        self.keys.push(key);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&KeyPair> {
+        self.keys.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn keys(&self) -> &[KeyPair] {
+        &self.keys
+    }
+
+    /// Returns the first key pair with the given public key, with its index
+    ///
+    pub fn find_by_pubkey(&self, pubkey: &PublicKey) -> Option<(usize, &KeyPair)> {
+        self.keys
+            .iter()
+            .enumerate()
+            .find(|(_, key)| key.public_key() == *pubkey)
+    }
+}
+
+impl ByteIO for Keyring {}
+impl FileIO for Keyring {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -141,6 +403,110 @@ mod tests {
         assert_eq!(key.verify(b"test", &signature), true);
     }
 
+    #[test]
+    fn address() {
+        let key = KeyPair::new().public_key();
+        let address = key.to_address();
+        assert_eq!(PublicKey::from_address(&address).unwrap(), key);
+        assert!(address.len() < key.to_hex_str().len());
+
+        // Base58Check with the 0x00 version starts with a 1
+        let zero = PublicKey::new([0; PUBLIC_KEY_LENGTH]);
+        assert!(zero.to_address().starts_with('1'));
+        assert_eq!(PublicKey::from_address(&zero.to_address()).unwrap(), zero);
+
+        assert!(PublicKey::from_address(&key.to_hex_str()).is_err());
+        assert!(PublicKey::from_address("0OIl").is_err());
+        assert!(PublicKey::from_address("").is_err());
+
+        // A changed character breaks the checksum
+        let mut chars: Vec<char> = address.chars().collect();
+        let last = chars.len() - 1;
+        chars[last] = if chars[last] == '2' { '3' } else { '2' };
+        let tampered: String = chars.into_iter().collect();
+        assert!(PublicKey::from_address(&tampered).is_err());
+    }
+
+    #[test]
+    fn p2sh_address() {
+        let key = KeyPair::new().public_key();
+        let address = key.to_p2sh_address();
+        assert_eq!(address, Script::p2pk(&key).to_hash().to_hex_str());
+        assert_ne!(address, KeyPair::new().public_key().to_p2sh_address());
+    }
+
+    #[test]
+    fn mnemonic() {
+        let words: Vec<&str> =
+            "legal winner thank year wave sausage worth useful legal winner thank yellow"
+                .split(' ')
+                .collect();
+        let key = KeyPair::from_mnemonic(&words).unwrap();
+        assert_eq!(
+            hex::encode(key.private_key()),
+            "878386efb78845b3355bd15ea4d39ef97d179cb712b77d5c12b6be415fffeffe"
+        );
+        assert!(KeyPair::from_mnemonic(&words[..11]).is_err());
+
+        let words = key.private_key_to_words();
+        assert_eq!(words.len(), 24);
+        assert_eq!(KeyPair::from_private_key_words(&words).unwrap(), key);
+        assert_ne!(KeyPair::from_mnemonic(&words).unwrap(), key);
+        assert_eq!(
+            KeyPair::from_private_key_words(&words[..12]),
+            Err(MnemonicError::InvalidLength)
+        );
+
+        let (key, words) = KeyPair::new_with_mnemonic();
+        assert_eq!(words.len(), 24);
+        assert_eq!(KeyPair::from_mnemonic(&words).unwrap(), key);
+        assert_ne!(KeyPair::new_with_mnemonic().1, words);
+    }
+
+    #[test]
+    fn encrypted_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("key.enc");
+        let key = KeyPair::new();
+
+        assert!(key.to_encrypted_file(&path, "password").is_ok());
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), ENCRYPTED_KEY_LENGTH);
+        assert_eq!(bytes[0], ENCRYPTED_KEY_VERSION);
+        assert!(!bytes
+            .windows(SECRET_KEY_LENGTH)
+            .any(|window| window == key.private_key()));
+
+        assert_eq!(
+            KeyPair::from_encrypted_file(&path, "password").unwrap(),
+            key
+        );
+        assert!(matches!(
+            KeyPair::from_encrypted_file(&path, "wrong"),
+            Err(IOError::DeserializationFailed)
+        ));
+
+        let mut expensive = bytes.clone();
+        expensive[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, expensive).unwrap();
+        assert!(matches!(
+            KeyPair::from_encrypted_file(&path, "password"),
+            Err(IOError::DeserializationFailed)
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[ENCRYPTED_KEY_LENGTH - 1] ^= 1;
+        fs::write(&path, corrupted).unwrap();
+        assert!(matches!(
+            KeyPair::from_encrypted_file(&path, "password"),
+            Err(IOError::DeserializationFailed)
+        ));
+        assert!(matches!(
+            KeyPair::from_encrypted_file(&dir.path().join("missing"), "password"),
+            Err(IOError::FileOperationFailed)
+        ));
+    }
+
     #[test]
     fn serialize() {
         let bytes = [0u8; 32];
@@ -149,6 +515,30 @@ mod tests {
         assert_eq!(serialized, bytes);
     }
 
+    #[test]
+    fn keyring() {
+        let mut keyring = Keyring::new();
+        assert!(keyring.is_empty());
+        let keys: Vec<KeyPair> = (0..3).map(|_| KeyPair::new()).collect();
+        for key in keys.iter() {
+            keyring.add(key.clone());
+        }
+        assert_eq!(keyring.len(), 3);
+        assert_eq!(keyring.get(1), Some(&keys[1]));
+        assert_eq!(keyring.get(3), None);
+        assert_eq!(
+            keyring.find_by_pubkey(&keys[2].public_key()),
+            Some((2, &keys[2]))
+        );
+        assert_eq!(keyring.find_by_pubkey(&KeyPair::new().public_key()), None);
+
+        let file = NamedTempFile::new().unwrap();
+        assert!(keyring.to_file(file.path()).is_ok());
+        let loaded = Keyring::from_file(file.path()).unwrap();
+        assert_eq!(loaded, keyring);
+        assert_eq!(loaded.keys(), keys.as_slice());
+    }
+
     #[test]
     fn deserialize() {
         let bytes = vec![0u8; 32];
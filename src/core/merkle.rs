@@ -0,0 +1,198 @@
+//! Merkle tree of transaction hashes
+//!
+//! The top hash of a [block](crate::core::block::Block) is the root of a binary tree
+//! built on the hashes of its transactions, padded with zero hashes to the next power
+//! of two: every node is the hash of its two children concatenated.
+//!
+//! This allows proving that a transaction is in a block by providing only the sibling
+//! hashes on the path from the transaction to the root.
+//!
+
+use crate::core::hash::Hash;
+use serde::{Deserialize, Serialize};
+
+/// The side of a sibling in a Merkle proof, relative to the node being verified
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum MerkleSide {
+    Left,
+    Right,
+}
+
+/// The sibling path from a leaf to the root of a Merkle tree, together with the root.
+/// It proves that the leaf is in the tree knowing only the root, e.g. the top hash
+/// of a block header.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MerkleProof {
+    pub path: Vec<(Hash, MerkleSide)>,
+    pub root: Hash,
+}
+
+impl MerkleProof {
+    /// Recomputes the root from the leaf and the path, and compares it with the root
+    ///
+    pub fn verify(&self, leaf: &Hash) -> bool {
+        let computed = self
+            .path
+            .iter()
+            .fold(leaf.clone(), |acc, (sibling, side)| match side {
+                MerkleSide::Left => hash_pair(sibling, &acc),
+                MerkleSide::Right => hash_pair(&acc, sibling),
+            });
+        computed == self.root
+    }
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    Hash::new(&[left.digest().as_slice(), right.digest().as_slice()].concat())
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| hash_pair(&pair[0], &pair[1]))
+        .collect()
+}
+
+/// A Merkle tree storing all its levels, from the leaves to the root.
+///
+/// The leaves are padded with zero hashes to the next power of two, so that every
+/// node has a sibling. The tree of an empty list has no leaves and the hash of no
+/// data as root.
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Hash>>,
+    leaf_count: usize,
+}
+
+impl MerkleTree {
+    pub fn new(hashes: &[Hash]) -> MerkleTree {
+        if hashes.is_empty() {
+            return MerkleTree {
+                levels: vec![vec![], vec![Hash::new(&[])]],
+                leaf_count: 0,
+            };
+        }
+        let mut leaves = hashes.to_vec();
+        leaves.resize(hashes.len().next_power_of_two(), Hash::default());
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            levels.push(next_level(&levels[levels.len() - 1]));
+        }
+        MerkleTree {
+            levels,
+            leaf_count: hashes.len(),
+        }
+    }
+
+    pub fn root(&self) -> &Hash {
+        &self.levels[self.levels.len() - 1][0]
+    }
+
+    /// Returns the levels from the leaves, padding included, to the root
+    ///
+    pub fn levels(&self) -> &[Vec<Hash>] {
+        &self.levels
+    }
+
+    /// Returns the proof for the leaf at the given index. Padding leaves have no proof.
+    ///
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_count {
+            return None;
+        }
+        let mut path = Vec::new();
+        let mut index = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if index % 2 == 1 {
+                path.push((level[index - 1].clone(), MerkleSide::Left));
+            } else {
+                path.push((level[index + 1].clone(), MerkleSide::Right));
+            }
+            index /= 2;
+        }
+        Some(MerkleProof {
+            path,
+            root: self.root().clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<Hash> {
+        (0..count)
+            .map(|i| Hash::new(format!("tx-{}", i).as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn root() {
+        let hashes = leaves(3);
+        assert_eq!(*MerkleTree::new(&[]).root(), Hash::new(&[]));
+        assert_eq!(*MerkleTree::new(&hashes[..1]).root(), hashes[0]);
+        assert_eq!(
+            *MerkleTree::new(&hashes[..2]).root(),
+            hash_pair(&hashes[0], &hashes[1])
+        );
+
+        // The third leaf is paired with a zero hash
+        let root = MerkleTree::new(&hashes).root().clone();
+        assert_eq!(
+            root,
+            hash_pair(
+                &hash_pair(&hashes[0], &hashes[1]),
+                &hash_pair(&hashes[2], &Hash::default())
+            )
+        );
+        assert_eq!(
+            root,
+            Hash::from_hex_str("a25ea0fbcc03ac3b65ca1e40a23130e418fa1691d0916b87cfa88b8dfecefbf5")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn tree() {
+        let hashes = leaves(5);
+        let tree = MerkleTree::new(&hashes);
+        let levels = tree.levels();
+        assert_eq!(levels.len(), 4);
+        assert_eq!(levels[0].len(), 8);
+        assert_eq!(levels[0][..5], hashes);
+        assert!(levels[0][5..].iter().all(|hash| hash.is_zero()));
+        assert_eq!(levels[1].len(), 4);
+        assert_eq!(levels[2].len(), 2);
+        assert_eq!(levels[3].len(), 1);
+
+        let empty = MerkleTree::new(&[]);
+        assert_eq!(*empty.root(), Hash::new(&[]));
+        assert!(empty.proof(0).is_none());
+        assert_eq!(MerkleTree::new(&hashes[..1]).proof(0).unwrap().path, vec![]);
+    }
+
+    #[test]
+    fn proof_verify() {
+        for count in 1..10 {
+            let hashes = leaves(count);
+            let tree = MerkleTree::new(&hashes);
+            for (index, hash) in hashes.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert_eq!(proof.root, *tree.root());
+                assert_eq!(proof.path.len(), tree.levels().len() - 1);
+                assert!(proof.verify(hash));
+                assert!(!proof.verify(&Hash::new(b"other")));
+
+                let mut wrong_root = proof.clone();
+                wrong_root.root = Hash::new(b"other");
+                assert!(!wrong_root.verify(hash));
+            }
+            // Padding leaves are not part of the tree
+            assert!(tree.proof(count).is_none());
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Mnemonic phrases for backing up keys
+//!
+//! A mnemonic is a list of words from a fixed dictionary that encodes some entropy
+//! plus a checksum, as specified by BIP39. The entropy is turned into a seed using
+//! PBKDF2-HMAC-SHA512, and the first half of the seed is used as a private key.
+//!
+//! The English BIP39 wordlist is used, so mnemonics are compatible with other wallets
+//! up to the seed. Key derivation from the seed is specific to this implementation.
+//!
+
+use crate::core::keys::KeyPair;
+use ring::pbkdf2;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::num::NonZeroU32;
+use std::sync::OnceLock;
+
+const WORDLIST: &str = include_str!("bip39_english.txt");
+const PBKDF2_ITERATIONS: u32 = 2048;
+pub const SEED_LENGTH: usize = 64;
+
+/// Errors returned when decoding mnemonics
+#[derive(PartialEq, Debug)]
+pub enum MnemonicError {
+    InvalidLength,
+    UnknownWord,
+    InvalidChecksum,
+}
+
+impl fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Mnemonic error: {}",
+            match self {
+                MnemonicError::InvalidLength => "invalid length",
+                MnemonicError::UnknownWord => "unknown word",
+                MnemonicError::InvalidChecksum => "invalid checksum",
+            }
+        )
+    }
+}
+
+impl std::error::Error for MnemonicError {}
+
+fn wordlist() -> &'static [&'static str] {
+    static LIST: OnceLock<Vec<&'static str>> = OnceLock::new();
+    LIST.get_or_init(|| WORDLIST.lines().collect())
+}
+
+/// Encodes the entropy (16 to 32 bytes, in steps of 4) into a list of words,
+/// 3 words for every 4 bytes.
+///
+pub fn entropy_to_mnemonic(entropy: &[u8]) -> Result<Vec<&'static str>, MnemonicError> {
+    if entropy.len() < 16 || entropy.len() > 32 || !entropy.len().is_multiple_of(4) {
+        return Err(MnemonicError::InvalidLength);
+    }
+
+    // Every byte of the checksum covers 8 words, at most 1 byte is needed
+    let checksum_bits = entropy.len() / 4;
+    let mut bits: Vec<bool> = entropy
+        .iter()
+        .chain(Sha256::digest(entropy).iter().take(1))
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+        .collect();
+    bits.truncate(entropy.len() * 8 + checksum_bits);
+
+    Ok(bits
+        .chunks(11)
+        .map(|chunk| {
+            let index = chunk
+                .iter()
+                .fold(0_usize, |acc, bit| (acc << 1) | *bit as usize);
+            wordlist()[index]
+        })
+        .collect())
+}
+
+/// Decodes a list of words into the original entropy, verifying the checksum
+///
+pub fn mnemonic_to_entropy(words: &[&str]) -> Result<Vec<u8>, MnemonicError> {
+    if words.len() < 12 || words.len() > 24 || !words.len().is_multiple_of(3) {
+        return Err(MnemonicError::InvalidLength);
+    }
+
+    let mut bits = Vec::<bool>::with_capacity(words.len() * 11);
+    for word in words {
+        let index = match wordlist().binary_search(word) {
+            Ok(index) => index,
+            Err(_) => return Err(MnemonicError::UnknownWord),
+        };
+        bits.extend((0..11).rev().map(|i| (index >> i) & 1 == 1));
+    }
+
+    let entropy_bits = words.len() * 11 * 32 / 33;
+    let entropy: Vec<u8> = bits[..entropy_bits]
+        .chunks(8)
+        .map(|chunk| chunk.iter().fold(0_u8, |acc, bit| (acc << 1) | *bit as u8))
+        .collect();
+
+    if entropy_to_mnemonic(&entropy)? != words {
+        return Err(MnemonicError::InvalidChecksum);
+    }
+    Ok(entropy)
+}
+
+/// Computes the seed from a list of words and an optional passphrase, using
+/// PBKDF2-HMAC-SHA512 with "mnemonic" + passphrase as salt.
+///
+pub fn mnemonic_to_seed(words: &[&str], passphrase: &str) -> [u8; SEED_LENGTH] {
+    let mut seed = [0; SEED_LENGTH];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA512,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        format!("mnemonic{}", passphrase).as_bytes(),
+        words.join(" ").as_bytes(),
+        &mut seed,
+    );
+    seed
+}
+
+/// Computes the seed of the mnemonic encoding the entropy, without passphrase
+///
+pub fn entropy_to_seed(entropy: &[u8]) -> Result<[u8; SEED_LENGTH], MnemonicError> {
+    Ok(mnemonic_to_seed(&entropy_to_mnemonic(entropy)?, ""))
+}
+
+/// Builds a key pair using the first 32 bytes of the seed as private key
+///
+pub fn seed_to_keypair(seed: &[u8; SEED_LENGTH]) -> KeyPair {
+    KeyPair::from_private_key(seed[..32].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wordlist() {
+        let list = super::wordlist();
+        assert_eq!(list.len(), 2048);
+        assert_eq!(list[0], "abandon");
+        assert_eq!(list[2047], "zoo");
+        assert!(list.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn vectors() {
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+                "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad",
+            ),
+        ];
+
+        for (entropy, mnemonic, seed) in vectors {
+            let entropy = hex::decode(entropy).unwrap();
+            let words: Vec<&str> = mnemonic.split(' ').collect();
+            assert_eq!(entropy_to_mnemonic(&entropy).unwrap(), words);
+            assert_eq!(mnemonic_to_entropy(&words).unwrap(), entropy);
+            assert_eq!(hex::encode(mnemonic_to_seed(&words, "TREZOR")), seed);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let words = ["abandon"; 12];
+        assert_eq!(
+            mnemonic_to_entropy(&words),
+            Err(MnemonicError::InvalidChecksum)
+        );
+        assert_eq!(
+            mnemonic_to_entropy(&words[..11]),
+            Err(MnemonicError::InvalidLength)
+        );
+
+        let mut words = words;
+        words[11] = "bitcoin";
+        assert_eq!(mnemonic_to_entropy(&words), Err(MnemonicError::UnknownWord));
+
+        assert_eq!(
+            entropy_to_mnemonic(&[0; 15]),
+            Err(MnemonicError::InvalidLength)
+        );
+        assert_eq!(entropy_to_seed(&[0; 33]), Err(MnemonicError::InvalidLength));
+    }
+
+    #[test]
+    fn seed_to_keypair() {
+        let seed = entropy_to_seed(&[0; 16]).unwrap();
+        assert_eq!(
+            hex::encode(seed),
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4"
+        );
+
+        let key = super::seed_to_keypair(&seed);
+        assert_eq!(key.private_key(), seed[..32]);
+    }
+}
@@ -5,5 +5,8 @@ pub mod block;
 pub mod blockchain;
 pub mod hash;
 pub mod keys;
+pub mod merkle;
+pub mod mnemonic;
+pub mod script;
 pub mod testing;
 pub mod transaction;
@@ -0,0 +1,151 @@
+//! Scripts for locking outputs to spending conditions
+//!
+//! A Pay-to-Script-Hash (P2SH) output stores only the hash of a script, see
+//! [with_script](crate::core::transaction::Output::with_script). It can be spent by an
+//! input that reveals the script and provides the signatures satisfying it.
+//!
+//! The supported scripts are:
+//! - P2PK: `<push 32> <pubkey> OP_CHECKSIG`, the key must sign
+//! - P2MS: `<threshold> <push 32> <pubkey>... <count> OP_CHECKMULTISIG`, at least
+//!   threshold keys out of count must sign
+//!
+
+use crate::core::hash::Hash;
+use crate::core::keys::{PublicKey, Signature, Verifier};
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
+use serde::{Deserialize, Serialize};
+
+const OP_PUSH_KEY: u8 = PUBLIC_KEY_LENGTH as u8;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+/// Script bytecode
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Script(pub Vec<u8>);
+
+/// The data provided by an input for spending a P2SH output: the script whose hash
+/// matches the output condition, and the signatures in addition to the input signature.
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ScriptReveal {
+    pub script: Script,
+    pub signatures: Vec<Signature>,
+}
+
+impl Script {
+    pub fn p2pk(pubkey: &PublicKey) -> Script {
+        let mut bytes = vec![OP_PUSH_KEY];
+        bytes.extend_from_slice(pubkey.as_bytes());
+        bytes.push(OP_CHECKSIG);
+        Script(bytes)
+    }
+
+    /// Builds a multisig script. The script is not valid unless the threshold is
+    /// between 1 and the number of keys, and there are at most 255 keys.
+    ///
+    pub fn p2ms(threshold: u8, pubkeys: &[PublicKey]) -> Script {
+        let mut bytes = vec![threshold];
+        for pubkey in pubkeys {
+            bytes.push(OP_PUSH_KEY);
+            bytes.extend_from_slice(pubkey.as_bytes());
+        }
+        bytes.push(pubkeys.len() as u8);
+        bytes.push(OP_CHECKMULTISIG);
+        Script(bytes)
+    }
+
+    pub fn to_hash(&self) -> Hash {
+        Hash::new(&self.0)
+    }
+
+    /// Decodes the script into the number of required signatures and the list of keys.
+    /// Returns None if the script is malformed.
+    ///
+    pub fn parse(&self) -> Option<(usize, Vec<PublicKey>)> {
+        match self.0.as_slice() {
+            [OP_PUSH_KEY, key @ .., OP_CHECKSIG] => Some((1, vec![parse_key(key)?])),
+            [threshold, keys @ .., count, OP_CHECKMULTISIG] => {
+                let keys = keys
+                    .chunks(PUBLIC_KEY_LENGTH + 1)
+                    .map(|chunk| match chunk {
+                        [OP_PUSH_KEY, key @ ..] => parse_key(key),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<PublicKey>>>()?;
+                let threshold = *threshold as usize;
+                if keys.len() != *count as usize || threshold == 0 || threshold > keys.len() {
+                    return None;
+                }
+                Some((threshold, keys))
+            }
+            _ => None,
+        }
+    }
+
+    /// Verifies that the signatures of the message satisfy the script. Each key can
+    /// provide at most one of the required signatures.
+    ///
+    pub fn verify(&self, message: &[u8], signatures: &[Signature]) -> bool {
+        let (threshold, keys) = match self.parse() {
+            Some(result) => result,
+            None => return false,
+        };
+        let signers = keys
+            .iter()
+            .filter(|key| {
+                signatures
+                    .iter()
+                    .any(|signature| key.verify(message, signature))
+            })
+            .count();
+        signers >= threshold
+    }
+}
+
+fn parse_key(bytes: &[u8]) -> Option<PublicKey> {
+    Some(PublicKey::new(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::keys::KeyPair;
+
+    #[test]
+    fn parse() {
+        let keys: Vec<PublicKey> = (0..3).map(|_| KeyPair::new().public_key()).collect();
+
+        assert_eq!(
+            Script::p2pk(&keys[0]).parse(),
+            Some((1, vec![keys[0].clone()]))
+        );
+        assert_eq!(Script::p2ms(2, &keys).parse(), Some((2, keys.clone())));
+
+        assert_eq!(Script::p2ms(0, &keys).parse(), None);
+        assert_eq!(Script::p2ms(4, &keys).parse(), None);
+        assert_eq!(Script(vec![]).parse(), None);
+        let mut script = Script::p2ms(2, &keys);
+        script.0.remove(1);
+        assert_eq!(script.parse(), None);
+    }
+
+    #[test]
+    fn verify() {
+        let keys: Vec<KeyPair> = (0..3).map(|_| KeyPair::new()).collect();
+        let pubkeys: Vec<PublicKey> = keys.iter().map(|key| key.public_key()).collect();
+        let message = b"message";
+        let signatures: Vec<Signature> = keys.iter().map(|key| key.sign(message)).collect();
+
+        let script = Script::p2pk(&pubkeys[0]);
+        assert!(script.verify(message, &signatures[..1]));
+        assert!(!script.verify(message, &signatures[1..]));
+        assert!(!script.verify(b"other", &signatures));
+
+        let script = Script::p2ms(2, &pubkeys);
+        assert!(script.verify(message, &signatures[1..]));
+        assert!(!script.verify(message, &signatures[..1]));
+        // The same key can't sign twice
+        assert!(!script.verify(message, &[signatures[0].clone(), signatures[0].clone()]));
+    }
+}
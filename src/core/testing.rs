@@ -56,12 +56,11 @@ impl Iterator for BlockGen {
                     hash: Hash::new(&name),
                     index: 0,
                     signature: self.keys.sign(&name),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 }],
                 (0..self.output_count)
-                    .map(|_| Output {
-                        value: self.output_value,
-                        pubkey: self.keys.public_key(),
-                    })
+                    .map(|_| Output::new(self.output_value, self.keys.public_key()))
                     .collect(),
             ))],
         ));
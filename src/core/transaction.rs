@@ -14,14 +14,74 @@
 //! transaction does not have inputs, only outputs.
 //!
 
+use crate::core::blockchain::{Blockchain, Height};
 use crate::core::hash::Hash;
-use crate::core::keys::{PublicKey, Signature};
+use crate::core::keys::{KeyPair, PublicKey, Signature, Verifier};
+use crate::core::script::{Script, ScriptReveal};
 use crate::traits::io::{ByteIO, FileIO, JsonIO};
+use crate::utxo::{IntoInputs, Utxo, UtxoError};
+use ed25519_dalek::PUBLIC_KEY_LENGTH;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Write;
 
 /// Utility type for representing coin value
 pub type Value = u64;
 
+/// Maximum number of bytes embedded in a data output, see [with_data](Output::with_data)
+pub const MAX_OUTPUT_DATA: usize = 80;
+
+/// Maximum number of outputs created by [split](Output::split)
+pub const MAX_SPLIT_OUTPUTS: u32 = 1000;
+
+/// Errors returned when splitting outputs
+#[derive(PartialEq, Debug)]
+pub enum SplitError {
+    InvalidCount,
+    ValueTooSmall,
+    TooManyOutputs,
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Split error: {}",
+            match self {
+                SplitError::InvalidCount => "invalid count",
+                SplitError::ValueTooSmall => "value too small",
+                SplitError::TooManyOutputs => "too many outputs",
+            }
+        )
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// Errors returned when consolidating outputs
+#[derive(PartialEq, Debug)]
+pub enum ConsolidateError {
+    NoOutputs,
+    KeyMismatch,
+    Overflow,
+}
+
+impl fmt::Display for ConsolidateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Consolidate error: {}",
+            match self {
+                ConsolidateError::NoOutputs => "no outputs",
+                ConsolidateError::KeyMismatch => "outputs assigned to different keys",
+                ConsolidateError::Overflow => "value overflow",
+            }
+        )
+    }
+}
+
+impl std::error::Error for ConsolidateError {}
+
 /// The input identifies coins to be consumed.
 ///
 /// An input points to a transaction through its [hash](struct@Hash), selects
@@ -29,19 +89,213 @@ pub type Value = u64;
 /// for proving that who is spending the coins is the actual recipient of
 /// the output.
 ///
+/// The sequence signals replace-by-fee: an input with a sequence lower than
+/// [SEQUENCE_FINAL](Input::SEQUENCE_FINAL) allows the transaction to be replaced in
+/// the miners' pools by a transaction paying a higher fee.
+///
+/// Inputs spending [P2SH outputs](Output::with_script) reveal the script, and the
+/// signature is checked against the script instead of the output public key.
+///
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Input {
     pub hash: Hash,
     pub index: u32,
     pub signature: Signature,
+    pub sequence: u32,
+    #[serde(default)]
+    pub reveal: Option<ScriptReveal>,
+}
+
+impl Input {
+    pub const SEQUENCE_FINAL: u32 = u32::MAX;
+
+    /// Serialized size of an input without script reveal
+    ///
+    pub fn serialized_size() -> usize {
+        TransactionData::INPUT_WEIGHT
+    }
+
+    /// Creates an input spending a P2SH output, signed by one of the script keys.
+    /// Other signatures required by the script can be added with
+    /// [add_script_signature](Input::add_script_signature).
+    ///
+    pub fn with_script_reveal(utxo: &Utxo, script: Script, key: &KeyPair) -> Input {
+        Input {
+            hash: utxo.hash.clone(),
+            index: utxo.output,
+            signature: key.sign(utxo.hash.digest()),
+            sequence: Input::SEQUENCE_FINAL,
+            reveal: Some(ScriptReveal {
+                script,
+                signatures: Vec::new(),
+            }),
+        }
+    }
+
+    /// Adds a signature to the revealed script. Returns false if no script is revealed.
+    ///
+    pub fn add_script_signature(&mut self, key: &KeyPair) -> bool {
+        match &mut self.reveal {
+            Some(reveal) => {
+                reveal.signatures.push(key.sign(self.hash.digest()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Verifies the signature against the output of the spent transaction, or against
+    /// the revealed script if the output is locked to a script. Returns false if the
+    /// spent transaction is not the one referenced by the input or has no such output.
+    ///
+    pub fn verify_signature(&self, spent_tx: &Transaction) -> bool {
+        let output = match spent_tx.data.outputs.get(self.index as usize) {
+            Some(output) if spent_tx.hash == self.hash => output,
+            _ => return false,
+        };
+        match (&output.condition, &self.reveal) {
+            (None, _) => output
+                .pubkey
+                .verify(spent_tx.hash.digest(), &self.signature),
+            (Some(condition), Some(reveal)) => {
+                let mut signatures = reveal.signatures.clone();
+                signatures.push(self.signature.clone());
+                reveal.script.to_hash() == *condition
+                    && reveal.script.verify(spent_tx.hash.digest(), &signatures)
+            }
+            (Some(_), None) => false,
+        }
+    }
 }
 
 /// An output specifies how many coins to be assigned to a [public key/address](PublicKey).
 ///
+/// If a condition is set, the output is locked to the [script](Script) with that hash,
+/// and the public key holds the script hash as address.
+///
+/// If a locktime is set, the output can only be spent once the chain reaches that height.
+///
+/// Outputs carrying data embed up to [MAX_OUTPUT_DATA] bytes of metadata in the chain.
+/// They have no value and can't be spent, so they are not tracked as UTXOs.
+///
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Output {
     pub value: Value,
     pub pubkey: PublicKey,
+    #[serde(default)]
+    pub condition: Option<Hash>,
+    #[serde(default)]
+    pub locktime: Option<Height>,
+    #[serde(default)]
+    pub data: Option<Vec<u8>>,
+}
+
+impl Output {
+    /// Serialized size of an output without condition, locktime and data
+    ///
+    pub fn serialized_size() -> usize {
+        TransactionData::OUTPUT_WEIGHT
+    }
+
+    /// Creates an output sending the value to the public key, without condition,
+    /// locktime or data
+    ///
+    pub fn new(value: Value, pubkey: PublicKey) -> Output {
+        Output {
+            value,
+            pubkey,
+            condition: None,
+            locktime: None,
+            data: None,
+        }
+    }
+
+    /// Creates an unspendable output embedding the data
+    ///
+    pub fn with_data(data: Vec<u8>) -> Output {
+        Output {
+            value: 0,
+            pubkey: PublicKey::new([0; PUBLIC_KEY_LENGTH]),
+            condition: None,
+            locktime: None,
+            data: Some(data),
+        }
+    }
+
+    /// A data output is valid if it has no value and the data is not too long.
+    /// Other outputs are always valid.
+    ///
+    pub fn is_data_valid(&self) -> bool {
+        match &self.data {
+            Some(data) => self.value == 0 && data.len() <= MAX_OUTPUT_DATA,
+            None => true,
+        }
+    }
+
+    pub fn is_spendable(&self) -> bool {
+        self.data.is_none()
+    }
+
+    pub fn with_script(script: Script, value: Value) -> Output {
+        let hash = script.to_hash();
+        Output {
+            value,
+            pubkey: PublicKey::new(*hash.digest()),
+            condition: Some(hash),
+            locktime: None,
+            data: None,
+        }
+    }
+
+    /// An output is dust if it's worth less than the threshold. Data outputs are never
+    /// dust, since they are not meant to be spent.
+    ///
+    pub fn is_dust(&self, dust_threshold: Value) -> bool {
+        self.is_spendable() && self.value < dust_threshold
+    }
+
+    /// Divides the output into count outputs to the same key, with the same value.
+    /// The remainder of the division is added to the first output. At most
+    /// [MAX_SPLIT_OUTPUTS] outputs can be created.
+    ///
+    pub fn split(&self, count: u32) -> Result<Vec<Output>, SplitError> {
+        if count == 0 {
+            return Err(SplitError::InvalidCount);
+        }
+        if count > MAX_SPLIT_OUTPUTS {
+            return Err(SplitError::TooManyOutputs);
+        }
+        if self.value < Value::from(count) {
+            return Err(SplitError::ValueTooSmall);
+        }
+        let value = self.value / Value::from(count);
+        let mut outputs = vec![
+            Output {
+                value,
+                ..self.clone()
+            };
+            count as usize
+        ];
+        outputs[0].value += self.value % Value::from(count);
+        Ok(outputs)
+    }
+
+    /// Merges outputs to the same key into a single output with their total value
+    ///
+    pub fn consolidate(outputs: Vec<Output>) -> Result<Output, ConsolidateError> {
+        let mut iter = outputs.into_iter();
+        let mut result = iter.next().ok_or(ConsolidateError::NoOutputs)?;
+        for output in iter {
+            if output.pubkey != result.pubkey || output.condition != result.condition {
+                return Err(ConsolidateError::KeyMismatch);
+            }
+            result.value = result
+                .value
+                .checked_add(output.value)
+                .ok_or(ConsolidateError::Overflow)?;
+        }
+        Ok(result)
+    }
 }
 
 /// The transaction data. It is composed by a list of inputs that will be consumed and
@@ -49,11 +303,16 @@ pub struct Output {
 ///
 /// The timestamp is an optional field used only in coinbase transactions (i.e. transactions
 /// which generate new coins).
+///
+/// If an expiry height is set, the transaction can only be included in blocks below that
+/// height. Like the other fields, it's part of the transaction hash.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct TransactionData {
     pub inputs: Vec<Input>,
     pub outputs: Vec<Output>,
     pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub expires_at: Option<Height>,
 }
 
 impl TransactionData {
@@ -62,6 +321,7 @@ impl TransactionData {
             inputs,
             outputs,
             timestamp: None,
+            expires_at: None,
         }
     }
 
@@ -74,6 +334,61 @@ impl TransactionData {
             inputs,
             outputs,
             timestamp: Some(timestamp),
+            expires_at: None,
+        }
+    }
+}
+
+impl TransactionData {
+    /// Serialized size of an input without script reveal, and of an output without
+    /// condition, locktime and data
+    const INPUT_WEIGHT: usize = 105;
+    const OUTPUT_WEIGHT: usize = 43;
+    /// Serialized size of a transaction without inputs and outputs, including its hash
+    const BASE_WEIGHT: usize = 50;
+
+    /// Estimates the size in bytes of a serialized transaction with the given number of
+    /// inputs and outputs. The estimate is exact if the inputs don't reveal a script and
+    /// the outputs are not [P2SH outputs](Output::with_script).
+    ///
+    pub fn estimated_weight(input_count: usize, output_count: usize) -> usize {
+        Self::BASE_WEIGHT + input_count * Self::INPUT_WEIGHT + output_count * Self::OUTPUT_WEIGHT
+    }
+
+    /// Estimates the fee of a transaction paying the outputs with the utxos, given a fee
+    /// rate in coins per byte. The utxos are selected in order like
+    /// [new_tx_with_fee](crate::utils::new_tx_with_fee) does, adding a change output if
+    /// they exceed the value to pay.
+    ///
+    /// Since the size depends on the number of inputs, which in turn depends on the fee,
+    /// the fee is recomputed until the selected inputs can pay for their own size.
+    /// The fee never decreases between iterations, so the estimate may slightly exceed
+    /// the minimum when the change output is dropped.
+    ///
+    pub fn estimate_fees(
+        utxos: &[Utxo],
+        outputs: &[Output],
+        fee_rate: Value,
+    ) -> Result<Value, UtxoError> {
+        let output_value = outputs
+            .iter()
+            .try_fold(0 as Value, |acc, output| acc.checked_add(output.value))
+            .ok_or(UtxoError::InvalidValue)?;
+        let mut fee: Value = 0;
+        loop {
+            let value = output_value
+                .checked_add(fee)
+                .ok_or(UtxoError::InvalidValue)?;
+            let selection = Utxo::collect(utxos, value)?;
+            let output_count = outputs.len() + usize::from(selection.change != 0);
+            let weight = Self::estimated_weight(selection.list.len(), output_count) as Value;
+            let needed = weight
+                .checked_mul(fee_rate)
+                .ok_or(UtxoError::InvalidValue)?;
+            if needed <= fee {
+                return Ok(fee);
+            }
+            fee = needed;
         }
     }
 }
@@ -98,14 +413,198 @@ impl Transaction {
         }
     }
 
+    #[must_use]
     pub fn is_hash_valid(&self) -> bool {
         let bytes: Vec<u8> = self.data.into_bytes();
         return Hash::new(bytes.as_slice()).digest() == self.hash.digest();
     }
 
+    /// Panics if the hash doesn't match the data, for catching data corruption
+    ///
+    pub fn assert_hash_valid(&self) {
+        if !self.is_hash_valid() {
+            panic!(
+                "Transaction hash mismatch: expected {}, got {}",
+                Hash::new(&self.data.into_bytes()),
+                self.hash
+            );
+        }
+    }
+
     pub fn is_coinbase(&self) -> bool {
         self.data.inputs.len() == 0
     }
+
+    /// A transaction is final if none of its inputs signals replaceability
+    ///
+    pub fn is_final(&self) -> bool {
+        self.data
+            .inputs
+            .iter()
+            .all(|input| input.sequence == Input::SEQUENCE_FINAL)
+    }
+
+    pub fn is_rbf_eligible(&self) -> bool {
+        !self.is_final()
+    }
+
+    /// A transaction is pruned when its outputs have been removed after being all spent,
+    /// see [gc_spent_data](crate::core::blockchain::Blockchain::gc_spent_data).
+    /// The hash of a pruned transaction can't be verified anymore.
+    ///
+    pub fn is_pruned(&self) -> bool {
+        self.data.outputs.is_empty()
+    }
+
+    /// Returns the fee left to the miner, see [get_tx_value](Blockchain::get_tx_value).
+    /// Returns None if any of the inputs is not in the chain.
+    ///
+    pub fn fee(&self, chain: &Blockchain) -> Option<Value> {
+        chain.get_tx_value(self).map(|value| value.fees)
+    }
+
+    /// Size in bytes of the serialized transaction, used for computing the fee per byte
+    ///
+    pub fn serialized_size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
+    }
+
+    /// A transaction is expired at a height if it can't be included in a block at
+    /// that height
+    ///
+    pub fn is_expired(&self, height: &Height) -> bool {
+        self.data
+            .expires_at
+            .as_ref()
+            .is_some_and(|expires_at| expires_at <= height)
+    }
+
+    pub fn total_output_value(&self) -> Value {
+        Blockchain::get_tx_output_value(&self.data.outputs)
+    }
+
+    /// A transaction is dust if any of its outputs is worth less than the threshold,
+    /// i.e. spending it would likely cost more in fees than its value.
+    ///
+    /// A change below the threshold should not be added as an output: the sender can
+    /// leave it to the miner as a fee instead.
+    ///
+    pub fn is_dust(&self, dust_threshold: Value) -> bool {
+        self.data
+            .outputs
+            .iter()
+            .any(|output| output.is_dust(dust_threshold))
+    }
+
+    /// Returns a copy of the transaction with inputs sorted by (hash, index) and
+    /// outputs sorted by (value, pubkey), so that the same set of inputs and outputs
+    /// always produces the same transaction hash.
+    ///
+    /// Input signatures refer to the spent transactions, so they stay valid after
+    /// reordering.
+    ///
+    pub fn normalize(&self) -> Transaction {
+        let mut data = self.data.clone();
+        data.inputs
+            .sort_by(|a, b| (a.hash.digest(), a.index).cmp(&(b.hash.digest(), b.index)));
+        data.outputs
+            .sort_by(|a, b| (a.value, a.pubkey.as_bytes()).cmp(&(b.value, b.pubkey.as_bytes())));
+        Transaction::new(data)
+    }
+
+    /// Creates a transaction spending the utxo and splitting its value into count
+    /// outputs to the same key, see [split](Output::split)
+    ///
+    pub fn split_output(key: &KeyPair, utxo: &Utxo, count: u32) -> Result<Transaction, SplitError> {
+        let output = Output::new(utxo.value, key.public_key());
+        Ok(Transaction::new(TransactionData::new(
+            std::slice::from_ref(utxo).into_inputs(key),
+            output.split(count)?,
+        )))
+    }
+
+    /// Creates a transaction spending all the utxos into a single output to the same
+    /// key, see [consolidate](Output::consolidate)
+    ///
+    pub fn consolidate_outputs(
+        key: &KeyPair,
+        utxos: &[Utxo],
+    ) -> Result<Transaction, ConsolidateError> {
+        let outputs = utxos
+            .iter()
+            .map(|utxo| Output::new(utxo.value, key.public_key()))
+            .collect();
+        let output = Output::consolidate(outputs)?;
+        Ok(Transaction::new(TransactionData::new(
+            utxos.into_inputs(key),
+            vec![output],
+        )))
+    }
+
+    pub fn is_normalized(&self) -> bool {
+        let inputs_sorted = self.data.inputs.windows(2).all(|pair| {
+            (pair[0].hash.digest(), pair[0].index) <= (pair[1].hash.digest(), pair[1].index)
+        });
+        let outputs_sorted = self.data.outputs.windows(2).all(|pair| {
+            (pair[0].value, pair[0].pubkey.as_bytes()) <= (pair[1].value, pair[1].pubkey.as_bytes())
+        });
+        inputs_sorted && outputs_sorted
+    }
+
+    /// Formats the transaction in a human readable way, for debugging purposes.
+    ///
+    /// The blockchain is used for resolving the outputs referenced by the inputs, so that
+    /// their value and owner can be displayed along with the fee. Outputs assigned to one
+    /// of the input owners are marked as change.
+    ///
+    pub fn pretty_print(&self, chain: &Blockchain) -> String {
+        let mut owners = Vec::<&PublicKey>::new();
+        let mut out = format!("Transaction {}\n  Inputs:\n", self.hash.to_hex_str());
+        for (i, input) in self.data.inputs.iter().enumerate() {
+            let referenced = chain
+                .query_tx(&input.hash)
+                .and_then(|(_, tx)| tx.data.outputs.get(input.index as usize));
+            let _ = write!(
+                out,
+                "    [{}] prev_tx {} output {}",
+                i,
+                input.hash.to_hex_str(),
+                input.index
+            );
+            let _ = match referenced {
+                Some(output) => {
+                    owners.push(&output.pubkey);
+                    writeln!(
+                        out,
+                        " ({} coins) signed by {}",
+                        output.value,
+                        output.pubkey.to_hex_str()
+                    )
+                }
+                None => writeln!(out, " (unknown output)"),
+            };
+        }
+        out.push_str("  Outputs:\n");
+        for (i, output) in self.data.outputs.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "    [{}] {} coins -> {}{}",
+                i,
+                output.value,
+                output.pubkey.to_hex_str(),
+                if owners.contains(&&output.pubkey) {
+                    " (change)"
+                } else {
+                    ""
+                }
+            );
+        }
+        let _ = match chain.get_tx_value(self) {
+            Some(value) => write!(out, "  Fee: {} coins", value.fees),
+            None => write!(out, "  Fee: unknown"),
+        };
+        out
+    }
 }
 
 impl ByteIO for Transaction {}
@@ -118,6 +617,7 @@ impl FileIO for Vec<Output> {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chain::Chain;
     use crate::core::keys::KeyPair;
     use tempfile::*;
 
@@ -129,11 +629,10 @@ mod tests {
                 hash: Hash::new(b"test"),
                 index: 0,
                 signature: key.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         );
 
         let tx_data_2 = TransactionData::new(
@@ -141,11 +640,10 @@ mod tests {
                 hash: Hash::new(b"test"),
                 index: 0,
                 signature: key.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         );
 
         let tx1 = Transaction::new(tx_data_1);
@@ -154,16 +652,23 @@ mod tests {
         assert_eq!(tx1.hash, tx2.hash)
     }
 
+    #[test]
+    #[should_panic(expected = "Transaction hash mismatch")]
+    fn assert_hash_valid() {
+        let key = KeyPair::new();
+        let mut tx = crate::utils::new_coinbase_tx(&key.public_key(), 100, 0);
+        tx.assert_hash_valid();
+        tx.data.outputs[0].value += 1;
+        tx.assert_hash_valid();
+    }
+
     #[test]
     fn is_coinbase() {
         let key = KeyPair::new();
 
         let tx = Transaction::new(TransactionData::new(
             vec![],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         ));
 
         assert!(tx.is_coinbase());
@@ -173,16 +678,173 @@ mod tests {
                 hash: Hash::new(b"test"),
                 index: 0,
                 signature: key.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         ));
 
         assert!(!tx.is_coinbase());
     }
 
+    #[test]
+    fn pretty_print() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+        let genesis_tx = &chain.get_last_block().transactions()[0];
+        let tx = crate::utils::new_tx_with_fee(
+            &key_1,
+            &chain.find_utxos_for_key(&key_1.public_key()),
+            vec![Output::new(7000, key_2.public_key())],
+            10,
+        )
+        .unwrap();
+
+        let printed = tx.pretty_print(&chain.chain);
+        assert!(printed.starts_with(&format!("Transaction {}", tx.hash.to_hex_str())));
+        assert!(printed.contains(&format!(
+            "[0] prev_tx {} output 0 (10000 coins) signed by {}",
+            genesis_tx.hash.to_hex_str(),
+            key_1.public_key().to_hex_str()
+        )));
+        assert!(printed.contains(&format!(
+            "[0] 7000 coins -> {}\n",
+            key_2.public_key().to_hex_str()
+        )));
+        assert!(printed.contains(&format!(
+            "[1] 2990 coins -> {} (change)",
+            key_1.public_key().to_hex_str()
+        )));
+        assert!(printed.ends_with("Fee: 10 coins"));
+
+        let unknown = Transaction::new(TransactionData::new(
+            vec![Input {
+                hash: Hash::new(b"test"),
+                index: 0,
+                signature: key_1.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
+            }],
+            vec![],
+        ));
+        let printed = unknown.pretty_print(&chain.chain);
+        assert!(printed.contains("output 0 (unknown output)"));
+        assert!(printed.ends_with("Fee: unknown"));
+    }
+
+    #[test]
+    fn fee() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = crate::utils::new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(7000, key_2.public_key())],
+            250,
+        )
+        .unwrap();
+        assert_eq!(tx.fee(&chain.chain), Some(250));
+        assert_eq!(tx.total_output_value(), 10000 - 250);
+
+        let coinbase = &chain.get_block(0).unwrap().transactions()[0];
+        assert_eq!(coinbase.fee(&chain.chain), Some(0));
+        assert_eq!(coinbase.total_output_value(), 10000);
+
+        let unknown = Transaction::new(TransactionData::new(
+            vec![Input {
+                hash: Hash::new(b"test"),
+                index: 0,
+                signature: key_1.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
+            }],
+            vec![],
+        ));
+        assert_eq!(unknown.fee(&chain.chain), None);
+        assert_eq!(unknown.total_output_value(), 0);
+    }
+
+    #[test]
+    fn is_dust() {
+        let key = KeyPair::new();
+        let tx = Transaction::new(TransactionData::new(
+            vec![Input {
+                hash: Hash::new(b"test"),
+                index: 0,
+                signature: key.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
+            }],
+            vec![
+                Output::new(100, key.public_key()),
+                Output::new(5, key.public_key()),
+            ],
+        ));
+
+        assert!(!tx.data.outputs[0].is_dust(100));
+        assert!(tx.data.outputs[0].is_dust(101));
+
+        assert!(!tx.is_dust(0));
+        assert!(!tx.is_dust(5));
+        assert!(tx.is_dust(6));
+        assert!(tx.is_dust(101));
+    }
+
+    #[test]
+    fn normalize() {
+        let key = KeyPair::new();
+        let tx = Transaction::new(TransactionData::new(
+            vec![
+                Input {
+                    hash: Hash::new(b"test_1"),
+                    index: 1,
+                    signature: key.sign(b"test_1"),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
+                },
+                Input {
+                    hash: Hash::new(b"test_1"),
+                    index: 0,
+                    signature: key.sign(b"test_1"),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
+                },
+                Input {
+                    hash: Hash::new(b"test_2"),
+                    index: 0,
+                    signature: key.sign(b"test_2"),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
+                },
+            ],
+            vec![
+                Output::new(5, key.public_key()),
+                Output::new(1, key.public_key()),
+            ],
+        ));
+        assert!(!tx.is_normalized());
+
+        let normalized = tx.normalize();
+        assert!(normalized.is_normalized());
+        assert!(normalized.is_hash_valid());
+        assert_ne!(normalized.hash, tx.hash);
+        assert_eq!(normalized.data.inputs.len(), 3);
+        assert_eq!(normalized.data.outputs[0].value, 1);
+        assert_eq!(normalized.data.outputs[1].value, 5);
+
+        let inputs = &normalized.data.inputs;
+        for pair in inputs.windows(2) {
+            assert!(
+                (pair[0].hash.digest(), pair[0].index) <= (pair[1].hash.digest(), pair[1].index)
+            );
+        }
+
+        assert_eq!(normalized.normalize(), normalized);
+    }
+
     #[test]
     fn validation() {
         let key = KeyPair::new();
@@ -191,11 +853,10 @@ mod tests {
                 hash: Hash::new(b"test"),
                 index: 0,
                 signature: key.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         );
 
         let tx_1 = Transaction::new(tx_data.clone());
@@ -216,11 +877,10 @@ mod tests {
                 hash: Hash::new(b"test"),
                 index: 0,
                 signature: key.sign(b"test"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 1,
-                pubkey: key.public_key(),
-            }],
+            vec![Output::new(1, key.public_key())],
         );
 
         let tx = Transaction {
@@ -234,6 +894,160 @@ mod tests {
         assert!(!deserialized_tx.is_hash_valid());
     }
 
+    #[test]
+    fn estimate_fees() {
+        use crate::utils::new_tx_with_fee;
+
+        let key = KeyPair::new();
+        let utxos: Vec<Utxo> = (0..4)
+            .map(|i| Utxo::new(Hash::new(format!("tx-{}", i).as_bytes()), i, 1000))
+            .collect();
+
+        // The fee alone requires one more input than the outputs
+        for (value, input_count) in [(500, 1), (950, 2), (1950, 3)] {
+            let fee = TransactionData::estimate_fees(
+                &utxos,
+                &[Output::new(value, KeyPair::new().public_key())],
+                1,
+            )
+            .unwrap();
+            let tx = new_tx_with_fee(
+                &key,
+                &utxos,
+                vec![Output::new(value, KeyPair::new().public_key())],
+                fee,
+            )
+            .unwrap();
+            assert_eq!(tx.data.inputs.len(), input_count);
+            assert_eq!(tx.data.outputs.len(), 2);
+            assert_eq!(
+                tx.into_bytes().len(),
+                TransactionData::estimated_weight(input_count, 2)
+            );
+            assert_eq!(fee, tx.into_bytes().len() as Value);
+            assert_eq!(tx.serialized_size(), tx.into_bytes().len());
+            assert_eq!(
+                tx.serialized_size(),
+                TransactionData::estimated_weight(0, 0)
+                    + input_count * Input::serialized_size()
+                    + 2 * Output::serialized_size()
+            );
+        }
+
+        assert_eq!(
+            TransactionData::estimate_fees(
+                &utxos,
+                &[Output::new(500, KeyPair::new().public_key())],
+                0
+            ),
+            Ok(0)
+        );
+        assert_eq!(
+            TransactionData::estimate_fees(
+                &utxos,
+                &[Output::new(3900, KeyPair::new().public_key())],
+                1
+            ),
+            Err(UtxoError::NotEnoughValue)
+        );
+        assert_eq!(
+            TransactionData::estimate_fees(
+                &utxos,
+                &[Output::new(500, KeyPair::new().public_key())],
+                Value::MAX
+            ),
+            Err(UtxoError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn split_and_consolidate() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+
+        let outputs = Output::new(10, key_1.public_key()).split(3).unwrap();
+        assert_eq!(
+            outputs,
+            vec![
+                Output::new(4, key_1.public_key()),
+                Output::new(3, key_1.public_key()),
+                Output::new(3, key_1.public_key())
+            ]
+        );
+        assert_eq!(
+            Output::new(10, key_1.public_key()).split(1).unwrap(),
+            vec![Output::new(10, key_1.public_key())]
+        );
+        assert_eq!(
+            Output::new(3, key_1.public_key()).split(3).unwrap().len(),
+            3
+        );
+        assert_eq!(
+            Output::new(10, key_1.public_key()).split(0),
+            Err(SplitError::InvalidCount)
+        );
+        assert_eq!(
+            Output::new(2, key_1.public_key()).split(3),
+            Err(SplitError::ValueTooSmall)
+        );
+        assert_eq!(
+            Output::new(Value::MAX, key_1.public_key()).split(u32::MAX),
+            Err(SplitError::TooManyOutputs)
+        );
+        assert_eq!(
+            Output::new(10000, key_1.public_key())
+                .split(MAX_SPLIT_OUTPUTS)
+                .map(|outputs| outputs.len()),
+            Ok(MAX_SPLIT_OUTPUTS as usize)
+        );
+
+        assert_eq!(
+            Output::consolidate(outputs),
+            Ok(Output::new(10, key_1.public_key()))
+        );
+        assert_eq!(
+            Output::consolidate(vec![]),
+            Err(ConsolidateError::NoOutputs)
+        );
+        assert_eq!(
+            Output::consolidate(vec![
+                Output::new(1, key_1.public_key()),
+                Output::new(1, key_2.public_key())
+            ]),
+            Err(ConsolidateError::KeyMismatch)
+        );
+        assert_eq!(
+            Output::consolidate(vec![
+                Output::new(Value::MAX, key_1.public_key()),
+                Output::new(1, key_1.public_key())
+            ]),
+            Err(ConsolidateError::Overflow)
+        );
+
+        // Split the genesis coins, then merge them back
+        let mut chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = Transaction::split_output(&key_1, &utxos[0], 3).unwrap();
+        assert_eq!(tx.data.outputs.len(), 3);
+        assert!(chain.validate_new_tx(&tx));
+        let block = crate::utils::new_block(&chain, 0, vec![tx]);
+        assert!(chain.add_block(block).is_ok());
+
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        assert_eq!(utxos.len(), 3);
+        let tx = Transaction::consolidate_outputs(&key_1, &utxos).unwrap();
+        assert_eq!(tx.data.inputs.len(), 3);
+        assert_eq!(
+            tx.data.outputs,
+            vec![Output::new(chain.rules.base_coins, key_1.public_key())]
+        );
+        assert!(chain.validate_new_tx(&tx));
+        assert_eq!(
+            Transaction::consolidate_outputs(&key_1, &[]),
+            Err(ConsolidateError::NoOutputs)
+        );
+    }
+
     #[test]
     fn file_io() {
         let key = KeyPair::new();
@@ -245,11 +1059,10 @@ mod tests {
                     hash: Hash::new(b"test"),
                     index: 0,
                     signature: key.sign(b"test"),
+                    sequence: Input::SEQUENCE_FINAL,
+                    reveal: None,
                 }],
-                vec![Output {
-                    value: 1,
-                    pubkey: key.public_key(),
-                }],
+                vec![Output::new(1, key.public_key())],
             ),
         };
 
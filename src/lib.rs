@@ -5,3 +5,4 @@ pub mod mining;
 pub mod traits;
 pub mod utils;
 pub mod utxo;
+pub mod wallet;
@@ -3,58 +3,332 @@
 //!
 
 use crate::chain::Chain;
-use crate::consensus::Target;
-use crate::core::block::Block;
-use crate::core::blockchain::TransactionValue;
+use crate::consensus::{ConsensusRules, Target};
+use crate::core::block::{unix_now, Block, BlockData, Nonce};
+use crate::core::blockchain::Blockchain;
 use crate::core::hash::Hash;
 use crate::core::keys::PublicKey;
-use crate::core::transaction::{Output, Transaction, TransactionData};
-use crate::utils::new_block;
+use crate::core::transaction::{Output, Transaction, TransactionData, Value};
+use crate::traits::io::ByteIO;
 use crate::utxo::Utxo;
 use rand::seq::SliceRandom;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of nonces tried between two checks of the found and cancel flags
+const NONCE_BATCH: u64 = 10000;
+
+/// Number of nonces tried between two calls of the progress callback
+const PROGRESS_INTERVAL: u64 = 100000;
+
+/// Prints mining details, only if the debug-logging feature is enabled
+macro_rules! debug_log {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "debug-logging") {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Pool capacity used by the node
+pub const DEFAULT_POOL_CAPACITY: usize = 10000;
+
+/// Called with the nonces tried so far and the most leading zeros seen in a hash
+pub type ProgressCallback = Box<dyn Fn(u32, u32) + Send>;
+
+/// Selection weight of final transactions relative to replaceable ones: final
+/// transactions are slightly preferred since they won't be replaced
+const FINAL_TX_WEIGHT: f64 = 1.2;
 
 /// Errors that can happen during mining
 #[derive(Debug)]
 pub enum MiningError {
     NotEnoughTransactions,
     NoBlockFound,
+    InvalidCoinbase,
+    Cancelled,
+}
+
+impl fmt::Display for MiningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Mining error: {}",
+            match self {
+                MiningError::NotEnoughTransactions => "not enough transactions",
+                MiningError::NoBlockFound => "no block found",
+                MiningError::InvalidCoinbase => "invalid coinbase transaction",
+                MiningError::Cancelled => "cancelled",
+            }
+        )
+    }
+}
+
+impl std::error::Error for MiningError {}
+
+/// Statistics on the transactions waiting in the pool of a miner.
+///
+/// Fees are summed over the transactions whose value can be computed, the average
+/// is over all the transactions.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinerPoolStats {
+    pub tx_count: usize,
+    pub total_fees: Value,
+    pub avg_fee_per_tx: Value,
+}
+
+/// How the miner picks the pool transactions to include in a block
+///
+/// - Random: weighted random choice, slightly preferring final transactions
+/// - FeeFirst: the transactions paying the highest fees per byte, ties broken by hash
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SelectionStrategy {
+    #[default]
+    Random,
+    FeeFirst,
+}
+
+/// Statistics on the work done by a miner.
+///
+/// Attempts count the calls to [mine](Miner::mine), the hashrate is computed over all
+/// of them, and the last difficulty is the leading zeros of the last target mined.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct MiningStats {
+    pub attempts: u64,
+    pub hashes_per_second: f64,
+    pub last_difficulty: u32,
 }
 
 /// A miner keeps a PublicKey which will be the recipient of the
 /// transaction fees and rewards, and a pool of outstanding transactions
-/// for constructing blocks
+/// for constructing blocks. The transactions are picked following the
+/// [selection strategy](SelectionStrategy), random by default.
+///
+/// The pool holds at most capacity transactions: when it's full, the oldest transaction
+/// is evicted to make room for a new one, unless it pays a higher fee. The insertion
+/// order may contain hashes already removed from the pool, which are skipped.
+///
+/// The cancel flag aborts the nonce search in progress, see [cancel](Miner::cancel).
+///
+/// The optional progress callback is called during the nonce search, see
+/// [with_progress_callback](Miner::with_progress_callback).
 ///
 pub struct Miner {
     recipient: PublicKey,
     pub pool: HashMap<Hash, Transaction>,
+    capacity: usize,
+    insertion_order: VecDeque<Hash>,
+    strategy: SelectionStrategy,
+    cancel: Arc<AtomicBool>,
+    progress_cb: Option<ProgressCallback>,
+    last_hashrate: Option<f64>,
+    attempts: u64,
+    hashes: u64,
+    mining_time: Duration,
+    last_difficulty: u32,
 }
 
 impl Miner {
-    pub fn new(recipient: PublicKey) -> Miner {
+    pub fn new(recipient: PublicKey, capacity: usize) -> Miner {
         Miner {
             recipient,
             pool: HashMap::new(),
+            capacity,
+            insertion_order: VecDeque::new(),
+            strategy: SelectionStrategy::default(),
+            cancel: Arc::new(AtomicBool::new(false)),
+            progress_cb: None,
+            last_hashrate: None,
+            attempts: 0,
+            hashes: 0,
+            mining_time: Duration::ZERO,
+            last_difficulty: 0,
         }
     }
 
+    pub fn with_selection_strategy(mut self, strategy: SelectionStrategy) -> Miner {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn selection_strategy(&self) -> SelectionStrategy {
+        self.strategy
+    }
+
+    /// Registers a callback called every 100000 nonces tried, with the number of nonces
+    /// tried so far in the current block and the most leading zeros seen in a hash.
+    /// The callback can be called by any of the mining threads.
+    ///
+    pub fn with_progress_callback(&mut self, cb: impl Fn(u32, u32) + Send + 'static) -> &mut Self {
+        self.progress_cb = Some(Box::new(cb));
+        self
+    }
+
+    /// Makes the mining in progress, and any following one, fail with
+    /// [Cancelled](MiningError::Cancelled) until [reset_cancel](Miner::reset_cancel)
+    /// is called. The selected transactions are returned to the pool.
+    ///
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset_cancel(&mut self) {
+        self.cancel.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns the cancel flag, which can be set while the miner is locked by a
+    /// mining thread
+    ///
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
     /// The main mining function, which tries to collect transactions into a block
     /// and performs Proof of Work until it reaches the target specified by the
     /// [consensus rules](crate::consensus::ConsensusRules).
     ///
     /// Creating a block involves:
-    /// - selecting some transaction
-    /// - build a block with a starting nonce value
+    /// - selecting some transactions with the [selection strategy](SelectionStrategy)
+    /// - build a block with a random nonce prefix and a starting nonce value
     /// - hash the block and compare the hash value with the consensus target
     /// - if the block meets the target, return it; otherwise, incement the nonce and restart
     ///
-    /// This is a naive implementation of a mining algorithm, it doesn't optimize transaction
-    /// selection for higher fees, nor makes any other smart choice.
+    /// The nonce space is searched in parallel on all the rayon threads.
+    ///
+    /// This is a naive implementation of a mining algorithm: apart from preferring higher
+    /// fees with [FeeFirst](SelectionStrategy::FeeFirst), it doesn't make any smart choice.
     ///
     pub fn mine(&mut self, chain: &Chain) -> Result<Block, MiningError> {
-        println!("Start mining");
+        debug_log!("Start mining");
+        let (txs, selected_utxos) = self.build_candidate(chain)?;
+        self.find_block(chain, txs, &selected_utxos, None)
+    }
+
+    /// Like [mine](Miner::mine), but the nonce space is split into one chunk for every
+    /// thread, each searched by a thread spawned for this block instead of the rayon
+    /// threads. All the threads are joined before returning.
+    ///
+    pub fn mine_with_threads(
+        &mut self,
+        chain: &Chain,
+        threads: usize,
+    ) -> Result<Block, MiningError> {
+        debug_log!("Start mining with {} threads", threads);
+        let (txs, selected_utxos) = self.build_candidate(chain)?;
+        self.find_block(chain, txs, &selected_utxos, Some(threads))
+    }
+
+    /// Selects the transactions of a new block and appends the coinbase paying
+    /// the reward and the fees to the recipient
+    ///
+    fn build_candidate(
+        &mut self,
+        chain: &Chain,
+    ) -> Result<(Vec<Transaction>, HashSet<Utxo>), MiningError> {
+        // The coinbase size doesn't depend on its value
+        let coinbase_size = self.coinbase_tx(chain, 0).serialized_size();
+        let (mut txs, selected_utxos, fees) = self.select_transactions(chain, coinbase_size)?;
+
+        let coinbase_value =
+            Block::compute_expected_coinbase_value(&chain.rules, chain.height(), fees);
+        if coinbase_value > 0 {
+            txs.push(self.coinbase_tx(chain, coinbase_value));
+        }
+        Ok((txs, selected_utxos))
+    }
+
+    fn coinbase_tx(&self, chain: &Chain, value: Value) -> Transaction {
+        Transaction::new(TransactionData::new_with_timestamp(
+            vec![],
+            vec![Output::new(value, self.recipient.clone())],
+            chain.height() - 1,
+        ))
+    }
+
+    /// Like [mine](Miner::mine), but the block pays the provided coinbase transaction
+    /// instead of the miner recipient. The coinbase must be valid for the selected
+    /// transactions, see [validate_coinbase_tx](Chain::validate_coinbase_tx), otherwise
+    /// the transactions are returned to the pool.
+    ///
+    pub fn mine_with_custom_coinbase(
+        &mut self,
+        chain: &Chain,
+        custom_coinbase: Transaction,
+    ) -> Result<Block, MiningError> {
+        debug_log!("Start mining with custom coinbase");
+        let (mut txs, selected_utxos, _) =
+            self.select_transactions(chain, custom_coinbase.serialized_size())?;
+
+        if !chain.validate_coinbase_tx(&chain.get_last_block().hash, &txs, &custom_coinbase) {
+            debug_log!("Invalid coinbase transaction");
+            self.return_to_pool(txs);
+            return Err(MiningError::InvalidCoinbase);
+        }
+        txs.push(custom_coinbase);
+
+        self.find_block(chain, txs, &selected_utxos, None)
+    }
+
+    /// Picks some transactions from the pool and removes them from it. Returns the
+    /// transactions, the UTXOs they spend and their total fee.
+    ///
+    /// The transactions fit in the max block size of the consensus rules, leaving
+    /// reserved_size bytes for the coinbase.
+    ///
+    fn select_transactions(
+        &mut self,
+        chain: &Chain,
+        reserved_size: usize,
+    ) -> Result<(Vec<Transaction>, HashSet<Utxo>, Value), MiningError> {
         let tx_count: usize = 5;
+        let max_size = chain
+            .rules
+            .max_block_size
+            .map_or(usize::MAX, |max| max.saturating_sub(reserved_size));
+        self.last_hashrate = None;
+
+        let (mut txs, selected_utxos) = match self.strategy {
+            SelectionStrategy::Random => self.pick_random(tx_count, max_size),
+            SelectionStrategy::FeeFirst => self.pick_by_fee(chain, tx_count, max_size),
+        };
+
+        if txs.is_empty() {
+            debug_log!("Failed to collect transactions");
+            return Err(MiningError::NotEnoughTransactions);
+        }
 
+        // Transactions whose inputs have been spent in the meantime are dropped
+        let mut fees: Value = 0;
+        txs.retain(|tx| {
+            self.pool.remove(&tx.hash);
+            match chain.utxo_pool().total_input_value(tx) {
+                Some(input) => {
+                    let output = Blockchain::get_tx_output_value(&tx.data.outputs);
+                    fees += input - output;
+                    true
+                }
+                None => false,
+            }
+        });
+        if txs.is_empty() {
+            debug_log!("No valid transactions left");
+            return Err(MiningError::NotEnoughTransactions);
+        }
+        Ok((txs, selected_utxos, fees))
+    }
+
+    /// Picks up to tx_count non conflicting transactions at random, up to max_size
+    /// bytes in total, retrying a few times if fewer than two are picked
+    ///
+    fn pick_random(&self, tx_count: usize, max_size: usize) -> (Vec<Transaction>, HashSet<Utxo>) {
         let mut rng = &mut rand::thread_rng();
 
         let mut txs = Vec::<Transaction>::new();
@@ -62,13 +336,26 @@ impl Miner {
         for _ in 0..10 {
             txs.clear();
             selected_utxos.clear();
+            let mut size: usize = 0;
             for (_, tx) in self
                 .pool
                 .iter()
                 .collect::<Vec<_>>()
-                .choose_multiple(&mut rng, tx_count)
+                .choose_multiple_weighted(&mut rng, tx_count, |(_, tx)| {
+                    if tx.is_final() {
+                        FINAL_TX_WEIGHT
+                    } else {
+                        1.0
+                    }
+                })
+                .unwrap()
             {
+                let tx_size = tx.serialized_size();
+                if size + tx_size > max_size {
+                    continue;
+                }
                 if merge_utxos(&tx, &mut selected_utxos) {
+                    size += tx_size;
                     txs.push((*tx).clone());
                 }
             }
@@ -77,70 +364,427 @@ impl Miner {
                 break;
             }
         }
+        (txs, selected_utxos)
+    }
 
-        if txs.is_empty() {
-            println!("Failed to collect transactions");
-            return Err(MiningError::NotEnoughTransactions);
+    /// Picks up to tx_count non conflicting transactions by decreasing fee per byte.
+    /// Transactions whose fee can't be computed come last, transactions that don't fit
+    /// in max_size bytes are skipped.
+    ///
+    fn pick_by_fee(
+        &self,
+        chain: &Chain,
+        tx_count: usize,
+        max_size: usize,
+    ) -> (Vec<Transaction>, HashSet<Utxo>) {
+        let fee_rate = |tx: &Transaction| {
+            tx.fee(&chain.chain)
+                .map(|fee| fee as f64 / tx.serialized_size() as f64)
+        };
+        let mut by_fee: Vec<(&Hash, &Transaction, Option<f64>)> = self
+            .pool
+            .iter()
+            .map(|(hash, tx)| (hash, tx, fee_rate(tx)))
+            .collect();
+        by_fee.sort_by(|a, b| {
+            b.2.partial_cmp(&a.2)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.digest().cmp(b.0.digest()))
+        });
+
+        let mut txs = Vec::<Transaction>::new();
+        let mut selected_utxos = HashSet::<Utxo>::new();
+        let mut size: usize = 0;
+        for (_, tx, _) in by_fee {
+            if txs.len() == tx_count {
+                break;
+            }
+            let tx_size = tx.serialized_size();
+            if size + tx_size > max_size {
+                continue;
+            }
+            if merge_utxos(tx, &mut selected_utxos) {
+                size += tx_size;
+                txs.push(tx.clone());
+            }
         }
+        (txs, selected_utxos)
+    }
 
-        let mut tx_value = TransactionValue::default();
-        for tx in txs.iter() {
-            self.pool.remove(&tx.hash);
-            tx_value = tx_value + chain.chain.get_tx_value(tx).unwrap();
+    /// Performs Proof of Work on a block made of the transactions, on the rayon threads
+    /// or on the given number of spawned threads. If no block is found, the transactions
+    /// are returned to the pool.
+    ///
+    fn find_block(
+        &mut self,
+        chain: &Chain,
+        txs: Vec<Transaction>,
+        selected_utxos: &HashSet<Utxo>,
+        threads: Option<usize>,
+    ) -> Result<Block, MiningError> {
+        // The target of the consensus rules is adjusted by the chain history
+        let rules = ConsensusRules {
+            target: chain.compute_retarget_target(),
+            ..chain.rules.clone()
+        };
+        debug_log!("Target: {:0256b}", rules.target);
+        debug_log!("Target leading: {}", rules.target.leading_zeros());
+        self.attempts += 1;
+        self.last_difficulty = rules.target.leading_zeros();
+        let start = Instant::now();
+        // A random prefix keeps the nonce space of this session apart from other miners'
+        let mut block_data =
+            BlockData::new_with_prefix(chain.get_last_block().hash.clone(), rand::random(), 0, txs);
+        block_data.unix_timestamp = Some(unix_now());
+        block_data.version = block_data
+            .version
+            .max(chain.rules.min_block_version_at(&chain.height()));
+        let (block, tries) = match threads {
+            Some(threads) => self.search_nonces_with_threads(&rules, &block_data, threads),
+            None => {
+                let progress = self.progress_cb.as_mut().map(Mutex::new);
+                search_nonces(&rules, &block_data, &self.cancel, progress.as_ref())
+            }
+        };
+        let elapsed = start.elapsed();
+        self.record_hashes(tries, elapsed);
+        self.last_hashrate = if elapsed.is_zero() {
+            None
+        } else {
+            Some(tries as f64 / elapsed.as_secs_f64())
+        };
+        debug_log!("Total tries: {}", tries);
+        if let Some(block) = block {
+            debug_log!("Hash: {:0256b}", Target::from_hash(&block.hash));
+            self.cleanup_pool(selected_utxos);
+            return Ok(block.with_author_hint(self.recipient.clone()));
         }
 
-        let coinbase_value = chain.rules.reward(chain.height()) + tx_value.fees;
-        if coinbase_value > 0 {
-            txs.push(Transaction::new(TransactionData::new_with_timestamp(
-                vec![],
-                vec![Output {
-                    value: coinbase_value,
-                    pubkey: self.recipient.clone(),
-                }],
-                chain.height() - 1,
-            )));
-        }
-
-        println!("Target: {:0256b}", chain.rules.target);
-        println!("Target leading: {}", chain.rules.target.leading_zeros());
-        let mut leading: u32 = 0;
-        let mut block = new_block(chain, 0, txs);
-        loop {
-            let block_target = Target::from_hash(&block.hash).leading_zeros();
-            if block_target > leading {
-                leading = block_target;
-                println!("Leading: {}", leading);
-            }
-            if chain.rules.validate_target(&block.hash) {
-                println!("Total tries: {}", block.data.nonce + 1);
-                println!("Hash: {:0256b}", Target::from_hash(&block.hash));
-                self.cleanup_pool(&selected_utxos);
-                return Ok(block);
-            }
-            let mut block_data = block.data;
-            if block_data.nonce == u32::MAX {
-                // Mining failed, reinsert transactions in pool
-                for tx in block_data.transactions {
-                    self.add_tx(chain, tx);
+        // Mining failed, reinsert transactions in pool
+        let mut txs = block_data.transactions;
+        txs.retain(|tx| !tx.is_coinbase());
+        self.return_to_pool(txs);
+        if self.cancel.load(Ordering::Relaxed) {
+            debug_log!("Mining cancelled");
+            self.last_hashrate = None;
+            return Err(MiningError::Cancelled);
+        }
+        Err(MiningError::NoBlockFound)
+    }
+
+    /// Searches the nonce space of the block on the given number of spawned threads,
+    /// see [NonceSearch::search]. The progress callback is moved to the threads for
+    /// the duration of the search.
+    ///
+    fn search_nonces_with_threads(
+        &mut self,
+        rules: &ConsensusRules,
+        block_data: &BlockData,
+        threads: usize,
+    ) -> (Option<Block>, u64) {
+        let search = Arc::new(NonceSearch::default());
+        let progress = Arc::new(Mutex::new(self.progress_cb.take()));
+
+        let workers: Vec<_> = nonce_chunks(threads as u64)
+            .map(|nonces| {
+                let (search, progress) = (search.clone(), progress.clone());
+                let (rules, data, cancel) =
+                    (rules.clone(), block_data.clone(), self.cancel.clone());
+                thread::spawn(move || {
+                    let report = |tries: u32, best_zeros: u32| {
+                        if let Some(progress) = progress.lock().unwrap().as_ref() {
+                            progress(tries, best_zeros);
+                        }
+                    };
+                    search.search(&rules, data, nonces, &cancel, report);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        self.progress_cb = Arc::into_inner(progress).unwrap().into_inner().unwrap();
+        Arc::into_inner(search).unwrap().into_result()
+    }
+
+    /// Keeps mining until a block is found or the stop flag is set. Every time the
+    /// nonce space is exhausted, transactions are selected again and a new block
+    /// is built. The stop flag is checked between attempts.
+    ///
+    /// Returns None if mining was stopped or if there are not enough transactions
+    /// in the pool.
+    ///
+    pub fn solo_mine_until_success(
+        &mut self,
+        chain: &Chain,
+        stop: Arc<AtomicBool>,
+    ) -> Option<Block> {
+        mine_until_success(&stop, || self.mine(chain))
+    }
+
+    pub fn mining_stats(&self) -> MiningStats {
+        let seconds = self.mining_time.as_secs_f64();
+        MiningStats {
+            attempts: self.attempts,
+            hashes_per_second: if seconds > 0.0 {
+                self.hashes as f64 / seconds
+            } else {
+                0.0
+            },
+            last_difficulty: self.last_difficulty,
+        }
+    }
+
+    /// Returns the hashes per second of the last [mine](Miner::mine) call, or None if
+    /// it was cancelled or there were not enough transactions
+    ///
+    pub fn last_hashrate(&self) -> Option<f64> {
+        self.last_hashrate
+    }
+
+    /// Estimates the seconds needed to find a block at the given hashrate: a hash meets
+    /// the target with probability 1 / 2^leading_zeros of the target.
+    ///
+    pub fn estimate_seconds_to_block(&self, chain: &Chain, hashrate_hps: f64) -> f64 {
+        let target = chain.compute_retarget_target();
+        let probability = 1.0 / 2_f64.powi(target.leading_zeros() as i32);
+        1.0 / (probability * hashrate_hps)
+    }
+
+    fn record_hashes(&mut self, hashes: u64, elapsed: Duration) {
+        self.hashes += hashes;
+        self.mining_time += elapsed;
+    }
+
+    pub fn add_tx(&mut self, chain: &Chain, tx: Transaction) -> bool {
+        // Dust, expired and low fee transactions are cheap to detect, skip the full validation
+        if let Some(threshold) = chain.rules.dust_threshold {
+            if tx.is_dust(threshold) {
+                return false;
+            }
+        }
+        if tx.is_expired(&chain.height()) {
+            return false;
+        }
+        if let Some(fee) = tx.fee(&chain.chain) {
+            if !chain.rules.validate_min_fee(fee) {
+                return false;
+            }
+        }
+        if !chain.validate_new_tx(&tx) {
+            return false;
+        }
+        if !self.pool.contains_key(&tx.hash) && self.pool.len() >= self.capacity {
+            let fee = |tx: &Transaction| tx.fee(&chain.chain);
+            match self.oldest_in_pool() {
+                Some(oldest) if fee(&self.pool[&oldest]) <= fee(&tx) => {
+                    self.pool.remove(&oldest);
+                    self.insertion_order.pop_front();
                 }
-                return Err(MiningError::NoBlockFound);
+                _ => return false,
             }
-            block_data.nonce += 1;
+        }
+        self.insert_in_pool(tx);
+        true
+    }
 
-            if block_data.nonce % 100000 == 0 {
-                println!("Tries: {}", block_data.nonce)
+    /// Drops the hashes of the transactions no longer in the pool from the front of the
+    /// insertion order, and returns the first one left
+    ///
+    fn oldest_in_pool(&mut self) -> Option<Hash> {
+        while let Some(hash) = self.insertion_order.front() {
+            if self.pool.contains_key(hash) {
+                return Some(hash.clone());
             }
+            self.insertion_order.pop_front();
+        }
+        None
+    }
 
-            block = Block::new(block_data);
+    /// Puts back the transactions selected for a block that was not mined. They were
+    /// validated when added and took their slots in the pool, so they skip both the
+    /// validation and the capacity check.
+    ///
+    fn return_to_pool(&mut self, txs: Vec<Transaction>) {
+        for tx in txs {
+            self.insert_in_pool(tx);
         }
     }
 
-    pub fn add_tx(&mut self, chain: &Chain, tx: Transaction) -> bool {
-        if chain.validate_new_tx(&tx) {
-            self.pool.insert(tx.hash.clone(), tx);
-            return true;
+    fn insert_in_pool(&mut self, tx: Transaction) {
+        // Removed transactions are only dropped from the front, compact from time to time
+        if self.insertion_order.len() >= 2 * self.capacity.max(1) {
+            self.insertion_order
+                .retain(|hash| self.pool.contains_key(hash));
         }
-        return false;
+        if !self.pool.contains_key(&tx.hash) {
+            self.insertion_order.push_back(tx.hash.clone());
+        }
+        self.pool.insert(tx.hash.clone(), tx);
+    }
+
+    /// Replaces the pool transactions spending any input of the new transaction, if the
+    /// new transaction is valid and pays a higher fee than all of them together.
+    ///
+    /// If the consensus rules enforce RBF signaling, the replaced transactions must all
+    /// be RBF eligible.
+    ///
+    /// Returns false and leaves the pool untouched if there is nothing to replace or
+    /// the replacement is not allowed.
+    ///
+    pub fn replace_in_pool(&mut self, chain: &Chain, tx: Transaction) -> bool {
+        let utxos = get_utxos(&tx);
+        let conflicts: Vec<Hash> = self
+            .pool
+            .iter()
+            .filter(|(_, pooled)| !utxos.is_disjoint(&get_utxos(pooled)))
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        if conflicts.is_empty() || !chain.validate_new_tx(&tx) {
+            return false;
+        }
+        if chain.rules.enforce_rbf_signaling
+            && conflicts.iter().any(|hash| self.pool[hash].is_final())
+        {
+            return false;
+        }
+
+        let fee = |tx: &Transaction| {
+            chain.utxo_pool().total_input_value(tx).and_then(|input| {
+                input.checked_sub(Blockchain::get_tx_output_value(&tx.data.outputs))
+            })
+        };
+        let replaced_fee: Option<Value> = conflicts.iter().map(|hash| fee(&self.pool[hash])).sum();
+        match (fee(&tx), replaced_fee) {
+            (Some(new_fee), Some(replaced_fee)) if new_fee > replaced_fee => {}
+            _ => return false,
+        }
+
+        for hash in conflicts {
+            self.pool.remove(&hash);
+        }
+        self.insert_in_pool(tx);
+        true
+    }
+
+    /// Returns the pool transactions with their fee rate, in coins per serialized byte,
+    /// sorted by decreasing fee rate. Ties are sorted by hash, so the order is stable.
+    ///
+    /// Transactions whose value can't be computed, e.g. because their inputs are not
+    /// in the chain, are returned last with a NaN fee rate.
+    ///
+    pub fn pool_by_fee_rate(&self, chain: &Chain) -> Vec<(&Hash, &Transaction, f64)> {
+        let mut rated: Vec<(&Hash, &Transaction, f64)> = Vec::new();
+        let mut unrated: Vec<(&Hash, &Transaction, f64)> = Vec::new();
+        for (hash, tx) in self.pool.iter() {
+            match chain.chain.get_tx_value(tx) {
+                Some(value) => {
+                    let size = tx.into_bytes().len() as f64;
+                    rated.push((hash, tx, value.fees as f64 / size));
+                }
+                None => unrated.push((hash, tx, f64::NAN)),
+            }
+        }
+        rated.sort_by(|a, b| {
+            b.2.total_cmp(&a.2)
+                .then_with(|| a.0.digest().cmp(b.0.digest()))
+        });
+        unrated.sort_by(|a, b| a.0.digest().cmp(b.0.digest()));
+        rated.extend(unrated);
+        rated
+    }
+
+    pub fn pool_stats(&self, chain: &Chain) -> MinerPoolStats {
+        let tx_count = self.pool.len();
+        let total_fees = self
+            .pool
+            .values()
+            .filter_map(|tx| chain.chain.get_tx_value(tx))
+            .fold(0 as Value, |acc, value| acc.saturating_add(value.fees));
+        MinerPoolStats {
+            tx_count,
+            total_fees,
+            avg_fee_per_tx: total_fees.checked_div(tx_count as Value).unwrap_or(0),
+        }
+    }
+
+    /// Returns the total output value of the pool transactions whose value can be
+    /// computed, see [pool_stats](Miner::pool_stats)
+    ///
+    pub fn pool_total_value(&self, chain: &Chain) -> Value {
+        self.pool
+            .values()
+            .filter_map(|tx| chain.chain.get_tx_value(tx))
+            .fold(0, |acc, value| acc.saturating_add(value.output))
+    }
+
+    /// Maps every pool transaction spending the same UTXO as other pool transactions
+    /// to the hashes of those transactions, sorted by hash. Transactions without
+    /// conflicts are not included.
+    ///
+    pub fn conflict_graph(&self) -> HashMap<Hash, Vec<Hash>> {
+        let mut spenders = HashMap::<Utxo, Vec<&Hash>>::new();
+        for (hash, tx) in self.pool.iter() {
+            for utxo in get_utxos(tx) {
+                spenders.entry(utxo).or_default().push(hash);
+            }
+        }
+
+        let mut graph = HashMap::<Hash, Vec<Hash>>::new();
+        for hashes in spenders.values().filter(|hashes| hashes.len() > 1) {
+            for hash in hashes.iter() {
+                let conflicts = graph.entry((*hash).clone()).or_default();
+                for other in hashes.iter().filter(|other| *other != hash) {
+                    if !conflicts.contains(other) {
+                        conflicts.push((*other).clone());
+                    }
+                }
+            }
+        }
+        for conflicts in graph.values_mut() {
+            conflicts.sort_by(|a, b| a.digest().cmp(b.digest()));
+        }
+        graph
+    }
+
+    /// Returns the hashes of the pool transactions that can't be mined because they
+    /// spend the same UTXO as a pool transaction paying a higher fee, sorted by hash.
+    /// Between transactions paying the same fee, the one with the higher hash is
+    /// preferred. Transactions whose fee can't be computed are always the least preferred.
+    ///
+    /// See [conflict_graph](Self::conflict_graph) for the full list of conflicts.
+    ///
+    pub fn find_unconfirmed_dependents(&self, chain: &Chain) -> Vec<Hash> {
+        let priority = |hash: &Hash| {
+            let fee = self
+                .pool
+                .get(hash)
+                .and_then(|tx| chain.chain.get_tx_value(tx))
+                .map(|value| value.fees);
+            (fee, hash.digest().to_owned())
+        };
+        let mut blocked: Vec<Hash> = self
+            .conflict_graph()
+            .into_iter()
+            .filter(|(hash, conflicts)| {
+                let own = priority(hash);
+                conflicts.iter().any(|other| priority(other) > own)
+            })
+            .map(|(hash, _)| hash)
+            .collect();
+        blocked.sort_by(|a, b| a.digest().cmp(b.digest()));
+        blocked
+    }
+
+    /// Removes the pool transactions that are no longer valid for the chain, e.g. because
+    /// their inputs have been spent by a new block or they expired. Returns the number
+    /// of removed transactions.
+    ///
+    pub fn prune_invalid_txs(&mut self, chain: &Chain) -> usize {
+        let count = self.pool.len();
+        self.pool.retain(|_, tx| chain.validate_new_tx(tx));
+        count - self.pool.len()
     }
 
     pub fn cleanup_pool(&mut self, utxos: &HashSet<Utxo>) {
@@ -148,6 +792,149 @@ impl Miner {
     }
 }
 
+/// Calls the mining attempt until it returns a block or the stop flag is set, retrying
+/// only when the nonce space was exhausted. The attempt count is logged every 1000
+/// attempts.
+///
+/// This is the loop of [solo_mine_until_success](Miner::solo_mine_until_success), for
+/// callers that need to lock the chain and the miner for a single attempt at a time.
+///
+pub fn mine_until_success(
+    stop: &AtomicBool,
+    mut attempt: impl FnMut() -> Result<Block, MiningError>,
+) -> Option<Block> {
+    let mut attempts: u64 = 0;
+    while !stop.load(Ordering::Relaxed) {
+        attempts += 1;
+        match attempt() {
+            Ok(block) => return Some(block),
+            Err(MiningError::NoBlockFound) => {
+                if attempts.is_multiple_of(1000) {
+                    debug_log!("Mining attempts: {}", attempts);
+                }
+            }
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// State shared by the workers searching the nonce space of a block
+///
+#[derive(Default)]
+struct NonceSearch {
+    found: AtomicBool,
+    tries: AtomicU64,
+    best_zeros: AtomicU32,
+    winner: Mutex<Option<Block>>,
+}
+
+impl NonceSearch {
+    /// Tries the nonces in batches, checking between batches whether a block was found
+    /// by any worker or the cancel flag is set. The first worker finding a block meeting
+    /// the target stores it as the winner.
+    ///
+    /// The progress function is called every [PROGRESS_INTERVAL] nonces tried by all
+    /// the workers, with the number of nonces tried so far and the most leading zeros
+    /// seen in a hash.
+    ///
+    fn search<F>(
+        &self,
+        rules: &ConsensusRules,
+        mut data: BlockData,
+        nonces: Range<u64>,
+        cancel: &AtomicBool,
+        progress: F,
+    ) where
+        F: Fn(u32, u32),
+    {
+        let mut next = nonces.start;
+        while next < nonces.end
+            && !self.found.load(Ordering::Relaxed)
+            && !cancel.load(Ordering::Relaxed)
+        {
+            let batch = next..(next + NONCE_BATCH).min(nonces.end);
+            next = batch.end;
+            let mut zeros = 0;
+            let mut result = None;
+            for nonce in batch.clone() {
+                data.nonce = nonce as Nonce;
+                let hash = Hash::new(&data.header_bytes());
+                zeros = zeros.max(Target::from_hash(&hash).leading_zeros());
+                if rules.validate_target(&hash) {
+                    result = Some(nonce);
+                    break;
+                }
+            }
+
+            let tried = result.map_or(batch.end, |nonce| nonce + 1) - batch.start;
+            let previous = self.tries.fetch_add(tried, Ordering::Relaxed);
+            let zeros = self
+                .best_zeros
+                .fetch_max(zeros, Ordering::Relaxed)
+                .max(zeros);
+            let total = previous + tried;
+            if total / PROGRESS_INTERVAL > previous / PROGRESS_INTERVAL {
+                progress(total.min(u32::MAX as u64) as u32, zeros);
+            }
+
+            if let Some(nonce) = result {
+                data.nonce = nonce as Nonce;
+                self.winner.lock().unwrap().get_or_insert(Block::new(data));
+                self.found.store(true, Ordering::Relaxed);
+                return;
+            }
+        }
+    }
+
+    /// Returns the block found, if any, and the number of nonces tried
+    ///
+    fn into_result(self) -> (Option<Block>, u64) {
+        (self.winner.into_inner().unwrap(), self.tries.into_inner())
+    }
+}
+
+/// Splits the nonce space into the given number of equal chunks
+///
+fn nonce_chunks(count: u64) -> impl Iterator<Item = Range<u64>> {
+    let nonce_count = Nonce::MAX as u64 + 1;
+    let chunk_len = nonce_count.div_ceil(count.max(1));
+    (0..count.max(1)).map(move |chunk| {
+        let start = chunk * chunk_len;
+        start..(start + chunk_len).min(nonce_count)
+    })
+}
+
+/// Searches the nonce space of the block in parallel: the nonces are split into equal
+/// chunks, one for every rayon thread, see [NonceSearch::search].
+///
+/// Returns the block, if any, and the number of nonces tried by all the workers.
+///
+fn search_nonces(
+    rules: &ConsensusRules,
+    block_data: &BlockData,
+    cancel: &AtomicBool,
+    progress: Option<&Mutex<&mut ProgressCallback>>,
+) -> (Option<Block>, u64) {
+    let search = NonceSearch::default();
+    let report = |tries: u32, best_zeros: u32| {
+        if let Some(progress) = progress {
+            (progress.lock().unwrap())(tries, best_zeros);
+        }
+    };
+
+    rayon::scope(|scope| {
+        for nonces in nonce_chunks(rayon::current_num_threads() as u64) {
+            let (search, report) = (&search, &report);
+            scope.spawn(move |_| {
+                search.search(rules, block_data.clone(), nonces, cancel, report);
+            });
+        }
+    });
+
+    search.into_result()
+}
+
 fn get_utxos(tx: &Transaction) -> HashSet<Utxo> {
     tx.data
         .inputs
@@ -169,19 +956,58 @@ fn merge_utxos(tx: &Transaction, utxos: &mut HashSet<Utxo>) -> bool {
 mod tests {
     use super::*;
     use crate::chain::Chain;
-    use crate::consensus::{ConsensusRules, Halving};
+    use crate::consensus::{ConsensusRulesBuilder, Halving};
+    use crate::core::blockchain::Height;
     use crate::core::keys::KeyPair;
     use crate::core::transaction::{Input, Output, TransactionData};
+    use crate::utils::{new_block, new_tx, new_tx_with_fee, TransactionBuilder};
+
+    /// Builds a chain whose block 1 splits the genesis coinbase of the key into outputs
+    /// of the given values. Returns it with a function building a transaction that
+    /// spends the output at an index, paying its value minus the fee to the recipient.
+    ///
+    fn split_genesis<'a>(
+        key: &'a KeyPair,
+        recipient: PublicKey,
+        values: &[Value],
+    ) -> (Chain, impl Fn(u32, Value) -> Transaction + 'a) {
+        let mut chain = Chain::new_with_consensus(
+            &key.public_key(),
+            ConsensusRulesBuilder::default()
+                .target(Target::MAX)
+                .coins_per_block(100000)
+                .halving(Halving::None)
+                .build(),
+        );
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        let outputs = values
+            .iter()
+            .map(|value| Output::new(*value, key.public_key()))
+            .collect();
+        let tx = new_tx(key, &utxos, outputs).unwrap();
+        let split_hash = tx.hash.clone();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+
+        let values = values.to_vec();
+        let spend = move |index: u32, fee: Value| {
+            let input_value = values[index as usize];
+            new_tx_with_fee(
+                key,
+                &[Utxo::new(split_hash.clone(), index, input_value)],
+                vec![Output::new(input_value - fee, recipient.clone())],
+                fee,
+            )
+            .unwrap()
+        };
+        (chain, spend)
+    }
 
     #[test]
     fn mining() {
         let key_1 = KeyPair::new();
         let key_2 = KeyPair::new();
 
-        let mut chain = Chain::new_with_consensus(
-            &key_1.public_key(),
-            ConsensusRules::new(Target::from_leading_zeros(0), 10000, Halving::None),
-        );
+        let mut chain = Chain::new(&key_1.public_key());
 
         let last_block = chain.chain.get_last_block();
         let last_coinbase = &last_block.data.transactions[0];
@@ -191,14 +1017,13 @@ mod tests {
                 hash: last_coinbase.hash.clone(),
                 index: 0,
                 signature: key_1.sign(last_coinbase.hash.digest()),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
             }],
-            vec![Output {
-                value: 5000,
-                pubkey: key_2.public_key(),
-            }],
+            vec![Output::new(5000, key_2.public_key())],
         ));
 
-        let mut miner = Miner::new(key_1.public_key());
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
         miner.add_tx(&chain, tx);
 
         assert_eq!(miner.pool.len(), 1);
@@ -208,10 +1033,666 @@ mod tests {
         assert!(block.is_ok());
 
         let block = block.unwrap();
+        assert_eq!(block.author_hint(), Some(&key_1.public_key()));
         assert!(chain.validate_new_block(&block));
 
         let result = chain.add_block(block);
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn custom_coinbase() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let pool_key = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(5000, key_2.public_key())],
+            100,
+        )
+        .unwrap();
+        let coinbase = |outputs: Vec<(&KeyPair, Value)>| {
+            Transaction::new(TransactionData::new_with_timestamp(
+                vec![],
+                outputs
+                    .into_iter()
+                    .map(|(key, value)| Output::new(value, key.public_key()))
+                    .collect(),
+                chain.height() - 1,
+            ))
+        };
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(miner.add_tx(&chain, tx.clone()));
+
+        // Reward and fees above the expected value
+        let result = miner.mine_with_custom_coinbase(&chain, coinbase(vec![(&pool_key, 10101)]));
+        assert!(matches!(result, Err(MiningError::InvalidCoinbase)));
+        assert!(miner.pool.contains_key(&tx.hash));
+
+        // Wrong height
+        let mut invalid = coinbase(vec![(&pool_key, 100)]);
+        invalid.data.timestamp = Some(5);
+        let invalid = Transaction::new(invalid.data);
+        let result = miner.mine_with_custom_coinbase(&chain, invalid);
+        assert!(matches!(result, Err(MiningError::InvalidCoinbase)));
+        assert_eq!(miner.pool.len(), 1);
+
+        // Reward and fees split between two keys
+        let custom = coinbase(vec![(&pool_key, 6000), (&key_2, 4100)]);
+        let block = miner
+            .mine_with_custom_coinbase(&chain, custom.clone())
+            .unwrap();
+        assert_eq!(block.transactions().last(), Some(&custom));
+        assert!(miner.pool.is_empty());
+        assert!(chain.validate_new_block(&block));
+
+        let mut chain = chain;
+        assert!(chain.add_block(block).is_ok());
+        assert_eq!(chain.get_balance(&pool_key.public_key()).unwrap(), 6000);
+        assert_eq!(chain.get_balance(&key_2.public_key()).unwrap(), 9100);
+    }
+
+    #[test]
+    fn mining_target() {
+        let key = KeyPair::new();
+        let target = Target::from_leading_zeros(8);
+        let mut chain = Chain::new_with_consensus(
+            &key.public_key(),
+            ConsensusRulesBuilder::default()
+                .target(target.clone())
+                .coins_per_block(10000)
+                .halving(Halving::None)
+                .build(),
+        );
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        let tx = new_tx(&key, &utxos, vec![Output::new(5000, key.public_key())]).unwrap();
+
+        let mut miner = Miner::new(key.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(miner.add_tx(&chain, tx));
+
+        let block = miner.mine(&chain).unwrap();
+        assert!(block.is_hash_valid());
+        assert!(Target::from_hash(&block.hash) <= target);
+        assert!(miner.pool.is_empty());
+        assert!(chain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn parallel_nonce_search() {
+        let key = KeyPair::new();
+        let rules = ConsensusRulesBuilder::default()
+            .target(Target::from_leading_zeros(8))
+            .coins_per_block(10000)
+            .halving(Halving::None)
+            .build();
+        let chain = Chain::new_with_consensus(&key.public_key(), rules.clone());
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        let tx = new_tx(&key, &utxos, vec![Output::new(5000, key.public_key())]).unwrap();
+        let block_data = BlockData::new_with_prefix(
+            chain.get_last_block().hash.clone(),
+            rand::random(),
+            0,
+            vec![tx],
+        );
+
+        // Single-threaded search over the whole nonce space
+        let (nonce, _) = Hash::find_below_target(&rules.target, 0..Nonce::MAX as u64, |nonce| {
+            BlockData {
+                nonce: nonce as Nonce,
+                ..block_data.clone()
+            }
+            .header_bytes()
+        })
+        .unwrap();
+        let sequential = Block::new(BlockData {
+            nonce: nonce as Nonce,
+            ..block_data.clone()
+        });
+
+        let (block, tries) = search_nonces(&rules, &block_data, &AtomicBool::new(false), None);
+        let block = block.unwrap();
+        assert!(tries > 0);
+        assert!(rules.validate_target(&block.hash));
+        assert_eq!(block, Block::new(block.data.clone()));
+        // Only the nonce can differ
+        let mut data = block.data.clone();
+        data.nonce = sequential.data.nonce;
+        assert_eq!(Block::new(data), sequential);
+    }
+
+    #[test]
+    fn mining_with_threads() {
+        let key = KeyPair::new();
+        let target = Target::from_leading_zeros(8);
+        let mut chain = Chain::new_with_consensus(
+            &key.public_key(),
+            ConsensusRulesBuilder::default()
+                .target(target.clone())
+                .coins_per_block(10000)
+                .halving(Halving::None)
+                .build(),
+        );
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        let tx = new_tx(&key, &utxos, vec![Output::new(5000, key.public_key())]).unwrap();
+
+        let mut miner = Miner::new(key.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(miner.add_tx(&chain, tx.clone()));
+        let block = miner.mine_with_threads(&chain, 2).unwrap();
+        assert!(chain.rules.validate_target(&block.hash));
+        assert!(block.is_hash_valid());
+        assert!(block.transactions().contains(&tx));
+        assert!(miner.pool.is_empty());
+        assert!(miner.last_hashrate().is_some());
+        assert!(chain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn fee_first_selection() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        // One large utxo paying a high fee, six small ones paying the minimum fee
+        let (mut chain, spend) = split_genesis(
+            &key_1,
+            key_2.public_key(),
+            &[10000, 100, 100, 100, 100, 100, 100],
+        );
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY)
+            .with_selection_strategy(SelectionStrategy::FeeFirst);
+        assert_eq!(miner.selection_strategy(), SelectionStrategy::FeeFirst);
+        let high_fee = spend(0, 9000);
+        assert!(miner.add_tx(&chain, high_fee.clone()));
+        let low_fee: Vec<Transaction> = (1..7).map(|index| spend(index, 1)).collect();
+        for tx in low_fee.iter() {
+            assert!(miner.add_tx(&chain, tx.clone()));
+        }
+        assert_eq!(chain.chain.get_tx_value(&high_fee).unwrap().fees, 9000);
+
+        let block = miner.mine(&chain).unwrap();
+        let transactions = block.transactions();
+        // Five pool transactions and the coinbase
+        assert_eq!(transactions.len(), 6);
+        assert!(transactions.contains(&high_fee));
+        assert_eq!(miner.pool.len(), 2);
+        assert!(!miner.pool.contains_key(&high_fee.hash));
+        assert_eq!(
+            transactions.last().unwrap().data.outputs[0].value,
+            100000 + 9000 + 4
+        );
+        let coinbase_size = transactions.last().unwrap().serialized_size();
+        assert!(chain.add_block(block).is_ok());
+
+        // Only one of the remaining transactions fits next to the coinbase
+        chain.rules.max_block_size = Some(coinbase_size + low_fee[0].serialized_size());
+        let block = miner.mine(&chain).unwrap();
+        assert_eq!(block.transactions().len(), 2);
+        assert_eq!(miner.pool.len(), 1);
+        assert!(chain.add_block(block).is_ok());
+    }
+
+    #[test]
+    fn cancel_mining() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        // No hash can meet the target, mining only ends when cancelled
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.target = Target::from_leading_zeros(255);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(miner.add_tx(&chain, tx.clone()));
+        let cancel = miner.cancel_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let result = miner.mine(&chain);
+        canceller.join().unwrap();
+        assert!(matches!(result, Err(MiningError::Cancelled)));
+        assert!(miner.pool.contains_key(&tx.hash));
+        let stats = miner.mining_stats();
+        assert!(stats.hashes_per_second > 0.0);
+        assert_eq!(miner.last_hashrate(), None);
+
+        // The flag stays set until reset
+        miner.cancel();
+        let stop = Arc::new(AtomicBool::new(false));
+        assert!(miner.solo_mine_until_success(&chain, stop).is_none());
+        assert!(miner.pool.contains_key(&tx.hash));
+
+        miner.reset_cancel();
+        chain.rules.target = Target::MAX;
+        assert!(miner.mine(&chain).is_ok());
+        assert!(miner.last_hashrate().unwrap() > 0.0);
+
+        // Nothing left to mine
+        assert!(matches!(
+            miner.mine(&chain),
+            Err(MiningError::NotEnoughTransactions)
+        ));
+        assert_eq!(miner.last_hashrate(), None);
+    }
+
+    #[test]
+    fn progress_callback() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.target = Target::from_leading_zeros(255);
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(miner.add_tx(&chain, tx));
+        let calls = Arc::new(Mutex::new(Vec::<(u32, u32)>::new()));
+        let calls_ref = calls.clone();
+        let cancel = miner.cancel_handle();
+        // Stop mining after the second report
+        miner.with_progress_callback(move |tries, best_zeros| {
+            let mut calls = calls_ref.lock().unwrap();
+            calls.push((tries, best_zeros));
+            if calls.len() == 2 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        });
+        assert!(matches!(miner.mine(&chain), Err(MiningError::Cancelled)));
+
+        let calls = calls.lock().unwrap();
+        assert!(calls.len() >= 2);
+        assert!(calls
+            .iter()
+            .all(|(tries, best_zeros)| *tries as u64 >= PROGRESS_INTERVAL && *best_zeros > 0));
+        assert!(calls
+            .iter()
+            .any(|(tries, _)| *tries as u64 >= 2 * PROGRESS_INTERVAL));
+    }
+
+    #[test]
+    fn estimate_seconds_to_block() {
+        let key = KeyPair::new();
+        let miner = Miner::new(key.public_key(), DEFAULT_POOL_CAPACITY);
+        let mut chain = Chain::new(&key.public_key());
+
+        // Every hash succeeds
+        assert_eq!(miner.estimate_seconds_to_block(&chain, 1.0), 1.0);
+        assert!(miner.estimate_seconds_to_block(&chain, 1_000_000.0) < 1e-5);
+
+        chain.rules.target = Target::from_leading_zeros(32);
+        let estimate = miner.estimate_seconds_to_block(&chain, 1_000_000.0);
+        assert!((estimate - 2_f64.powi(32) / 1_000_000.0).abs() < 1e-6);
+        assert_eq!(miner.estimate_seconds_to_block(&chain, 0.0), f64::INFINITY);
+    }
+
+    #[test]
+    fn pool_stats() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let (chain, spend) = split_genesis(&key_1, key_2.public_key(), &[1000, 2000, 3000]);
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        let empty = MinerPoolStats {
+            tx_count: 0,
+            total_fees: 0,
+            avg_fee_per_tx: 0,
+        };
+        assert_eq!(miner.pool_stats(&chain), empty);
+        assert_eq!(miner.pool_total_value(&chain), 0);
+
+        for (index, fee) in [(0, 100), (1, 0), (2, 250)] {
+            assert!(miner.add_tx(&chain, spend(index, fee)));
+        }
+
+        assert_eq!(
+            miner.pool_stats(&chain),
+            MinerPoolStats {
+                tx_count: 3,
+                total_fees: 350,
+                avg_fee_per_tx: 116,
+            }
+        );
+        assert_eq!(miner.pool_total_value(&chain), 5650);
+    }
+
+    #[test]
+    fn prune_invalid_txs() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
+        let conflicting =
+            new_tx(&key_1, &utxos, vec![Output::new(6000, key_2.public_key())]).unwrap();
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(miner.add_tx(&chain, tx.clone()));
+        assert_eq!(miner.prune_invalid_txs(&chain), 0);
+        assert_eq!(miner.pool.len(), 1);
+
+        // The competing block spends the same utxo
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![conflicting.clone()]))
+            .is_ok());
+        assert_eq!(miner.prune_invalid_txs(&chain), 1);
+        assert!(miner.pool.is_empty());
+
+        // The transaction itself can be in the new block
+        let utxos = chain.find_utxos_for_key(&key_2.public_key());
+        let tx = new_tx(&key_2, &utxos, vec![Output::new(100, key_2.public_key())]).unwrap();
+        assert!(miner.add_tx(&chain, tx.clone()));
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        assert_eq!(miner.prune_invalid_txs(&chain), 1);
+        assert!(miner.pool.is_empty());
+
+        // No block is mined when none of the selected transactions is still valid
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(100, key_2.public_key())]).unwrap();
+        let conflicting =
+            new_tx(&key_1, &utxos, vec![Output::new(200, key_2.public_key())]).unwrap();
+        assert!(miner.add_tx(&chain, tx));
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![conflicting]))
+            .is_ok());
+        assert!(matches!(
+            miner.mine(&chain),
+            Err(MiningError::NotEnoughTransactions)
+        ));
+        assert!(miner.pool.is_empty());
+    }
+
+    #[test]
+    fn expired_txs() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let split = new_tx(&key_1, &utxos, vec![Output::new(5000, key_1.public_key())]).unwrap();
+        let split_hash = split.hash.clone();
+        assert!(chain.add_block(new_block(&chain, 0, vec![split])).is_ok());
+
+        let spend = |index: u32, expires_at: Option<u64>| {
+            let mut builder = TransactionBuilder::new(&key_1);
+            builder
+                .add_utxo(Utxo::new(split_hash.clone(), index, 5000))
+                .add_output(key_2.public_key(), 5000);
+            if let Some(height) = expires_at {
+                builder.expires_at(Height::from(height));
+            }
+            builder.build().unwrap()
+        };
+        let expiring = spend(0, Some(3));
+        let regular = spend(1, None);
+        assert!(!expiring.is_expired(&chain.height()));
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(!miner.add_tx(&chain, spend(0, Some(2))));
+        assert!(miner.add_tx(&chain, expiring.clone()));
+        assert!(miner.add_tx(&chain, regular.clone()));
+
+        assert!(chain.add_block(new_block(&chain, 0, vec![regular])).is_ok());
+        assert!(expiring.is_expired(&chain.height()));
+        assert!(!chain.validate_new_tx(&expiring));
+        assert!(chain
+            .add_block(new_block(&chain, 0, vec![expiring]))
+            .is_err());
+        assert_eq!(miner.prune_invalid_txs(&chain), 2);
+        assert!(miner.pool.is_empty());
+    }
+
+    #[test]
+    fn pool_capacity() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let (chain, spend) = split_genesis(&key_1, key_2.public_key(), &[10000, 100, 100]);
+        let oldest = spend(1, 5);
+        let newer = spend(2, 1);
+
+        let mut miner = Miner::new(key_1.public_key(), 2);
+        assert!(miner.add_tx(&chain, oldest.clone()));
+        assert!(miner.add_tx(&chain, newer.clone()));
+        // Adding the same transaction again doesn't evict anything
+        assert!(miner.add_tx(&chain, newer.clone()));
+        assert_eq!(miner.pool.len(), 2);
+
+        // The oldest transaction pays more than the new one
+        let low_fee = spend(0, 1);
+        assert!(!miner.add_tx(&chain, low_fee.clone()));
+        assert!(miner.pool.contains_key(&oldest.hash));
+        assert!(!miner.pool.contains_key(&low_fee.hash));
+
+        // A high fee transaction displaces the oldest one
+        let high_fee = spend(0, 9000);
+        assert!(miner.add_tx(&chain, high_fee.clone()));
+        assert_eq!(miner.pool.len(), 2);
+        assert!(!miner.pool.contains_key(&oldest.hash));
+        assert!(miner.pool.contains_key(&newer.hash));
+        assert!(miner.pool.contains_key(&high_fee.hash));
+
+        // Transactions removed from the pool are skipped
+        miner.pool.remove(&newer.hash);
+        assert!(miner.add_tx(&chain, oldest.clone()));
+        assert!(!miner.add_tx(&chain, newer));
+        assert_eq!(miner.pool.len(), 2);
+        assert!(miner.pool.contains_key(&high_fee.hash));
+
+        let mut empty = Miner::new(key_1.public_key(), 0);
+        assert!(!empty.add_tx(&chain, oldest));
+        assert!(empty.pool.is_empty());
+    }
+
+    #[test]
+    fn solo_mining() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+
+        let chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Nothing to mine
+        assert!(miner
+            .solo_mine_until_success(&chain, stop.clone())
+            .is_none());
+        assert_eq!(miner.mining_stats().attempts, 0);
+
+        assert!(miner.add_tx(&chain, tx));
+
+        // Stopped before the first attempt
+        stop.store(true, Ordering::Relaxed);
+        assert!(miner
+            .solo_mine_until_success(&chain, stop.clone())
+            .is_none());
+        assert_eq!(miner.pool.len(), 1);
+
+        stop.store(false, Ordering::Relaxed);
+        let block = miner.solo_mine_until_success(&chain, stop).unwrap();
+        assert!(chain.validate_new_block(&block));
+
+        let stats = miner.mining_stats();
+        assert_eq!(stats.attempts, 1);
+        assert_eq!(stats.last_difficulty, 0);
+        assert!(stats.hashes_per_second >= 0.0);
+    }
+
+    #[test]
+    fn replace_in_pool() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        chain.rules.enforce_rbf_signaling = true;
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let outputs = vec![Output::new(5000, key_2.public_key())];
+
+        let mut original = new_tx_with_fee(&key_1, &utxos, outputs.clone(), 10).unwrap();
+        let lower_fee = new_tx_with_fee(&key_1, &utxos, outputs.clone(), 5).unwrap();
+        let higher_fee = new_tx_with_fee(&key_1, &utxos, outputs.clone(), 20).unwrap();
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+
+        // Nothing to replace
+        assert!(!miner.replace_in_pool(&chain, original.clone()));
+
+        // Final transactions can't be replaced
+        assert!(original.is_final());
+        assert!(miner.add_tx(&chain, original.clone()));
+        assert!(!miner.replace_in_pool(&chain, higher_fee.clone()));
+
+        original.data.inputs[0].sequence = 0;
+        let original = Transaction::new(original.data);
+        assert!(original.is_rbf_eligible());
+        miner.pool.clear();
+        assert!(miner.add_tx(&chain, original.clone()));
+
+        assert!(!miner.replace_in_pool(&chain, lower_fee));
+        assert!(miner.pool.contains_key(&original.hash));
+
+        assert!(miner.replace_in_pool(&chain, higher_fee.clone()));
+        assert_eq!(miner.pool.len(), 1);
+        assert!(miner.pool.contains_key(&higher_fee.hash));
+    }
+
+    #[test]
+    fn conflict_graph() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+        let outputs = vec![Output::new(100, key_2.public_key())];
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        assert!(miner.conflict_graph().is_empty());
+
+        let tx_1 = new_tx_with_fee(&key_1, &utxos, outputs.clone(), 10).unwrap();
+        assert!(miner.add_tx(&chain, tx_1.clone()));
+        assert!(miner.conflict_graph().is_empty());
+
+        let tx_2 = new_tx_with_fee(&key_1, &utxos, outputs.clone(), 20).unwrap();
+        let tx_3 = new_tx_with_fee(&key_1, &utxos, outputs, 30).unwrap();
+        assert!(miner.add_tx(&chain, tx_2.clone()));
+        assert!(miner.add_tx(&chain, tx_3.clone()));
+        let graph = miner.conflict_graph();
+        assert_eq!(graph.len(), 3);
+        let sorted = |mut hashes: Vec<Hash>| {
+            hashes.sort_by(|a, b| a.digest().cmp(b.digest()));
+            hashes
+        };
+        assert_eq!(
+            graph[&tx_1.hash],
+            sorted(vec![tx_2.hash.clone(), tx_3.hash.clone()])
+        );
+        assert_eq!(
+            graph[&tx_2.hash],
+            sorted(vec![tx_1.hash.clone(), tx_3.hash.clone()])
+        );
+        assert_eq!(graph[&tx_3.hash], sorted(vec![tx_1.hash, tx_2.hash]));
+    }
+
+    #[test]
+    fn pool_by_fee_rate() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        for fee in [10, 30, 20] {
+            // Same size, different fees
+            miner.add_tx(
+                &chain,
+                new_tx_with_fee(
+                    &key_1,
+                    &utxos,
+                    vec![Output::new(100, key_2.public_key())],
+                    fee,
+                )
+                .unwrap(),
+            );
+        }
+        let missing = Transaction::new(TransactionData::new(
+            vec![Input {
+                hash: Hash::new(b"missing"),
+                index: 0,
+                signature: key_1.sign(b"missing"),
+                sequence: Input::SEQUENCE_FINAL,
+                reveal: None,
+            }],
+            vec![Output::new(100, key_2.public_key())],
+        ));
+        miner.pool.insert(missing.hash.clone(), missing.clone());
+
+        let sorted = miner.pool_by_fee_rate(&chain);
+        assert_eq!(sorted.len(), 4);
+        let size = sorted[0].1.into_bytes().len() as f64;
+        let rates: Vec<f64> = sorted[..3].iter().map(|(_, _, rate)| rate * size).collect();
+        assert_eq!(rates, vec![30.0, 20.0, 10.0]);
+        assert_eq!(sorted[3].0, &missing.hash);
+        assert!(sorted[3].2.is_nan());
+
+        let hashes: Vec<&Hash> = sorted.iter().map(|(hash, _, _)| *hash).collect();
+        let again: Vec<&Hash> = miner
+            .pool_by_fee_rate(&chain)
+            .iter()
+            .map(|(hash, _, _)| *hash)
+            .collect();
+        assert_eq!(hashes, again);
+    }
+
+    #[test]
+    fn pool_conflicts() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let tx = new_tx(
+            &key_1,
+            &chain.find_utxos_for_key(&key_1.public_key()),
+            vec![Output::new(100, key_2.public_key())],
+        )
+        .unwrap();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+        let output = vec![Output::new(10, key_2.public_key())];
+        let new_pool_tx = |key: &KeyPair, fee: Value| {
+            let utxos = chain.find_utxos_for_key(&key.public_key());
+            new_tx_with_fee(key, &utxos, output.clone(), fee).unwrap()
+        };
+
+        let mut miner = Miner::new(key_1.public_key(), DEFAULT_POOL_CAPACITY);
+        let independent = new_pool_tx(&key_2, 5);
+        assert!(miner.add_tx(&chain, independent.clone()));
+        assert!(miner.find_unconfirmed_dependents(&chain).is_empty());
+
+        let low_fee = new_pool_tx(&key_1, 10);
+        let high_fee = new_pool_tx(&key_1, 30);
+        let mid_fee = new_pool_tx(&key_1, 20);
+        for tx in [&low_fee, &high_fee, &mid_fee] {
+            assert!(miner.add_tx(&chain, tx.clone()));
+        }
+        let mut expected = vec![low_fee.hash.clone(), mid_fee.hash.clone()];
+        expected.sort_by(|a, b| a.digest().cmp(b.digest()));
+        assert_eq!(miner.find_unconfirmed_dependents(&chain), expected);
+
+        let graph = miner.conflict_graph();
+        assert_eq!(graph.len(), 3);
+        assert!(!graph.contains_key(&independent.hash));
+        assert_eq!(graph[&high_fee.hash].len(), 2);
+
+        // Same fee, only one of them can be mined
+        miner.pool.remove(&high_fee.hash);
+        miner.pool.remove(&mid_fee.hash);
+        let same_fee = new_tx_with_fee(
+            &key_1,
+            &chain.find_utxos_for_key(&key_1.public_key()),
+            vec![Output::new(11, key_2.public_key())],
+            10,
+        )
+        .unwrap();
+        assert!(miner.add_tx(&chain, same_fee.clone()));
+        assert_eq!(miner.find_unconfirmed_dependents(&chain).len(), 1);
+    }
 }
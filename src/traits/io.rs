@@ -36,6 +36,8 @@ impl fmt::Display for IOError {
     }
 }
 
+impl std::error::Error for IOError {}
+
 /// Implements ser/de to/from byte arrays
 ///
 pub trait ByteIO: Serialize + for<'a> Deserialize<'a> {
@@ -110,4 +112,30 @@ pub trait JsonIO: Sized + Serialize + for<'a> Deserialize<'a> {
             Err(_) => Err(IOError::DeserializationFailed),
         }
     }
+
+    fn from_json_file(path: &Path) -> Result<Self, IOError> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Err(IOError::FileOperationFailed),
+        };
+
+        let mut string = String::new();
+        if file.read_to_string(&mut string).is_err() {
+            return Err(IOError::FileOperationFailed);
+        }
+        Self::from_json(string.as_str())
+    }
+
+    fn to_json_file(&self, path: &Path) -> Result<usize, IOError> {
+        let json = self.to_json()?;
+        let mut file = match File::create(path) {
+            Ok(file) => file,
+            Err(_) => return Err(IOError::FileOperationFailed),
+        };
+
+        match file.write_all(json.as_bytes()) {
+            Ok(_) => Ok(json.len()),
+            Err(_) => Err(IOError::FileOperationFailed),
+        }
+    }
 }
@@ -3,13 +3,15 @@
 
 use crate::chain::Chain;
 use crate::core::block::{Block, BlockData, Nonce};
-use crate::core::blockchain::Blockchain;
+use crate::core::blockchain::{Blockchain, Height};
 use crate::core::hash::Hash;
 use crate::core::keys::KeyPair;
 use crate::core::keys::PublicKey;
 use crate::core::transaction::{Output, Transaction, TransactionData, Value};
 use crate::traits::io::IOError;
 use crate::utxo::{IntoInputs, Utxo, UtxoError};
+use std::collections::HashMap;
+use std::fmt;
 
 /// Serialize a list of [UTXO](Utxo)s into json
 ///
@@ -34,42 +36,166 @@ pub fn json_to_utxos(json: &str) -> Result<Vec<Utxo>, IOError> {
 pub fn new_coinbase_tx(pubkey: &PublicKey, value: Value, timestamp: u64) -> Transaction {
     Transaction::new(TransactionData::new_with_timestamp(
         Vec::new(),
-        vec![Output {
-            value,
-            pubkey: pubkey.clone(),
-        }],
+        vec![Output::new(value, pubkey.clone())],
         timestamp,
     ))
 }
 
-/// Create a new genesis block by specifying a single public key and a value
-pub fn new_genesis_block(pubkey: &PublicKey, coinbase_value: Value) -> Block {
-    Block::new(BlockData::new(
+fn new_genesis_block_data(pubkey: &PublicKey, coinbase_value: Value) -> BlockData {
+    BlockData::new(
         Hash::default(),
         0,
         vec![new_coinbase_tx(pubkey, coinbase_value, 0)],
-    ))
+    )
+}
+
+/// Create a new genesis block by specifying a single public key and a value
+pub fn new_genesis_block(pubkey: &PublicKey, coinbase_value: Value) -> Block {
+    Block::new(new_genesis_block_data(pubkey, coinbase_value))
+}
+
+/// Create a new genesis block like [new_genesis_block], also recording its creation time.
+/// The coinbase timestamp still encodes the block height (0), the Unix timestamp is stored
+/// in the block data.
+///
+pub fn new_genesis_block_with_timestamp(
+    pubkey: &PublicKey,
+    coinbase_value: Value,
+    unix_timestamp: u64,
+) -> Block {
+    let mut data = new_genesis_block_data(pubkey, coinbase_value);
+    data.unix_timestamp = Some(unix_timestamp);
+    Block::new(data)
 }
 
 /// Create a new transaction from a private key, a list of utxos and a list of outputs
 ///
 pub fn new_tx(
+    key: &KeyPair,
+    utxos: &[Utxo],
+    outputs: Vec<Output>,
+) -> Result<Transaction, UtxoError> {
+    new_tx_with_fee(key, utxos, outputs, 0)
+}
+
+/// Create a new transaction from a private key, a list of utxos and a list of outputs,
+/// leaving exactly the specified fee to the miner. The remaining value is sent back
+/// to the key as change.
+///
+pub fn new_tx_with_fee(
     key: &KeyPair,
     utxos: &[Utxo],
     mut outputs: Vec<Output>,
+    fee: Value,
 ) -> Result<Transaction, UtxoError> {
-    let value = Blockchain::get_tx_output_value(&outputs);
+    let value = match Blockchain::get_tx_output_value(&outputs).checked_add(fee) {
+        Some(value) => value,
+        None => return Err(UtxoError::InvalidValue),
+    };
     let selection = Utxo::collect(utxos, value)?;
-    let inputs = selection.list.into_inputs(key);
+    let inputs = selection.list.as_slice().into_inputs(key);
     if selection.change != 0 {
-        outputs.push(Output {
-            value: selection.change,
-            pubkey: key.public_key(),
-        });
+        outputs.push(Output::new(selection.change, key.public_key()));
     }
     Ok(Transaction::new(TransactionData::new(inputs, outputs)))
 }
 
+/// Create a new transaction paying every recipient the given value, sending the
+/// remaining value back to the key as change. The outputs are ordered by recipient
+/// address, so the same payments always produce the same transaction.
+///
+pub fn new_tx_multi_output(
+    key: &KeyPair,
+    utxos: &[Utxo],
+    payments: HashMap<PublicKey, Value>,
+) -> Result<Transaction, UtxoError> {
+    let mut outputs: Vec<Output> = payments
+        .into_iter()
+        .map(|(pubkey, value)| Output::new(value, pubkey))
+        .collect();
+    outputs.sort_by(|a, b| a.pubkey.as_bytes().cmp(b.pubkey.as_bytes()));
+    new_tx(key, utxos, outputs)
+}
+
+/// Builder for transactions signed by a single key. UTXOs and outputs are added one
+/// by one, then [build](TransactionBuilder::build) selects the UTXOs covering the
+/// outputs and sends the remaining value back to the key as change, as [new_tx] does.
+///
+pub struct TransactionBuilder<'a> {
+    key: &'a KeyPair,
+    utxos: Vec<Utxo>,
+    outputs: Vec<Output>,
+    expires_at: Option<Height>,
+}
+
+impl<'a> TransactionBuilder<'a> {
+    pub fn new(key: &'a KeyPair) -> Self {
+        TransactionBuilder {
+            key,
+            utxos: Vec::new(),
+            outputs: Vec::new(),
+            expires_at: None,
+        }
+    }
+
+    pub fn add_utxo(&mut self, utxo: Utxo) -> &mut Self {
+        self.utxos.push(utxo);
+        self
+    }
+
+    pub fn add_output(&mut self, pubkey: PublicKey, value: Value) -> &mut Self {
+        self.outputs.push(Output::new(value, pubkey));
+        self
+    }
+
+    /// Adds an output as is, keeping its spending condition, locktime and data
+    ///
+    pub fn add_full_output(&mut self, output: Output) -> &mut Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Sets the height from which the transaction can't be included in blocks anymore
+    ///
+    pub fn expires_at(&mut self, height: Height) -> &mut Self {
+        self.expires_at = Some(height);
+        self
+    }
+
+    pub fn build(self) -> Result<Transaction, UtxoError> {
+        let tx = new_tx(self.key, &self.utxos, self.outputs)?;
+        if self.expires_at.is_none() {
+            return Ok(tx);
+        }
+        Ok(Transaction::new(TransactionData {
+            expires_at: self.expires_at,
+            ..tx.data
+        }))
+    }
+}
+
+/// Create a batch of transactions from a private key, a list of utxos and a list of
+/// outputs for every transaction. All the transactions are signed by the same key and
+/// spend different utxos: each transaction selects its inputs from the utxos that are
+/// not used by the previous ones, as [new_tx] does. Returns an error if any of the
+/// transactions can't be created.
+///
+pub fn sign_tx_batch(
+    key: &KeyPair,
+    utxos: &[Utxo],
+    batch: Vec<Vec<Output>>,
+) -> Result<Vec<Transaction>, UtxoError> {
+    let mut remaining = utxos;
+    batch
+        .into_iter()
+        .map(|outputs| {
+            let tx = new_tx(key, remaining, outputs)?;
+            remaining = &remaining[tx.data.inputs.len()..];
+            Ok(tx)
+        })
+        .collect()
+}
+
 /// Create a new block from a nonce and a list of transactions
 ///
 pub fn new_block(chain: &Chain, nonce: Nonce, transactions: Vec<Transaction>) -> Block {
@@ -80,10 +206,80 @@ pub fn new_block(chain: &Chain, nonce: Nonce, transactions: Vec<Transaction>) ->
     ))
 }
 
+/// Errors returned when parsing coin amounts
+#[derive(PartialEq, Debug)]
+pub enum ParseCoinsError {
+    InvalidFormat,
+    TooManyDecimals,
+    Overflow,
+}
+
+impl fmt::Display for ParseCoinsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Parse coins error: {}",
+            match self {
+                ParseCoinsError::InvalidFormat => "invalid format",
+                ParseCoinsError::TooManyDecimals => "too many decimals",
+                ParseCoinsError::Overflow => "overflow",
+            }
+        )
+    }
+}
+
+impl std::error::Error for ParseCoinsError {}
+
+/// Formats a value as coins, where the last decimals digits are the sub-units,
+/// e.g. `format_coins(12345, 2) == "123.45"`
+///
+pub fn format_coins(value: Value, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return value.to_string();
+    }
+    let digits = format!("{:0>width$}", value, width = decimals + 1);
+    let (units, sub_units) = digits.split_at(digits.len() - decimals);
+    format!("{}.{}", units, sub_units)
+}
+
+/// Parses an amount of coins formatted like [format_coins] into a value. Fewer
+/// decimal digits than decimals are allowed, more are not.
+///
+pub fn parse_coins(string: &str, decimals: u8) -> Result<Value, ParseCoinsError> {
+    let (units, sub_units) = match string.split_once('.') {
+        Some((units, sub_units)) if !sub_units.is_empty() => (units, sub_units),
+        Some(_) => return Err(ParseCoinsError::InvalidFormat),
+        None => (string, ""),
+    };
+    let is_number = |digits: &str| digits.chars().all(|c| c.is_ascii_digit());
+    if units.is_empty() || !is_number(units) || !is_number(sub_units) {
+        return Err(ParseCoinsError::InvalidFormat);
+    }
+    if sub_units.len() > decimals as usize {
+        return Err(ParseCoinsError::TooManyDecimals);
+    }
+
+    let scale = |digits: u32| 10_u64.checked_pow(digits).ok_or(ParseCoinsError::Overflow);
+    let units: Value = units.parse().map_err(|_| ParseCoinsError::Overflow)?;
+    let sub_units: Value = match sub_units {
+        "" => 0,
+        digits => digits
+            .parse::<Value>()
+            .map_err(|_| ParseCoinsError::Overflow)?
+            .checked_mul(scale(decimals as u32 - digits.len() as u32)?)
+            .ok_or(ParseCoinsError::Overflow)?,
+    };
+    units
+        .checked_mul(scale(decimals as u32)?)
+        .and_then(|value| value.checked_add(sub_units))
+        .ok_or(ParseCoinsError::Overflow)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::keys::KeyPair;
+    use crate::core::keys::{KeyPair, Verifier};
 
     #[test]
     fn tx_creation() {
@@ -97,10 +293,7 @@ mod tests {
         let tx = new_tx(
             &key_1,
             &utxos,
-            vec![Output {
-                value: 20000,
-                pubkey: key_2.public_key().clone(),
-            }],
+            vec![Output::new(20000, key_2.public_key().clone())],
         );
 
         assert!(tx.is_err());
@@ -108,10 +301,7 @@ mod tests {
         let tx = new_tx(
             &key_1,
             &utxos,
-            vec![Output {
-                value: 7000,
-                pubkey: key_2.public_key().clone(),
-            }],
+            vec![Output::new(7000, key_2.public_key().clone())],
         );
 
         assert!(tx.is_ok());
@@ -126,10 +316,7 @@ mod tests {
         let tx = new_tx(
             &key_1,
             &utxos,
-            vec![Output {
-                value: 10000,
-                pubkey: key_2.public_key().clone(),
-            }],
+            vec![Output::new(10000, key_2.public_key().clone())],
         );
 
         assert!(tx.is_ok());
@@ -139,4 +326,237 @@ mod tests {
         assert_eq!(tx.data.outputs[0].value, 10000);
         assert_eq!(tx.data.outputs[0].pubkey, key_2.public_key());
     }
+
+    #[test]
+    fn tx_creation_with_fee() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+
+        let chain = Chain::new(&key_1.public_key());
+        let utxos = chain.find_utxos_for_key(&key_1.public_key());
+
+        // Zero fee behaves like new_tx
+        let tx = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(7000, key_2.public_key())],
+            0,
+        )
+        .unwrap();
+        assert_eq!(tx.data.outputs.len(), 2);
+        assert_eq!(tx.data.outputs[1].value, 3000);
+        assert_eq!(chain.chain.get_tx_value(&tx).unwrap().fees, 0);
+
+        // Fee is subtracted from the change
+        let tx = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(7000, key_2.public_key())],
+            500,
+        )
+        .unwrap();
+        assert_eq!(tx.data.outputs.len(), 2);
+        assert_eq!(tx.data.outputs[0].value, 7000);
+        assert_eq!(tx.data.outputs[1].value, 2500);
+        assert_eq!(tx.data.outputs[1].pubkey, key_1.public_key());
+        assert_eq!(chain.chain.get_tx_value(&tx).unwrap().fees, 500);
+        assert!(chain.validate_new_tx(&tx));
+
+        // Outputs and fee exactly cover the utxos, no change
+        let tx = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(9000, key_2.public_key())],
+            1000,
+        )
+        .unwrap();
+        assert_eq!(tx.data.outputs.len(), 1);
+        assert_eq!(chain.chain.get_tx_value(&tx).unwrap().fees, 1000);
+
+        // Outputs and fee exceed the utxos
+        let result = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(9000, key_2.public_key())],
+            1001,
+        );
+        assert_eq!(result.unwrap_err(), UtxoError::NotEnoughValue);
+
+        let result = new_tx_with_fee(
+            &key_1,
+            &utxos,
+            vec![Output::new(1, key_2.public_key())],
+            Value::MAX,
+        );
+        assert_eq!(result.unwrap_err(), UtxoError::InvalidValue);
+
+        let result = new_tx_with_fee(&key_1, &utxos, vec![], 0);
+        assert_eq!(result.unwrap_err(), UtxoError::InvalidValue);
+    }
+
+    #[test]
+    fn tx_builder() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let chain = Chain::new(&key_1.public_key());
+
+        let mut builder = TransactionBuilder::new(&key_1);
+        for utxo in chain.find_utxos_for_key(&key_1.public_key()) {
+            builder.add_utxo(utxo);
+        }
+        builder
+            .add_output(key_2.public_key(), 100)
+            .add_output(key_2.public_key(), 50);
+        let tx = builder.build().unwrap();
+
+        assert_eq!(tx.data.inputs.len(), 1);
+        assert_eq!(tx.data.outputs.len(), 3);
+        assert_eq!(tx.data.outputs[0].value, 100);
+        assert_eq!(tx.data.outputs[1].value, 50);
+        assert_eq!(tx.data.outputs[2].pubkey, key_1.public_key());
+        assert_eq!(tx.data.outputs[2].value, 10000 - 150);
+        assert!(chain.validate_new_tx(&tx));
+
+        let mut builder = TransactionBuilder::new(&key_1);
+        for utxo in chain.find_utxos_for_key(&key_1.public_key()) {
+            builder.add_utxo(utxo);
+        }
+        builder.add_full_output(Output {
+            locktime: Some(Height::from(5)),
+            ..Output::new(100, key_2.public_key())
+        });
+        let tx = builder.build().unwrap();
+        assert_eq!(tx.data.outputs[0].locktime, Some(Height::from(5)));
+        assert!(chain.validate_new_tx(&tx));
+
+        let mut builder = TransactionBuilder::new(&key_1);
+        builder.add_output(key_2.public_key(), 100);
+        assert_eq!(builder.build().unwrap_err(), UtxoError::NotEnoughValue);
+    }
+
+    #[test]
+    fn tx_multi_output() {
+        let key = KeyPair::new();
+        let chain = Chain::new(&key.public_key());
+        let utxos = chain.find_utxos_for_key(&key.public_key());
+        let check = |recipients: usize| {
+            let payments: HashMap<PublicKey, Value> = (1..=recipients)
+                .map(|i| (KeyPair::new().public_key(), 100 * i as Value))
+                .collect();
+            let tx = new_tx_multi_output(&key, &utxos, payments.clone()).unwrap();
+            assert_eq!(tx.data.outputs.len(), recipients + 1);
+            let (paid, change) = tx.data.outputs.split_at(recipients);
+            assert!(paid
+                .iter()
+                .all(|output| payments.get(&output.pubkey) == Some(&output.value)));
+            assert!(paid
+                .windows(2)
+                .all(|pair| pair[0].pubkey.as_bytes() < pair[1].pubkey.as_bytes()));
+            let total: Value = payments.values().sum();
+            assert_eq!(change[0].pubkey, key.public_key());
+            assert_eq!(change[0].value, 10000 - total);
+            assert!(chain.validate_new_tx(&tx));
+        };
+        check(2);
+        check(5);
+
+        let payments = HashMap::from([(KeyPair::new().public_key(), 10001)]);
+        assert_eq!(
+            new_tx_multi_output(&key, &utxos, payments).unwrap_err(),
+            UtxoError::NotEnoughValue
+        );
+        assert_eq!(
+            new_tx_multi_output(&key, &utxos, HashMap::new()).unwrap_err(),
+            UtxoError::InvalidValue
+        );
+    }
+
+    #[test]
+    fn tx_batch() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let utxos = vec![
+            Utxo::new(Hash::new(b"tx-1"), 0, 100),
+            Utxo::new(Hash::new(b"tx-2"), 0, 50),
+            Utxo::new(Hash::new(b"tx-3"), 1, 200),
+        ];
+
+        let txs = sign_tx_batch(
+            &key_1,
+            &utxos,
+            vec![
+                vec![Output::new(100, key_2.public_key())],
+                vec![Output::new(220, key_2.public_key())],
+            ],
+        )
+        .unwrap();
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].data.inputs.len(), 1);
+        assert_eq!(txs[0].data.inputs[0].hash, utxos[0].hash);
+        assert_eq!(txs[0].data.outputs.len(), 1);
+        // The second transaction doesn't spend the first utxo again
+        assert_eq!(txs[1].data.inputs.len(), 2);
+        assert_eq!(txs[1].data.inputs[0].hash, utxos[1].hash);
+        assert_eq!(txs[1].data.inputs[1].hash, utxos[2].hash);
+        assert_eq!(txs[1].data.outputs[1].value, 30);
+        assert!(txs
+            .iter()
+            .flat_map(|tx| tx.data.inputs.iter())
+            .all(|input| key_1
+                .public_key()
+                .verify(input.hash.digest(), &input.signature)));
+
+        assert_eq!(sign_tx_batch(&key_1, &utxos, vec![]).unwrap(), vec![]);
+        assert_eq!(
+            sign_tx_batch(
+                &key_1,
+                &utxos,
+                vec![
+                    vec![Output::new(300, key_2.public_key())],
+                    vec![Output::new(100, key_2.public_key())]
+                ]
+            )
+            .unwrap_err(),
+            UtxoError::NotEnoughValue
+        );
+    }
+
+    #[test]
+    fn coins_formatting() {
+        assert_eq!(format_coins(12345, 2), "123.45");
+        assert_eq!(format_coins(0, 0), "0");
+        assert_eq!(format_coins(0, 4), "0.0000");
+        assert_eq!(format_coins(5, 3), "0.005");
+        assert_eq!(format_coins(Value::MAX, 0), "18446744073709551615");
+        assert_eq!(format_coins(Value::MAX, 4), "1844674407370955.1615");
+        assert_eq!(format_coins(1, 25), format!("0.{}1", "0".repeat(24)));
+
+        assert_eq!(parse_coins("123.45", 2), Ok(12345));
+        assert_eq!(parse_coins("123.4", 2), Ok(12340));
+        assert_eq!(parse_coins("123", 2), Ok(12300));
+        assert_eq!(parse_coins("0", 0), Ok(0));
+        assert_eq!(parse_coins("0.0000", 4), Ok(0));
+        assert_eq!(parse_coins("1844674407370955.1615", 4), Ok(Value::MAX));
+        for value in [0, 1, 12345, Value::MAX] {
+            assert_eq!(parse_coins(&format_coins(value, 4), 4), Ok(value));
+        }
+
+        assert_eq!(
+            parse_coins("1.234", 2),
+            Err(ParseCoinsError::TooManyDecimals)
+        );
+        assert_eq!(parse_coins("1.2", 0), Err(ParseCoinsError::TooManyDecimals));
+        assert_eq!(
+            parse_coins("1844674407370955.1616", 4),
+            Err(ParseCoinsError::Overflow)
+        );
+        assert_eq!(parse_coins("1", 20), Err(ParseCoinsError::Overflow));
+        assert_eq!(
+            parse_coins("0.9999999999999999999", 20),
+            Err(ParseCoinsError::Overflow)
+        );
+        for invalid in ["", ".", "1.", ".5", "1.2.3", "-1", "1,5", " 1", "a"] {
+            assert_eq!(parse_coins(invalid, 2), Err(ParseCoinsError::InvalidFormat));
+        }
+    }
 }
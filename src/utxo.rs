@@ -17,8 +17,13 @@ use crate::core::keys::KeyPair;
 use crate::core::transaction::Input;
 use crate::core::transaction::Value;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::fmt;
 
+/// Default maximum number of steps of the branch-and-bound search,
+/// see [collect_bnb](Utxo::collect_bnb)
+pub const BNB_MAX_ITERATIONS: usize = 1000;
+
 /// Errors returned by UTXO-related functions
 #[derive(PartialEq, Debug)]
 pub enum UtxoError {
@@ -29,17 +34,36 @@ pub enum UtxoError {
 
 impl fmt::Display for UtxoError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Utxo error: {:?}", self)
+        write!(
+            f,
+            "Utxo error: {}",
+            match self {
+                UtxoError::InvalidValue => "invalid value",
+                UtxoError::NotEnoughValue => "not enough value",
+                UtxoError::InvalidTransaction => "invalid transaction",
+            }
+        )
     }
 }
 
+impl std::error::Error for UtxoError {}
+
+/// The algorithm that produced a [UTXO selection](UtxoSelection)
+///
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum UtxoSelectionMethod {
+    Exact,
+    Greedy,
+}
+
 /// A helper struct representing a collection of UTXOs covering some
 /// coin value, and the coin change if the total value of the selected
 /// UTXOs exceeds the original value. See [collect](Utxo::collect)
 ///
 pub struct UtxoSelection<'a> {
-    pub list: &'a [Utxo],
+    pub list: Vec<&'a Utxo>,
     pub change: Value,
+    pub method: UtxoSelectionMethod,
 }
 
 /// An unspent transaction output.
@@ -89,16 +113,102 @@ impl Utxo {
         }
 
         Ok(UtxoSelection {
-            list: &utxos[..last + 1],
+            list: utxos[..last + 1].iter().collect(),
             change: acc - value,
+            method: UtxoSelectionMethod::Greedy,
         })
     }
 
+    /// Same as [collect](Utxo::collect), but first searches for a collection of UTXOs
+    /// whose value is exactly the requested one, so that no change is needed. Falls
+    /// back to collect if the search doesn't find one within
+    /// [BNB_MAX_ITERATIONS] steps.
+    ///
+    pub fn collect_bnb(utxos: &[Utxo], value: Value) -> Result<UtxoSelection<'_>, UtxoError> {
+        Self::collect_bnb_with_limit(utxos, value, BNB_MAX_ITERATIONS)
+    }
+
+    /// Same as [collect_bnb](Utxo::collect_bnb) with a custom bound on the steps of the
+    /// search.
+    ///
+    /// The search is a depth-first visit of the UTXOs sorted by decreasing value, where
+    /// every UTXO is either included or excluded. A branch is abandoned when its value
+    /// exceeds the requested one, or when the remaining UTXOs can't cover it anymore.
+    ///
+    pub fn collect_bnb_with_limit(
+        utxos: &[Utxo],
+        value: Value,
+        max_iterations: usize,
+    ) -> Result<UtxoSelection<'_>, UtxoError> {
+        if value == 0 {
+            return Err(UtxoError::InvalidValue);
+        }
+
+        let mut sorted: Vec<&Utxo> = utxos.iter().collect();
+        sorted.sort_by_key(|utxo| Reverse(utxo.value));
+        let mut remaining: Vec<Value> = vec![0; sorted.len() + 1];
+        for (idx, utxo) in sorted.iter().enumerate().rev() {
+            remaining[idx] = remaining[idx + 1].saturating_add(utxo.value);
+        }
+
+        let mut search = BnbSearch {
+            sorted: &sorted,
+            remaining: &remaining,
+            selected: Vec::new(),
+            iterations: 0,
+            max_iterations,
+        };
+        if search.visit(0, value) {
+            return Ok(UtxoSelection {
+                list: search.selected.iter().map(|idx| sorted[*idx]).collect(),
+                change: 0,
+                method: UtxoSelectionMethod::Exact,
+            });
+        }
+        Self::collect(utxos, value)
+    }
+
     // pub fn sign(&self, key: &KeyPair) -> Signature {
     //     key.sign(self.hash.digest())
     // }
 }
 
+/// State of the branch-and-bound search of [collect_bnb](Utxo::collect_bnb).
+/// Remaining holds, for every index, the total value of the UTXOs from that index on.
+///
+struct BnbSearch<'a, 'b> {
+    sorted: &'b [&'a Utxo],
+    remaining: &'b [Value],
+    selected: Vec<usize>,
+    iterations: usize,
+    max_iterations: usize,
+}
+
+impl BnbSearch<'_, '_> {
+    fn visit(&mut self, idx: usize, value: Value) -> bool {
+        if value == 0 {
+            return true;
+        }
+        if idx == self.sorted.len()
+            || self.iterations >= self.max_iterations
+            || self.remaining[idx] < value
+        {
+            return false;
+        }
+        self.iterations += 1;
+
+        let utxo_value = self.sorted[idx].value;
+        if utxo_value <= value {
+            self.selected.push(idx);
+            if self.visit(idx + 1, value - utxo_value) {
+                return true;
+            }
+            self.selected.pop();
+        }
+        self.visit(idx + 1, value)
+    }
+}
+
 /// Provides a function to transform some data into a list of
 /// signed transaction inputs that can be used when creating
 /// a new transaction
@@ -107,15 +217,25 @@ pub trait IntoInputs {
     fn into_inputs(&self, key: &KeyPair) -> Vec<Input>;
 }
 
+fn into_input(utxo: &Utxo, key: &KeyPair) -> Input {
+    Input {
+        hash: utxo.hash.clone(),
+        index: utxo.output,
+        signature: key.sign(utxo.hash.digest()),
+        sequence: Input::SEQUENCE_FINAL,
+        reveal: None,
+    }
+}
+
 impl IntoInputs for &[Utxo] {
     fn into_inputs(&self, key: &KeyPair) -> Vec<Input> {
-        self.into_iter()
-            .map(|utxo| Input {
-                hash: utxo.hash.clone(),
-                index: utxo.output,
-                signature: key.sign(utxo.hash.digest()),
-            })
-            .collect()
+        self.iter().map(|utxo| into_input(utxo, key)).collect()
+    }
+}
+
+impl IntoInputs for &[&Utxo] {
+    fn into_inputs(&self, key: &KeyPair) -> Vec<Input> {
+        self.iter().map(|utxo| into_input(utxo, key)).collect()
     }
 }
 
@@ -144,7 +264,7 @@ mod tests {
         }
 
         match Utxo::collect(&utxos, 100) {
-            Ok(UtxoSelection { list, change }) => {
+            Ok(UtxoSelection { list, change, .. }) => {
                 assert_eq!(list.len(), 1);
                 assert_eq!(change, 0);
             }
@@ -152,7 +272,7 @@ mod tests {
         }
 
         match Utxo::collect(&utxos, 101) {
-            Ok(UtxoSelection { list, change }) => {
+            Ok(UtxoSelection { list, change, .. }) => {
                 assert_eq!(list.len(), 2);
                 assert_eq!(change, 49);
             }
@@ -162,7 +282,7 @@ mod tests {
         utxos.sort_by(|a, b| a.value.cmp(&b.value));
 
         match Utxo::collect(&utxos, 51) {
-            Ok(UtxoSelection { list, change }) => {
+            Ok(UtxoSelection { list, change, .. }) => {
                 assert_eq!(list.len(), 2);
                 assert_eq!(change, 0);
             }
@@ -170,6 +290,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn collect_bnb() {
+        let utxos = vec![
+            Utxo::new(Hash::new(b"test"), 0, 50),
+            Utxo::new(Hash::new(b"test"), 1, 30),
+            Utxo::new(Hash::new(b"test"), 2, 20),
+            Utxo::new(Hash::new(b"test"), 3, 7),
+        ];
+
+        let selection = Utxo::collect_bnb(&utxos, 57).unwrap();
+        assert_eq!(selection.method, UtxoSelectionMethod::Exact);
+        assert_eq!(selection.change, 0);
+        assert_eq!(selection.list, vec![&utxos[0], &utxos[3]]);
+
+        let selection = Utxo::collect_bnb(&utxos, 27).unwrap();
+        assert_eq!(selection.method, UtxoSelectionMethod::Exact);
+        assert_eq!(selection.list, vec![&utxos[2], &utxos[3]]);
+
+        // No exact match, the greedy selection is used
+        let selection = Utxo::collect_bnb(&utxos, 60).unwrap();
+        assert_eq!(selection.method, UtxoSelectionMethod::Greedy);
+        assert_eq!(selection.change, 20);
+        assert_eq!(selection.list, vec![&utxos[0], &utxos[1]]);
+
+        // The search is stopped before finding the exact match
+        let selection = Utxo::collect_bnb_with_limit(&utxos, 27, 1).unwrap();
+        assert_eq!(selection.method, UtxoSelectionMethod::Greedy);
+
+        assert_eq!(
+            Utxo::collect_bnb(&utxos, 0).err(),
+            Some(UtxoError::InvalidValue)
+        );
+        assert_eq!(
+            Utxo::collect_bnb(&utxos, 200).err(),
+            Some(UtxoError::NotEnoughValue)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        use std::error::Error;
+
+        let err: Box<dyn Error> = Box::new(UtxoError::NotEnoughValue);
+        assert_eq!(err.to_string(), "Utxo error: not enough value");
+        assert!(err.source().is_none());
+    }
+
     #[test]
     fn into_inputs() {
         let key = KeyPair::new();
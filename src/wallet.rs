@@ -0,0 +1,188 @@
+//! Wallets tracking the coins of a public key
+//!
+//! A wallet holds a key pair and can send its coins, while a watch-only wallet only
+//! knows the public key, so it can monitor the balance of an address without access
+//! to the private key, e.g. for keys kept in cold storage.
+//!
+
+use crate::chain::Chain;
+use crate::core::blockchain::Height;
+use crate::core::keys::{KeyPair, PubkeyDeserializeError, PublicKey};
+use crate::core::transaction::{Output, Transaction, Value};
+use crate::utils::TransactionBuilder;
+use crate::utxo::{Utxo, UtxoError};
+
+/// A wallet that can query and spend the coins of a key pair
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wallet {
+    key: KeyPair,
+    pubkey: PublicKey,
+}
+
+impl Wallet {
+    pub fn new(key: KeyPair) -> Wallet {
+        let pubkey = key.public_key();
+        Wallet { key, pubkey }
+    }
+
+    pub fn key(&self) -> &KeyPair {
+        &self.key
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.pubkey
+    }
+
+    pub fn balance(&self, chain: &Chain) -> Option<Value> {
+        chain.get_balance(&self.pubkey)
+    }
+
+    pub fn utxos(&self, chain: &Chain) -> Vec<Utxo> {
+        chain.find_utxos_for_key(&self.pubkey)
+    }
+
+    /// Builds a transaction paying the outputs with the UTXOs of the wallet in the
+    /// chain, see [send_from](Wallet::send_from)
+    ///
+    pub fn send(&self, chain: &Chain, outputs: Vec<Output>) -> Result<Transaction, UtxoError> {
+        self.send_from(self.utxos(chain), outputs, None)
+    }
+
+    /// Builds a transaction paying the outputs with the given UTXOs, e.g. fetched from
+    /// a node, optionally expiring at the given height. The inputs are selected with
+    /// [collect](Utxo::collect) and signed, and the remaining value is sent back to the
+    /// wallet as change, see [TransactionBuilder].
+    ///
+    pub fn send_from(
+        &self,
+        utxos: Vec<Utxo>,
+        outputs: Vec<Output>,
+        expires_at: Option<Height>,
+    ) -> Result<Transaction, UtxoError> {
+        let mut builder = TransactionBuilder::new(&self.key);
+        for utxo in utxos {
+            builder.add_utxo(utxo);
+        }
+        for output in outputs {
+            builder.add_full_output(output);
+        }
+        if let Some(height) = expires_at {
+            builder.expires_at(height);
+        }
+        builder.build()
+    }
+}
+
+/// A wallet that can query the coins of a public key, but not spend them
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchOnlyWallet {
+    pub pubkey: PublicKey,
+}
+
+impl WatchOnlyWallet {
+    pub fn new(pubkey: PublicKey) -> WatchOnlyWallet {
+        WatchOnlyWallet { pubkey }
+    }
+
+    /// Builds a wallet from a [Base58Check address](PublicKey::to_address)
+    ///
+    pub fn from_address(addr: &str) -> Result<WatchOnlyWallet, PubkeyDeserializeError> {
+        Ok(WatchOnlyWallet::new(PublicKey::from_address(addr)?))
+    }
+
+    pub fn balance(&self, chain: &Chain) -> Option<Value> {
+        chain.get_balance(&self.pubkey)
+    }
+
+    pub fn utxos(&self, chain: &Chain) -> Vec<Utxo> {
+        chain.find_utxos_for_key(&self.pubkey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::keys::KeyPair;
+    use crate::core::transaction::Output;
+    use crate::utils::{new_block, new_tx};
+
+    #[test]
+    fn wallet() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let wallet = Wallet::new(key_1.clone());
+        assert_eq!(*wallet.public_key(), key_1.public_key());
+        assert_eq!(wallet.balance(&chain).unwrap(), chain.rules.base_coins);
+        assert_eq!(wallet.utxos(&chain).len(), 1);
+
+        let tx = wallet
+            .send(&chain, vec![Output::new(300, key_2.public_key())])
+            .unwrap();
+        assert!(chain.validate_new_tx(&tx));
+        // The change goes back to the wallet
+        assert_eq!(tx.data.outputs.len(), 2);
+        assert_eq!(tx.data.outputs[1].pubkey, key_1.public_key());
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+
+        assert_eq!(
+            wallet.balance(&chain).unwrap(),
+            chain.rules.base_coins - 300
+        );
+        assert_eq!(
+            wallet.send(
+                &chain,
+                vec![Output::new(chain.rules.base_coins, key_2.public_key())]
+            ),
+            Err(UtxoError::NotEnoughValue)
+        );
+        assert_eq!(
+            wallet.send_from(vec![], vec![Output::new(1, key_2.public_key())], None),
+            Err(UtxoError::NotEnoughValue)
+        );
+
+        let utxos = wallet.utxos(&chain);
+        let tx = wallet
+            .send_from(
+                utxos,
+                vec![Output::new(100, key_2.public_key())],
+                Some(Height::from(5)),
+            )
+            .unwrap();
+        assert_eq!(tx.data.expires_at, Some(Height::from(5)));
+        assert!(chain.validate_new_tx(&tx));
+    }
+
+    #[test]
+    fn watch_only() {
+        let key_1 = KeyPair::new();
+        let key_2 = KeyPair::new();
+        let mut chain = Chain::new(&key_1.public_key());
+        let wallet_1 = WatchOnlyWallet::from_address(&key_1.public_key().to_address()).unwrap();
+        let wallet_2 = WatchOnlyWallet::new(key_2.public_key());
+        assert_eq!(wallet_1.pubkey, key_1.public_key());
+        assert_eq!(wallet_1.balance(&chain).unwrap(), chain.rules.base_coins);
+        assert_eq!(wallet_2.balance(&chain).unwrap(), 0);
+        assert!(wallet_2.utxos(&chain).is_empty());
+
+        let tx = new_tx(
+            &key_1,
+            &wallet_1.utxos(&chain),
+            vec![Output::new(300, key_2.public_key())],
+        )
+        .unwrap();
+        assert!(chain.add_block(new_block(&chain, 0, vec![tx])).is_ok());
+
+        assert_eq!(
+            wallet_1.balance(&chain).unwrap(),
+            chain.rules.base_coins - 300
+        );
+        assert_eq!(wallet_2.balance(&chain).unwrap(), 300);
+        assert_eq!(wallet_2.utxos(&chain).len(), 1);
+        assert_eq!(wallet_2.utxos(&chain)[0].value, 300);
+
+        assert!(WatchOnlyWallet::from_address(&key_2.public_key().to_hex_str()).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! Round trips consensus rules through files, in both binary and JSON formats.
+//!
+
+use coin::consensus::{ConsensusRules, ConsensusRulesBuilder, Halving, Target};
+use coin::core::blockchain::Height;
+use coin::core::hash::Hash;
+use coin::traits::io::{FileIO, JsonIO};
+use tempfile::tempdir;
+
+fn rules() -> ConsensusRules {
+    let mut rules = ConsensusRulesBuilder::default()
+        .target(Target::from_leading_zeros(128))
+        .coins_per_block(10000)
+        .halving(Halving::Height(200000))
+        .dust_limit(10)
+        .build();
+    rules
+        .add_checkpoint(Height::from(10), Hash::new(b"10"))
+        .unwrap();
+    rules
+}
+
+#[test]
+fn rules_file_round_trip() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("rules.bin");
+    let rules = rules();
+
+    assert!(rules.to_file(&path).is_ok());
+    let loaded = ConsensusRules::from_file(&path).unwrap();
+    assert_eq!(loaded, rules);
+    assert_eq!(loaded.target, Target::from_leading_zeros(128));
+}
+
+#[test]
+fn rules_json_file_round_trip() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("rules.json");
+    let rules = rules();
+
+    assert!(rules.to_json_file(&path).is_ok());
+    assert_eq!(ConsensusRules::from_json_file(&path).unwrap(), rules);
+
+    assert!(ConsensusRules::from_json_file(&dir.path().join("missing.json")).is_err());
+    assert!(ConsensusRules::from_file(&path).is_err());
+}
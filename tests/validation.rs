@@ -0,0 +1,44 @@
+//! Validates transactions and blocks through the public chain API, against
+//! UTXO pools other than the one kept by the chain.
+//!
+
+use coin::chain::{Chain, UtxoPool};
+use coin::core::keys::KeyPair;
+use coin::core::transaction::Output;
+use coin::utils::{new_block, new_coinbase_tx, new_tx};
+
+#[test]
+fn validate_tx_with_external_pool() {
+    let key_1 = KeyPair::new();
+    let key_2 = KeyPair::new();
+    let chain = Chain::new(&key_1.public_key());
+
+    let utxos = chain.find_utxos_for_key(&key_1.public_key());
+    let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
+
+    let pool = UtxoPool::new(&chain.chain);
+    assert!(chain.validate_tx(&tx, &pool));
+    assert!(!chain.validate_tx(&tx, &UtxoPool::default()));
+}
+
+#[test]
+fn validate_block_with_external_pool() {
+    let key_1 = KeyPair::new();
+    let key_2 = KeyPair::new();
+    let chain = Chain::new(&key_1.public_key());
+    let genesis = chain.get_block(0).unwrap();
+
+    let utxos = chain.find_utxos_for_key(&key_1.public_key());
+    let tx = new_tx(&key_1, &utxos, vec![Output::new(5000, key_2.public_key())]).unwrap();
+    let coinbase = new_coinbase_tx(&key_1.public_key(), chain.rules.base_coins, 0);
+    let block = new_block(&chain, 0, vec![tx.clone(), coinbase.clone()]);
+
+    assert!(chain.validate_double_spend(block.transactions()));
+    assert!(!chain.validate_double_spend(&[tx.clone(), tx.clone()]));
+    assert!(chain.validate_coinbase_tx(block.prev_hash(), block.transactions(), &coinbase));
+
+    let pool = UtxoPool::new(&chain.chain);
+    assert!(chain.validate_block(&block, genesis, &pool));
+    assert!(!chain.validate_block(&block, genesis, &UtxoPool::default()));
+    assert!(!chain.validate_block(&block, &block, &pool));
+}